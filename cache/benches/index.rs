@@ -0,0 +1,164 @@
+//! Compares the B-tree index of `Cache` with the sorted `Vec` it replaced.
+//!
+//! Run with `cargo bench -p cache`.
+
+use std::collections::HashMap;
+
+use cache::cache::{Cache, ListProps, StartAfter};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+
+const SIZES: [usize; 3] = [1_000, 10_000, 100_000];
+
+/// The previous implementation: a `HashMap` plus a `Vec` kept sorted with a linear scan.
+struct VecIndex {
+    map: HashMap<String, usize>,
+    list: Vec<String>,
+}
+
+impl VecIndex {
+    fn new() -> Self {
+        Self {
+            map: HashMap::new(),
+            list: Vec::new(),
+        }
+    }
+
+    fn insert(&mut self, key: String, value: usize) {
+        let position = self
+            .list
+            .iter()
+            .position(|k| k > &key)
+            .unwrap_or(self.list.len());
+        self.list.insert(position, key.clone());
+        self.map.insert(key, value);
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(position) = self.list.iter().position(|k| k == key) {
+            self.list.remove(position);
+            self.map.remove(key);
+        }
+    }
+
+    fn list(&self, start_after: &str, limit: usize) -> Vec<(&String, &usize)> {
+        let position = match self.list.iter().position(|k| k == start_after) {
+            Some(position) => position + 1,
+            None => return Vec::new(),
+        };
+
+        self.list
+            .iter()
+            .skip(position)
+            .take(limit)
+            .map(|k| (k, self.map.get(k).unwrap()))
+            .collect()
+    }
+}
+
+fn keys(size: usize) -> Vec<String> {
+    // spread the keys so inserts do not always land at the end
+    (0..size)
+        .map(|i| format!("key{:08}", (i * 7919) % size))
+        .collect()
+}
+
+fn filled_cache(keys: &[String]) -> Cache<String, usize> {
+    let mut cache = Cache::new(keys.len());
+    for (i, key) in keys.iter().enumerate() {
+        cache.insert(key.clone(), i).unwrap();
+    }
+    cache
+}
+
+fn filled_vec(keys: &[String]) -> VecIndex {
+    let mut index = VecIndex::new();
+    for (i, key) in keys.iter().enumerate() {
+        index.insert(key.clone(), i);
+    }
+    index
+}
+
+/// Same contents as `filled_vec`, built without the quadratic inserts.
+fn sorted_vec(keys: &[String]) -> VecIndex {
+    let mut index = VecIndex::new();
+    for (i, key) in keys.iter().enumerate() {
+        index.map.insert(key.clone(), i);
+    }
+    index.list = keys.to_vec();
+    index.list.sort();
+    index
+}
+
+fn bench_insert(c: &mut Criterion) {
+    let mut group = c.benchmark_group("insert");
+    group.sample_size(10);
+
+    // filling the Vec baseline with 100k keys one insert at a time takes minutes
+    for size in &SIZES[..2] {
+        let size = *size;
+        let keys = keys(size);
+
+        group.bench_with_input(BenchmarkId::new("btree", size), &keys, |b, keys| {
+            b.iter(|| black_box(filled_cache(keys)))
+        });
+        group.bench_with_input(BenchmarkId::new("vec", size), &keys, |b, keys| {
+            b.iter(|| black_box(filled_vec(keys)))
+        });
+    }
+
+    group.finish();
+}
+
+fn bench_remove(c: &mut Criterion) {
+    let mut group = c.benchmark_group("remove_insert");
+
+    for size in SIZES {
+        let keys = keys(size);
+        let key = keys[size / 2].clone();
+
+        let mut cache = filled_cache(&keys);
+        group.bench_with_input(BenchmarkId::new("btree", size), &key, |b, key| {
+            b.iter(|| {
+                cache.remove(key.as_str()).unwrap();
+                cache.insert(key.clone(), 0).unwrap();
+            })
+        });
+
+        let mut index = sorted_vec(&keys);
+        group.bench_with_input(BenchmarkId::new("vec", size), &key, |b, key| {
+            b.iter(|| {
+                index.remove(key);
+                index.insert(key.clone(), 0);
+            })
+        });
+    }
+
+    group.finish();
+}
+
+fn bench_list(c: &mut Criterion) {
+    let mut group = c.benchmark_group("list_start_after");
+
+    for size in SIZES {
+        let keys = keys(size);
+        let key = format!("key{:08}", size / 2);
+
+        let cache = filled_cache(&keys);
+        group.bench_with_input(BenchmarkId::new("btree", size), &key, |b, key| {
+            b.iter(|| {
+                let props: ListProps<String> = StartAfter::Key(key.clone()).into();
+                black_box(cache.list(props).unwrap().len())
+            })
+        });
+
+        let index = sorted_vec(&keys);
+        group.bench_with_input(BenchmarkId::new("vec", size), &key, |b, key| {
+            b.iter(|| black_box(index.list(key, 10).len()))
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_insert, bench_remove, bench_list);
+criterion_main!(benches);
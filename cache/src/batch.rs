@@ -0,0 +1,179 @@
+//! Groups of writes applied to a `Cache` all-or-nothing.
+//!
+//! `Cache::apply` first checks every operation of a `Batch` against the cache as changed by
+//! the operations before it, and only writes once they all pass. A failed check returns
+//! `Error::Batch` with the position of the operation and leaves the cache untouched.
+
+use crate::cache::CacheKey;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Op<K, V> {
+    Insert(K, V),
+    /// Fails with `Error::SortKeyExists` when the key is present.
+    InsertIfNotExists(K, V),
+    /// Fails with `Error::KeyNotFound` when the key is missing.
+    Remove(K),
+}
+
+impl<K, V> Op<K, V> {
+    pub fn key(&self) -> &K {
+        match self {
+            Op::Insert(key, _) | Op::InsertIfNotExists(key, _) | Op::Remove(key) => key,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Batch<K, V> {
+    ops: Vec<Op<K, V>>,
+}
+
+impl<K, V> Default for Batch<K, V> {
+    fn default() -> Self {
+        Self { ops: Vec::new() }
+    }
+}
+
+impl<K, V> Batch<K, V>
+where
+    K: CacheKey,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert<T>(mut self, key: T, value: V) -> Self
+    where
+        T: Into<K>,
+    {
+        self.ops.push(Op::Insert(key.into(), value));
+        self
+    }
+
+    pub fn insert_if_not_exists<T>(mut self, key: T, value: V) -> Self
+    where
+        T: Into<K>,
+    {
+        self.ops.push(Op::InsertIfNotExists(key.into(), value));
+        self
+    }
+
+    pub fn remove<T>(mut self, key: T) -> Self
+    where
+        T: Into<K>,
+    {
+        self.ops.push(Op::Remove(key.into()));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    pub fn ops(&self) -> &[Op<K, V>] {
+        &self.ops
+    }
+}
+
+impl<K, V> IntoIterator for Batch<K, V> {
+    type Item = Op<K, V>;
+    type IntoIter = std::vec::IntoIter<Op<K, V>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.ops.into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::{Cache, Error};
+
+    fn cache() -> Cache<String, i32> {
+        let mut cache = Cache::new(10);
+        cache.insert("a", 1).unwrap();
+        cache.insert("b", 2).unwrap();
+        cache
+    }
+
+    #[test]
+    fn test_apply_batch() {
+        let mut cache = cache();
+        let batch = Batch::new()
+            .insert("a", 10)
+            .remove("b")
+            .insert_if_not_exists("b", 20)
+            .insert_if_not_exists("c", 30);
+
+        cache.apply(batch).unwrap();
+
+        assert_eq!(cache.get("a"), Some(&10));
+        assert_eq!(cache.get("b"), Some(&20));
+        assert_eq!(cache.get("c"), Some(&30));
+    }
+
+    #[test]
+    fn test_apply_batch_is_all_or_nothing() {
+        let mut cache = cache();
+        let batch = Batch::new()
+            .insert("a", 10)
+            .insert_if_not_exists("c", 30)
+            .insert_if_not_exists("c", 31);
+
+        assert!(matches!(
+            cache.apply(batch),
+            Err(Error::Batch(2, error)) if matches!(*error, Error::SortKeyExists)
+        ));
+
+        let batch = Batch::new().remove("a").remove("a");
+        assert!(matches!(
+            cache.apply(batch),
+            Err(Error::Batch(1, error)) if matches!(*error, Error::KeyNotFound)
+        ));
+
+        assert_eq!(cache.get("a"), Some(&1));
+        assert_eq!(cache.get("b"), Some(&2));
+        assert_eq!(cache.get("c"), None);
+        assert_eq!(cache.stats().inserts, 2);
+    }
+
+    #[test]
+    fn test_apply_batch_at_capacity() {
+        let mut cache: Cache<String, i32> = Cache::new(3);
+        cache.insert("a", 1).unwrap();
+        cache.insert("b", 2).unwrap();
+        cache.insert("c", 3).unwrap();
+
+        // `a` is the least recently used, but the batch removes it after inserting `d`
+        let batch = Batch::new().insert("d", 4).remove("a");
+        cache.apply(batch).unwrap();
+
+        assert_eq!(cache.get("a"), None);
+        assert_eq!(cache.get("b"), None);
+        assert_eq!(cache.get("c"), Some(&3));
+        assert_eq!(cache.get("d"), Some(&4));
+        assert_eq!(cache.stats().evictions, 1);
+
+        // every key is pinned by the batch, nothing can make room for `f`
+        let batch = Batch::new()
+            .insert("e", 5)
+            .insert("f", 6)
+            .insert("c", 30)
+            .insert("d", 40);
+        assert!(matches!(
+            cache.apply(batch),
+            Err(Error::Batch(1, error)) if matches!(*error, Error::CapacityExceeded)
+        ));
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get("d"), Some(&4));
+
+        let batch = Batch::new().insert("e", 5).insert("d", 40);
+        cache.apply(batch).unwrap();
+        assert_eq!(cache.len(), 3);
+        assert_eq!(cache.get("c"), Some(&3));
+    }
+}
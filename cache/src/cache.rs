@@ -14,16 +14,16 @@
 //! ## Example
 //!
 //! ```
-//! use rust_cache::{Cache, ListProps, Order, Filter, StartAfter};
+//! use cache::cache::{Cache, ListProps, Order, Filter, StartAfter};
 //!
 //! fn main() {
 //!     // Create a new cache with a capacity of 100
-//!     let mut cache: Cache<i32> = Cache::new(100);
+//!     let mut cache: Cache<String, i32> = Cache::new(100);
 //!
 //!     // Insert key-value pairs into the cache
-//!     cache.insert("key1", 1);
-//!     cache.insert("key2", 2);
-//!     cache.insert("key3", 3);
+//!     cache.insert("key1", 1).unwrap();
+//!     cache.insert("key2", 2).unwrap();
+//!     cache.insert("key3", 3).unwrap();
 //!
 //!     // Get a value from the cache
 //!     if let Some(value) = cache.get("key1") {
@@ -34,11 +34,10 @@
 //!     cache.remove("key2");
 //!
 //!     // List all key-value pairs in the cache
-//!     let list_props = ListProps::new()
+//!     let list_props = ListProps::default()
 //!         .start_after_key("key1")
 //!         .filter(Filter::None)
-//!         .order(Order::Asc)
-//!         .limit(10);
+//!         .order(Order::Asc);
 //!
 //!     if let Ok(list) = cache.list(list_props) {
 //!         for (key, value) in list {
@@ -50,29 +49,51 @@
 //!
 //! ## Structs
 //!
-//! ### `Cache<V>`
+//! ### `Cache<K, V>`
 //!
 //! A cache struct that stores key-value pairs.
 //!
 //! #### Type Parameters
 //!
+//! - `K`: The type of the keys stored in the cache. Keys are owned and ordered, see `CacheKey`.
 //! - `V`: The type of the values stored in the cache.
 //!
 //! #### Methods
 //!
-//! - `new(capacity: usize) -> Cache<V>`: Creates a new cache with the specified capacity.
-//! - `insert(&mut self, key: &'static str, value: V)`: Inserts a key-value pair into the cache. If the key already exists, the value is updated.
-//! - `insert_if_not_exists(&mut self, key: &'static str, value: V) -> Result<(), Error>`: Inserts a key-value pair into the cache only if the key does not already exist.
-//! - `get(&self, key: &str) -> Option<&V>`: Returns a reference to the value associated with the given key, or `None` if the key is not found in the cache.
-//! - `get_mut(&mut self, key: &str) -> Option<&mut V>`: Returns a mutable reference to the value associated with the given key, or `None` if the key is not found in the cache.
+//! - `new(capacity: usize) -> Cache<K, V>`: Creates a new cache with the specified capacity, evicting the least recently used key.
+//! - `with_policy<P: EvictionPolicy<K>>(capacity: usize, policy: P) -> Cache<K, V>`: Creates a new cache that evicts keys with the given policy (see the `eviction` module).
+//! - `empty_policy(&self) -> Box<dyn EvictionPolicy<K>>`: Returns a copy of the eviction policy that tracks no key, to build a cache evicting the same way.
+//! - `insert<T: Into<K>>(&mut self, key: T, value: V) -> Result<(), Error>`: Inserts a key-value pair into the cache. If the key already exists, the value is updated. A new key fails with `Error::CapacityExceeded` when the cache is full and nothing can be evicted.
+//! - `insert_if_not_exists<T: Into<K>>(&mut self, key: T, value: V) -> Result<(), Error>`: Inserts a key-value pair into the cache only if the key does not already exist.
+//! - `get<Q>(&self, key: &Q) -> Option<&V>`: Returns a reference to the value associated with the given key, or `None` if the key is not found in the cache. Counts as an access for the eviction policy.
+//! - `get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>`: Returns a mutable reference to the value associated with the given key, or `None` if the key is not found in the cache.
+//! - `insert_with_ttl<T: Into<K>>(&mut self, key: T, value: V, ttl: Duration) -> Result<(), Error>`: Inserts a key-value pair that expires after `ttl`.
+//! - `ttl(&self) -> Option<Duration>` / `set_ttl(&mut self, ttl: Option<Duration>)`: The default TTL applied by `insert`.
+//! - `purge_expired(&mut self) -> Vec<(K, V)>`: Removes and returns every expired entry. Expired entries are already hidden from `get` and `list` before they are purged.
+//! - `weight(&self) -> usize`: Returns the total weight of the stored entries, as measured by the cache's `Weigher` (one per entry by default).
+//! - `set_max_weight(&mut self, max_weight: Option<usize>)` / `set_weigher<W: Weigher<K, V>>(&mut self, weigher: W)`: Bounds the cache by total weight, e.g. bytes, on top of the entry capacity.
+//! - `add_listener<F: Fn(Change, &K)>(&mut self, listener: F)`: Calls `listener` on every insert, update, removal, eviction and expiration (see the `listener` module).
+//! - `stats(&self) -> Stats`: Returns the hit, miss, insert, update, eviction and expiration counters of the cache.
 //! - `capacity(&self) -> usize`: Returns the capacity of the cache.
 //! - `set_capacity(&mut self, capacity: usize)`: Sets the capacity of the cache.
-//! - `remove(&mut self, key: &str) -> Result<(), Error>`: Removes the key-value pair with the given key from the cache.
+//! - `version_of<Q>(&self, key: &Q) -> Option<u64>`: Returns the version of an entry. Every insert gives the entry a new, higher version.
+//! - `compare_and_swap<T: Into<K>>(&mut self, key: T, expected_version: u64, value: V) -> Result<u64, Error>`: Replaces a value only if its version is still `expected_version`, or fails with `Error::VersionMismatch`.
+//! - `remove<Q>(&mut self, key: &Q) -> Result<(), Error>`: Removes the key-value pair with the given key from the cache.
+//! - `apply(&mut self, batch: Batch<K, V>) -> Result<(), Error>`: Applies the inserts and removals of a `Batch` all-or-nothing (see the `batch` module).
 //! - `clear(&mut self)`: Removes all key-value pairs from the cache.
 //! - `len(&self) -> usize`: Returns the number of key-value pairs in the cache.
 //! - `is_empty(&self) -> bool`: Returns `true` if the cache is empty, `false` otherwise.
-//! - `contains_key(&self, key: &str) -> bool`: Returns `true` if the cache contains the given key, `false` otherwise.
-//! - `list<T>(&self, props: T) -> Result<Vec<(&str, &V)>, Error>`: Returns a list of key-value pairs in the cache based on the provided list properties.
+//! - `contains_key<Q>(&self, key: &Q) -> bool`: Returns `true` if the cache contains the given key, `false` otherwise.
+//! - `iter(&self) -> Iter<'_, K, V>`: Returns a lazy, double-ended iterator over the live entries in key order.
+//! - `range<Q, R: RangeBounds<Q>>(&self, range: R) -> Iter<'_, K, V>`: Same as `iter`, restricted to the keys within `range`. `Iter::order` and `Iter::filter_keys` reverse it and filter its keys like `list` does.
+//! - `list<T>(&self, props: T) -> Result<Page<(&K, &V), K>, Error>`: Returns a page of key-value pairs in the cache based on the provided list properties, with a `Cursor` to the next page when the limit cut the list. Props with a clause fail with `Error::ClauseUnsupported`.
+//! - `list_where<T>(&self, props: T) -> Result<Page<(&K, &V), K>, Error>`: Same as `list`, keeping the entries whose value matches the clause of the props. Values must implement `Document`.
+//!
+//! ### Traits
+//!
+//! #### `CacheKey`
+//!
+//! Implemented by key types that can be stored in a `Cache`. Provided for `String` and `Vec<u8>`.
 //!
 //! ### Enums
 //!
@@ -98,22 +119,58 @@
 //!
 //! #### `ListProps`
 //!
-//! A struct that holds the properties for listing key-value pairs in the cache.
+//! A struct that holds the properties for listing key-value pairs in the cache: a `from`/`to`
+//! key range with inclusive or exclusive bounds, a key filter, a `Clause` on the values, which
+//! `Cache::list_where` evaluates, the order, a limit and the `Cursor` to resume from.
+//!
+//! #### `Page`
+//!
+//! The items of one `list` call and the `Cursor` to the next page, if any.
 //!
 //! ## License
 //!
 //! This library is licensed under the MIT License.
 
-use std::collections::HashMap;
+use std::borrow::Borrow;
+use std::collections::{btree_set, BTreeSet, HashMap};
 use std::fmt::Debug;
 use std::fmt::Display;
+use std::hash::Hash;
+use std::iter::FusedIterator;
+use std::ops::{Bound, RangeBounds};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use valu3::prelude::*;
+
+use crate::batch::{Batch, Op};
+use crate::condition::{self, Clause, Document};
+use crate::eviction::{EvictionPolicy, Lru};
+use crate::listener::{Change, Listeners};
+use crate::stats::{Counters, Stats};
+use crate::weigher::{UnitWeigher, Weigher};
 
 pub enum Error {
     SortKeyNotFound,
     CacheAlreadyExists,
     SortKeyExists,
     TableAlreadyExists,
+    TableNotFound,
     KeyNotFound,
+    InvalidCursor,
+    IndexAlreadyExists,
+    IndexNotFound,
+    /// The entry changed since the version given to `compare_and_swap`.
+    VersionMismatch,
+    /// The cache is full and its eviction policy has no key to evict.
+    CapacityExceeded,
+    /// The table writes through its loader, which only the async writes of the service reach.
+    TableWritesThrough,
+    /// `list` was given a clause, which only `list_where` evaluates.
+    ClauseUnsupported,
+    Clause(condition::Error),
+    /// The operation at this position of a `Batch` failed, nothing was applied.
+    Batch(usize, Box<Error>),
 }
 
 impl Display for Error {
@@ -123,7 +180,17 @@ impl Display for Error {
             Error::CacheAlreadyExists => write!(f, "Cache already exists"),
             Error::SortKeyExists => write!(f, "Sort key exists"),
             Error::TableAlreadyExists => write!(f, "Table already exists"),
+            Error::TableNotFound => write!(f, "Table not found"),
             Error::KeyNotFound => write!(f, "Key not found"),
+            Error::InvalidCursor => write!(f, "Invalid cursor"),
+            Error::IndexAlreadyExists => write!(f, "Index already exists"),
+            Error::IndexNotFound => write!(f, "Index not found"),
+            Error::VersionMismatch => write!(f, "Version mismatch"),
+            Error::CapacityExceeded => write!(f, "Capacity exceeded"),
+            Error::TableWritesThrough => write!(f, "Table writes through its loader"),
+            Error::ClauseUnsupported => write!(f, "Clause is only evaluated by list_where"),
+            Error::Clause(err) => write!(f, "Clause error: {}", err),
+            Error::Batch(index, err) => write!(f, "Batch operation {} failed: {}", index, err),
         }
     }
 }
@@ -134,15 +201,44 @@ impl Debug for Error {
     }
 }
 
-#[derive(Debug)]
-pub enum Filter {
-    StartWith(&'static str),
-    EndWith(&'static str),
-    StartAndEndWith(&'static str, &'static str),
+/// An owned, ordered key that can be stored in a `Cache`.
+///
+/// `starts_with` and `ends_with` back the prefix/suffix `Filter`s used by `Cache::list`.
+/// Composite keys implement them in whatever way makes sense for their ordering.
+pub trait CacheKey: Clone + Ord + Hash + Debug + Send + Sync + 'static {
+    fn starts_with(&self, prefix: &Self) -> bool;
+    fn ends_with(&self, suffix: &Self) -> bool;
+}
+
+impl CacheKey for String {
+    fn starts_with(&self, prefix: &Self) -> bool {
+        self.as_str().starts_with(prefix.as_str())
+    }
+
+    fn ends_with(&self, suffix: &Self) -> bool {
+        self.as_str().ends_with(suffix.as_str())
+    }
+}
+
+impl CacheKey for Vec<u8> {
+    fn starts_with(&self, prefix: &Self) -> bool {
+        self.as_slice().starts_with(prefix)
+    }
+
+    fn ends_with(&self, suffix: &Self) -> bool {
+        self.as_slice().ends_with(suffix)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Filter<K> {
+    StartWith(K),
+    EndWith(K),
+    StartAndEndWith(K, K),
     None,
 }
 
-impl Default for Filter {
+impl<K> Default for Filter<K> {
     fn default() -> Self {
         Self::None
     }
@@ -161,41 +257,147 @@ impl Default for Order {
 }
 
 #[derive(Debug, Clone)]
-pub enum StartAfter {
-    Key(&'static str),
+pub enum StartAfter<K> {
+    Key(K),
     None,
 }
 
-impl Default for StartAfter {
+impl<K> Default for StartAfter<K> {
     fn default() -> Self {
         Self::None
     }
 }
 
-#[derive(Default, Debug)]
-pub struct ListProps {
-    pub start_after_key: StartAfter,
-    pub filter: Filter,
+/// Where a `list` resumes from, returned by `Cache::list` when the page was cut by the limit.
+///
+/// A cursor only remembers the last key of the page: listing again with it continues right
+/// after that key, even if the key was removed in the meantime. `encode` and `decode` turn
+/// it into an opaque string that can be handed out to clients.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cursor<K> {
+    key: K,
+}
+
+impl Cursor<String> {
+    pub fn encode(&self) -> String {
+        encode_hex(self.key.as_bytes())
+    }
+
+    pub fn decode(cursor: &str) -> Result<Self, Error> {
+        let key = String::from_utf8(decode_hex(cursor)?).map_err(|_| Error::InvalidCursor)?;
+        Ok(Self { key })
+    }
+}
+
+impl Cursor<Vec<u8>> {
+    pub fn encode(&self) -> String {
+        encode_hex(&self.key)
+    }
+
+    pub fn decode(cursor: &str) -> Result<Self, Error> {
+        Ok(Self {
+            key: decode_hex(cursor)?,
+        })
+    }
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(value: &str) -> Result<Vec<u8>, Error> {
+    value
+        .as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            std::str::from_utf8(pair)
+                .ok()
+                .filter(|pair| pair.len() == 2)
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or(Error::InvalidCursor)
+        })
+        .collect()
+}
+
+/// One page of a `list`, with the cursor to fetch the next one if there is more.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Page<I, K> {
+    pub items: Vec<I>,
+    pub cursor: Option<Cursor<K>>,
+}
+
+impl<I, K> Page<I, K> {
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn map<F, J>(self, f: F) -> Page<J, K>
+    where
+        F: FnMut(I) -> J,
+    {
+        Page {
+            items: self.items.into_iter().map(f).collect(),
+            cursor: self.cursor,
+        }
+    }
+}
+
+impl<I, K> IntoIterator for Page<I, K> {
+    type Item = I;
+    type IntoIter = std::vec::IntoIter<I>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.items.into_iter()
+    }
+}
+
+/// `from` and `to` bound the listed keys whatever the `order`, so a descending list walks
+/// from `to` down to `from`. A `limit` of 0, the default however the props are built, lists
+/// everything.
+///
+/// `filter` matches keys while `clause` is evaluated against the values by `Cache::list_where`,
+/// an entry is listed when both match and only listed entries count towards the `limit`.
+#[derive(Debug)]
+pub struct ListProps<K> {
+    pub start_after_key: StartAfter<K>,
+    pub filter: Filter<K>,
     pub order: Order,
     pub limit: usize,
+    pub from: Bound<K>,
+    pub to: Bound<K>,
+    pub cursor: Option<Cursor<K>>,
+    pub clause: Option<Clause>,
 }
 
-impl ListProps {
-    fn new() -> Self {
+impl<K> Default for ListProps<K> {
+    fn default() -> Self {
         Self {
             start_after_key: StartAfter::None,
             filter: Filter::None,
             order: Order::Asc,
-            limit: 10,
+            limit: 0,
+            from: Bound::Unbounded,
+            to: Bound::Unbounded,
+            cursor: None,
+            clause: None,
         }
     }
+}
 
-    pub fn start_after_key(mut self, key: &'static str) -> Self {
-        self.start_after_key = StartAfter::Key(key);
+impl<K> ListProps<K> {
+    pub fn start_after_key<T>(mut self, key: T) -> Self
+    where
+        T: Into<K>,
+    {
+        self.start_after_key = StartAfter::Key(key.into());
         self
     }
 
-    pub fn filter(mut self, filter: Filter) -> Self {
+    pub fn filter(mut self, filter: Filter<K>) -> Self {
         self.filter = filter;
         self
     }
@@ -204,375 +406,1211 @@ impl ListProps {
         self.order = order;
         self
     }
+
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Lower bound of the listed keys, `inclusive` tells whether `key` itself is listed.
+    pub fn from<T>(mut self, key: T, inclusive: bool) -> Self
+    where
+        T: Into<K>,
+    {
+        self.from = bound(key.into(), inclusive);
+        self
+    }
+
+    /// Upper bound of the listed keys, `inclusive` tells whether `key` itself is listed.
+    pub fn to<T>(mut self, key: T, inclusive: bool) -> Self
+    where
+        T: Into<K>,
+    {
+        self.to = bound(key.into(), inclusive);
+        self
+    }
+
+    /// Resumes the list after the last key of a previous page.
+    pub fn cursor(mut self, cursor: Cursor<K>) -> Self {
+        self.cursor = Some(cursor);
+        self
+    }
+
+    /// Only lists the entries whose value matches `clause`.
+    pub fn clause(mut self, clause: Clause) -> Self {
+        self.clause = Some(clause);
+        self
+    }
 }
 
-impl From<Filter> for ListProps {
-    fn from(filter: Filter) -> Self {
+fn bound<K>(key: K, inclusive: bool) -> Bound<K> {
+    if inclusive {
+        Bound::Included(key)
+    } else {
+        Bound::Excluded(key)
+    }
+}
+
+/// Picks the bound that lets fewer keys through. `lower` tells which side both bounds are on.
+fn narrowest<'a, K: Ord>(a: Bound<&'a K>, b: Bound<&'a K>, lower: bool) -> Bound<&'a K> {
+    let key = |bound: &Bound<&'a K>| match bound {
+        Bound::Included(key) | Bound::Excluded(key) => Some(*key),
+        Bound::Unbounded => None,
+    };
+
+    match (key(&a), key(&b)) {
+        (None, _) => b,
+        (_, None) => a,
+        (Some(x), Some(y)) if x == y => match a {
+            Bound::Excluded(_) => a,
+            _ => b,
+        },
+        (Some(x), Some(y)) if (x > y) == lower => a,
+        _ => b,
+    }
+}
+
+/// `BTreeSet::range` panics on these, they select no key at all.
+pub(crate) fn is_empty_range<K: Ord + ?Sized>(from: Bound<&K>, to: Bound<&K>) -> bool {
+    match (from, to) {
+        (Bound::Included(from), Bound::Included(to)) => from > to,
+        (Bound::Included(from), Bound::Excluded(to))
+        | (Bound::Excluded(from), Bound::Included(to))
+        | (Bound::Excluded(from), Bound::Excluded(to)) => from >= to,
+        _ => false,
+    }
+}
+
+impl<K> From<Filter<K>> for ListProps<K> {
+    fn from(filter: Filter<K>) -> Self {
         Self {
-            start_after_key: StartAfter::None,
             filter,
-            order: Order::Asc,
-            limit: 10,
+            ..Self::default()
         }
     }
 }
 
-impl From<Order> for ListProps {
+impl<K> From<Order> for ListProps<K> {
     fn from(order: Order) -> Self {
         Self {
-            start_after_key: StartAfter::None,
-            filter: Filter::None,
             order,
-            limit: 10,
+            ..Self::default()
         }
     }
 }
 
-impl From<StartAfter> for ListProps {
-    fn from(start_after_key: StartAfter) -> Self {
+impl<K> From<StartAfter<K>> for ListProps<K> {
+    fn from(start_after_key: StartAfter<K>) -> Self {
         Self {
             start_after_key,
-            filter: Filter::None,
-            order: Order::Asc,
-            limit: 10,
+            ..Self::default()
         }
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
-pub struct Cache<V>
+#[derive(Debug)]
+pub struct Cache<K, V>
 where
+    K: CacheKey,
     V: PartialEq,
 {
-    map: HashMap<&'static str, V>,
-    list: Vec<&'static str>,
+    map: HashMap<K, V>,
+    index: BTreeSet<K>,
     capacity: usize,
-    _phantom: std::marker::PhantomData<V>,
+    policy: Mutex<Box<dyn EvictionPolicy<K>>>,
+    ttl: Option<Duration>,
+    expirations: HashMap<K, Instant>,
+    deadlines: BTreeSet<(Instant, K)>,
+    weigher: Arc<dyn Weigher<K, V>>,
+    weights: HashMap<K, usize>,
+    weight: usize,
+    max_weight: Option<usize>,
+    listeners: Listeners<K>,
+    counters: Counters,
+    versions: HashMap<K, u64>,
+    version: u64,
+    /// Keys `evict` passes over, see `pin`.
+    pinned: BTreeSet<K>,
 }
 
-impl<V> Cache<V>
+impl<K, V> Clone for Cache<K, V>
 where
-    V: PartialEq,
+    K: CacheKey,
+    V: PartialEq + Clone,
 {
-    pub fn new(capacity: usize) -> Self {
+    fn clone(&self) -> Self {
         Self {
-            map: HashMap::new(),
-            list: Vec::new(),
-            capacity,
-            _phantom: std::marker::PhantomData,
+            map: self.map.clone(),
+            index: self.index.clone(),
+            capacity: self.capacity,
+            policy: Mutex::new(self.policy().clone()),
+            ttl: self.ttl,
+            expirations: self.expirations.clone(),
+            deadlines: self.deadlines.clone(),
+            weigher: self.weigher.clone(),
+            weights: self.weights.clone(),
+            weight: self.weight,
+            max_weight: self.max_weight,
+            listeners: Listeners::new(),
+            counters: Counters::from(self.stats()),
+            versions: self.versions.clone(),
+            version: self.version,
+            pinned: BTreeSet::new(),
         }
     }
+}
 
-    pub fn insert(&mut self, key: &'static str, value: V) {
-        if let Some(value) = self.map.get(&key) {
-            if value.eq(value) {
-                return;
-            }
-        }
-
-        if self.map.len() != 0 && self.map.len() == self.capacity {
-            let first_key = self.list.remove(0);
-            self.map.remove(&first_key);
-        }
+impl<K, V> PartialEq for Cache<K, V>
+where
+    K: CacheKey,
+    V: PartialEq,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.capacity == other.capacity && self.map == other.map
+    }
+}
 
-        // sorted insert
-        let position = self
-            .list
+/// A cache is seen by a `Clause` as an object of its live entries, so a table can be listed
+/// by the contents of its partitions. Only the entries the clause reads are converted.
+impl<V> Document for Cache<String, V>
+where
+    V: PartialEq + ToValueBehavior,
+{
+    fn to_document(&self, attributes: &BTreeSet<&str>) -> Value {
+        let entries: HashMap<String, Value> = attributes
             .iter()
-            .position(|k| k > &key)
-            .unwrap_or(self.list.len());
-        self.list.insert(position, key);
-        self.map.insert(key, value.into());
+            .filter_map(|name| Some((name.to_string(), self.peek(*name)?.to_value())))
+            .collect();
+
+        Value::from(entries)
     }
+}
 
-    pub fn insert_if_not_exists(&mut self, key: &'static str, value: V) -> Result<(), Error> {
-        if self.map.contains_key(key) {
-            return Err(Error::SortKeyExists);
-        }
+impl<K, V> Cache<K, V>
+where
+    K: CacheKey,
+    V: PartialEq,
+{
+    pub fn new(capacity: usize) -> Self {
+        Self::with_policy(capacity, Lru::new())
+    }
 
-        self.insert(key, value);
-        Ok(())
+    pub fn with_policy<P>(capacity: usize, policy: P) -> Self
+    where
+        P: EvictionPolicy<K> + 'static,
+    {
+        Self::with_boxed_policy(capacity, Box::new(policy))
     }
 
-    pub fn get(&self, key: &str) -> Option<&V> {
-        self.map.get(key)
+    pub fn with_boxed_policy(capacity: usize, policy: Box<dyn EvictionPolicy<K>>) -> Self {
+        Self {
+            map: HashMap::new(),
+            index: BTreeSet::new(),
+            capacity,
+            policy: Mutex::new(policy),
+            ttl: None,
+            expirations: HashMap::new(),
+            deadlines: BTreeSet::new(),
+            weigher: Arc::new(UnitWeigher),
+            weights: HashMap::new(),
+            weight: 0,
+            max_weight: None,
+            listeners: Listeners::new(),
+            counters: Counters::default(),
+            versions: HashMap::new(),
+            version: 0,
+            pinned: BTreeSet::new(),
+        }
     }
 
-    pub fn get_mut(&mut self, key: &str) -> Option<&mut V> {
-        self.map.get_mut(key)
+    fn policy(&self) -> MutexGuard<'_, Box<dyn EvictionPolicy<K>>> {
+        self.policy.lock().unwrap_or_else(PoisonError::into_inner)
     }
 
-    pub fn capacity(&self) -> usize {
-        self.capacity
+    /// A copy of the eviction policy that tracks no key, to build a cache evicting the same way.
+    pub fn empty_policy(&self) -> Box<dyn EvictionPolicy<K>> {
+        let mut policy = self.policy().clone_box();
+        policy.clear();
+        policy
     }
 
-    pub fn set_capacity(&mut self, capacity: usize) {
-        self.capacity = capacity;
+    /// Calls `listener` after every insert, update, removal, eviction and expiration.
+    pub fn add_listener<F>(&mut self, listener: F)
+    where
+        F: Fn(Change, &K) + Send + Sync + 'static,
+    {
+        self.listeners.push(Arc::new(listener));
     }
 
-    pub fn remove(&mut self, key: &str) -> Result<(), Error> {
-        match self.list.iter().position(|k| k == &key) {
-            Some(position) => {
-                self.list.remove(position);
-                self.map.remove(key);
-                Ok(())
-            }
-            None => Err(Error::KeyNotFound),
-        }
+    pub fn clear_listeners(&mut self) {
+        self.listeners.clear();
     }
 
-    pub fn clear(&mut self) {
-        self.map.clear();
-        self.list.clear();
+    fn changed(&self, change: Change, key: &K) {
+        self.counters.record(change);
+        self.listeners.notify(change, key);
     }
 
-    pub fn len(&self) -> usize {
-        self.map.len()
+    /// Snapshot of the hit, miss, insert, update, eviction and expiration counters.
+    pub fn stats(&self) -> Stats {
+        self.counters.snapshot()
     }
 
-    pub fn is_empty(&self) -> bool {
-        self.map.is_empty()
+    pub fn reset_stats(&mut self) {
+        self.counters = Counters::default();
     }
 
-    pub fn contains_key(&self, key: &str) -> bool {
-        self.map.contains_key(key)
+    /// Inserts a key-value pair that expires after the default TTL of the cache, if any.
+    ///
+    /// A new key is refused with `Error::CapacityExceeded` when the cache is full and the
+    /// eviction policy has no key to evict.
+    pub fn insert<T>(&mut self, key: T, value: V) -> Result<(), Error>
+    where
+        T: Into<K>,
+    {
+        let ttl = self.ttl;
+        self.insert_entry(key.into(), value, ttl)
     }
 
-    pub fn list<T>(&self, props: T) -> Result<Vec<(&str, &V)>, Error>
+    /// Inserts a key-value pair that expires after `ttl`, regardless of the default TTL.
+    pub fn insert_with_ttl<T>(&mut self, key: T, value: V, ttl: Duration) -> Result<(), Error>
     where
-        T: Into<ListProps>,
+        T: Into<K>,
     {
-        let props = props.into();
+        self.insert_entry(key.into(), value, Some(ttl))
+    }
 
-        let position = match props.start_after_key {
-            StartAfter::Key(key) => {
-                self.list
-                    .iter()
-                    .position(|k| k == &key)
-                    .ok_or(Error::SortKeyNotFound)?
-                    + 1
-            }
-            StartAfter::None => 0,
-        };
+    fn insert_entry(&mut self, key: K, value: V, ttl: Option<Duration>) -> Result<(), Error> {
+        if self.is_expired(&key) {
+            self.remove_entry(&key);
+            self.policy().on_remove(&key);
+            self.changed(Change::Expire, &key);
+        }
 
-        let mut list = Vec::new();
-        let mut count = 0;
-
-        match props.order {
-            Order::Asc => {
-                let skip_iter = self.list.iter().skip(position);
-                for k in skip_iter {
-                    let filtered: Option<(&str, &V)> = match props.filter {
-                        Filter::StartWith(key) => {
-                            if k.starts_with(&key) {
-                                Some((k.clone(), self.map.get(k).unwrap()))
-                            } else {
-                                None
-                            }
-                        }
-                        Filter::EndWith(key) => {
-                            if k.ends_with(&key) {
-                                Some((k.clone(), self.map.get(k).unwrap()))
-                            } else {
-                                None
-                            }
-                        }
-                        Filter::StartAndEndWith(start_key, end_key) => {
-                            if k.starts_with(&start_key) && k.ends_with(&end_key) {
-                                Some((k.clone(), self.map.get(k).unwrap()))
-                            } else {
-                                None
-                            }
-                        }
-                        Filter::None => Some((k.clone(), self.map.get(k).unwrap())),
-                    };
-
-                    if let Some(item) = filtered {
-                        list.push(item);
-                        count += 1;
-                        if count == props.limit {
-                            break;
-                        }
-                    }
-                }
-            }
-            Order::Desc => {
-                let skip_iter = self.list.iter().rev().skip(position);
-                for k in skip_iter {
-                    let filtered: Option<(&str, &V)> = match props.filter {
-                        Filter::StartWith(key) => {
-                            if k.starts_with(&key) {
-                                Some((k.clone(), self.map.get(k).unwrap()))
-                            } else {
-                                None
-                            }
-                        }
-                        Filter::EndWith(key) => {
-                            if k.ends_with(&key) {
-                                Some((k.clone(), self.map.get(k).unwrap()))
-                            } else {
-                                None
-                            }
-                        }
-                        Filter::StartAndEndWith(start_key, end_key) => {
-                            if k.starts_with(&start_key) && k.ends_with(&end_key) {
-                                Some((k.clone(), self.map.get(k).unwrap()))
-                            } else {
-                                None
-                            }
-                        }
-                        Filter::None => Some((k.clone(), self.map.get(k).unwrap())),
-                    };
-
-                    if let Some(item) = filtered {
-                        list.push(item);
-                        count += 1;
-                        if count == props.limit {
-                            break;
-                        }
-                    }
-                }
-            }
-        };
+        if self.map.contains_key(&key) {
+            self.set_expiration(&key, ttl);
+            self.replace_entry(&key, value);
+            return Ok(());
+        }
 
-        Ok(list)
-    }
-}
+        let weight = self.weigher.weigh(&key, &value);
 
-#[cfg(test)]
-mod test {
-    use super::*;
+        if self.map.len() != 0 && self.map.len() == self.capacity {
+            // expired entries go first, live ones are only evicted when nothing has expired
+            if self.purge_expired().is_empty() && self.evict().is_none() {
+                return Err(Error::CapacityExceeded);
+            }
+        }
 
-    #[test]
-    fn test_cache_insert() {
-        let mut cache = Cache::new(2);
-        cache.insert("key1", 1);
-        cache.insert("key2", 2);
-        cache.insert("key3", 3);
-        assert_eq!(cache.get("key1"), None);
-        assert_eq!(cache.get("key2"), Some(&2));
-        assert_eq!(cache.get("key3"), Some(&3));
-    }
+        self.shrink_to_max_weight(weight, 0);
 
-    #[test]
-    fn test_cache_remove() {
-        let mut cache = Cache::new(2);
-        cache.insert("key1", 1);
-        cache.insert("key2", 2);
-        cache.remove("key1");
-        assert_eq!(cache.get("key1"), None);
-        cache.insert("key3", 3);
-        assert_eq!(cache.get("key3"), Some(&3));
-        assert_eq!(cache.get("key2"), Some(&2));
+        self.index.insert(key.clone());
+        self.set_expiration(&key, ttl);
+        self.set_weight(&key, weight);
+        self.next_version(&key);
+        self.policy().on_insert(&key);
+        self.map.insert(key.clone(), value);
+        self.changed(Change::Insert, &key);
+        Ok(())
     }
 
-    #[test]
-    fn test_cache_clear() {
-        let mut cache = Cache::new(2);
-        cache.insert("key1", 1);
-        cache.insert("key2", 2);
-        cache.clear();
-        assert_eq!(cache.len(), 0);
+    /// Replaces the value of a present key, keeping its deadline, and returns its new version.
+    fn replace_entry(&mut self, key: &K, value: V) -> u64 {
+        let weight = self.weigher.weigh(key, &value);
+
+        if let Some(current) = self.map.get_mut(key) {
+            if *current != value {
+                *current = value;
+            }
+        }
+
+        self.set_weight(key, weight);
+        let version = self.next_version(key);
+        self.policy().on_access(key);
+        self.changed(Change::Update, key);
+        self.shrink_to_max_weight(0, 1);
+        version
     }
 
-    #[test]
-    fn test_cache_list_asc() {
-        let mut cache = Cache::new(5);
-        cache.insert("key2", 2);
-        cache.insert("key1", 1);
-        cache.insert("key5", 5);
-        cache.insert("key4", 4);
-        cache.insert("key3", 3);
+    /// Evicts entries until `incoming` more weight fits under `max_weight`, or only `keep` entries are left.
+    fn shrink_to_max_weight(&mut self, incoming: usize, keep: usize) {
+        let max_weight = match self.max_weight {
+            Some(max_weight) => max_weight,
+            None => return,
+        };
 
-        let result_res = cache.list(StartAfter::Key("key2"));
+        if self.weight + incoming > max_weight {
+            self.purge_expired();
+        }
 
-        assert_eq!(result_res.is_ok(), true);
+        while self.weight + incoming > max_weight && self.map.len() > keep {
+            if self.evict().is_none() {
+                break;
+            }
+        }
+    }
 
-        let result = match result_res {
-            Ok(result) => result,
-            Err(_) => panic!("Error"),
+    /// Removes the entry chosen by the eviction policy and returns it. Pinned keys are never
+    /// chosen, see `pin`.
+    pub fn evict(&mut self) -> Option<(K, V)> {
+        let mut skipped = Vec::new();
+        let victim = loop {
+            match self.policy().evict() {
+                Some(key) if self.pinned.contains(&key) => skipped.push(key),
+                victim => break victim,
+            }
         };
 
-        assert_eq!(result.len(), 3);
-        assert_eq!(result[0], ("key3", &3));
-        assert_eq!(result[1], ("key4", &4));
-        assert_eq!(result[2], ("key5", &5));
+        // pinned keys go back to the policy, as if they had just been inserted
+        for key in skipped {
+            let mut policy = self.policy();
+            policy.on_insert(&key);
+            policy.on_deadline(&key, self.expirations.get(&key).copied());
+        }
+
+        let victim = victim?;
+        let value = self.remove_entry(&victim)?;
+        self.changed(Change::Evict, &victim);
+        Some((victim, value))
     }
 
-    #[test]
-    fn test_cache_list_desc() {
-        let mut cache = Cache::new(5);
-        cache.insert("key5", 5);
-        cache.insert("key1", 1);
-        cache.insert("key3", 3);
-        cache.insert("key4", 4);
-        cache.insert("key2", 2);
+    fn next_version(&mut self, key: &K) -> u64 {
+        self.version += 1;
+        self.versions.insert(key.clone(), self.version);
+        self.version
+    }
 
-        let result_res = cache.list(ListProps {
-            order: Order::Desc,
-            filter: Filter::None,
-            start_after_key: StartAfter::Key("key3"),
-            limit: 10,
-        });
+    /// Version of a live entry. Versions are unique within a cache and grow with every insert,
+    /// so a key removed and inserted again never gets back an old version.
+    pub fn version_of<Q>(&self, key: &Q) -> Option<u64>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if self.is_expired(key) {
+            return None;
+        }
 
-        assert_eq!(result_res.is_ok(), true);
+        self.versions.get(key).copied()
+    }
 
-        let result = match result_res {
-            Ok(result) => result,
-            Err(_) => panic!("Error"),
-        };
+    /// Gives a live entry a new version, for changes made in place through `get_mut`.
+    pub fn bump_version<Q>(&mut self, key: &Q) -> Option<u64>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if self.is_expired(key) {
+            return None;
+        }
 
-        assert_eq!(result.len(), 2);
-        assert_eq!(result[0], ("key2", &2));
-        assert_eq!(result[1], ("key1", &1));
+        let (key, _) = self.map.get_key_value(key)?;
+        let key = key.clone();
+        Some(self.next_version(&key))
     }
 
-    #[test]
-    fn test_filter_start_with() {
-        let mut cache = Cache::new(10);
+    /// Replaces the value of `key` only if its version is still `expected_version`, and
+    /// returns its new version. The entry keeps the time it had left to live.
+    pub fn compare_and_swap<T>(
+        &mut self,
+        key: T,
+        expected_version: u64,
+        value: V,
+    ) -> Result<u64, Error>
+    where
+        T: Into<K>,
+    {
+        let key = key.into();
 
-        cache.insert("postmodern", 8);
-        cache.insert("postpone", 6);
-        cache.insert("precept", 2);
-        cache.insert("postmortem", 9);
-        cache.insert("precaution", 3);
-        cache.insert("precede", 1);
-        cache.insert("precognition", 5);
-        cache.insert("postmark", 10);
-        cache.insert("postgraduate", 7);
-        cache.insert("preconceive", 4);
+        match self.version_of(&key) {
+            None => Err(Error::KeyNotFound),
+            Some(version) if version != expected_version => Err(Error::VersionMismatch),
+            Some(_) => Ok(self.replace_entry(&key, value)),
+        }
+    }
 
-        let result_res = cache.list(Filter::StartWith("postm"));
+    fn remove_entry(&mut self, key: &K) -> Option<V> {
+        self.versions.remove(key);
+        self.index.remove(key);
+        self.set_expiration(key, None);
+        self.set_weight(key, 0);
+        self.map.remove(key)
+    }
 
-        assert_eq!(result_res.is_ok(), true);
+    fn set_weight(&mut self, key: &K, weight: usize) {
+        if let Some(previous) = self.weights.remove(key) {
+            self.weight -= previous;
+        }
 
-        let result = match result_res {
-            Ok(result) => result,
-            Err(_) => panic!("Error"),
-        };
+        if weight > 0 {
+            self.weights.insert(key.clone(), weight);
+            self.weight += weight;
+        }
+    }
 
-        assert_eq!(result.len(), 3);
-        assert_eq!(result[0], ("postmark", &10));
-        assert_eq!(result[1], ("postmodern", &8));
-        assert_eq!(result[2], ("postmortem", &9));
+    /// Total weight of the entries currently stored, as measured by the weigher.
+    pub fn weight(&self) -> usize {
+        self.weight
     }
 
-    #[test]
+    pub fn max_weight(&self) -> Option<usize> {
+        self.max_weight
+    }
+
+    /// Sets the maximum total weight, evicting entries right away if it is already exceeded.
+    pub fn set_max_weight(&mut self, max_weight: Option<usize>) {
+        self.max_weight = max_weight;
+        self.shrink_to_max_weight(0, 0);
+    }
+
+    /// Replaces the weigher and measures every stored entry again.
+    pub fn set_weigher<W>(&mut self, weigher: W)
+    where
+        W: Weigher<K, V> + 'static,
+    {
+        self.weigher = Arc::new(weigher);
+        self.weights.clear();
+        self.weight = 0;
+
+        for (key, value) in self.map.iter() {
+            let weight = self.weigher.weigh(key, value);
+            if weight > 0 {
+                self.weights.insert(key.clone(), weight);
+                self.weight += weight;
+            }
+        }
+
+        self.shrink_to_max_weight(0, 0);
+    }
+
+    /// Measures `key` again, after its value was changed through `get_mut`.
+    pub fn reweigh<Q>(&mut self, key: &Q)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let (key, weight) = match self.map.get_key_value(key) {
+            Some((key, value)) => (key.clone(), self.weigher.weigh(key, value)),
+            None => return,
+        };
+
+        self.set_weight(&key, weight);
+        self.shrink_to_max_weight(0, 1);
+    }
+
+    fn set_expiration(&mut self, key: &K, ttl: Option<Duration>) {
+        if let Some(deadline) = self.expirations.remove(key) {
+            self.deadlines.remove(&(deadline, key.clone()));
+        }
+
+        let deadline = ttl.map(|ttl| Instant::now() + ttl);
+        if let Some(deadline) = deadline {
+            self.expirations.insert(key.clone(), deadline);
+            self.deadlines.insert((deadline, key.clone()));
+        }
+
+        self.policy().on_deadline(key, deadline);
+    }
+
+    fn is_expired<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        match self.expirations.get(key) {
+            Some(deadline) => *deadline <= Instant::now(),
+            None => false,
+        }
+    }
+
+    /// Removes every expired entry and returns them.
+    pub fn purge_expired(&mut self) -> Vec<(K, V)> {
+        let now = Instant::now();
+        let mut expired = Vec::new();
+
+        while let Some((deadline, _)) = self.deadlines.first() {
+            if *deadline > now {
+                break;
+            }
+
+            let (_, key) = match self.deadlines.pop_first() {
+                Some(entry) => entry,
+                None => break,
+            };
+
+            if let Some(value) = self.remove_entry(&key) {
+                self.policy().on_remove(&key);
+                self.changed(Change::Expire, &key);
+                expired.push((key, value));
+            }
+        }
+
+        expired
+    }
+
+    /// Returns the time left before `key` expires, or `None` if it never expires or is not found.
+    pub fn ttl_of<Q>(&self, key: &Q) -> Option<Duration>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.expirations
+            .get(key)
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    pub fn ttl(&self) -> Option<Duration> {
+        self.ttl
+    }
+
+    /// Sets the default TTL applied by `insert`. Entries already stored keep their expiration.
+    pub fn set_ttl(&mut self, ttl: Option<Duration>) {
+        self.ttl = ttl;
+    }
+
+    pub fn insert_if_not_exists<T>(&mut self, key: T, value: V) -> Result<(), Error>
+    where
+        T: Into<K>,
+    {
+        let key = key.into();
+
+        if self.contains_key(&key) {
+            return Err(Error::SortKeyExists);
+        }
+
+        let ttl = self.ttl;
+        self.insert_entry(key, value, ttl)
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if self.is_expired(key) {
+            self.counters.read(false);
+            return None;
+        }
+
+        let (key, value) = match self.map.get_key_value(key) {
+            Some(entry) => entry,
+            None => {
+                self.counters.read(false);
+                return None;
+            }
+        };
+
+        self.counters.read(true);
+        self.policy().on_access(key);
+        Some(value)
+    }
+
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if self.is_expired(key) {
+            self.counters.read(false);
+            return None;
+        }
+
+        match self.map.get_key_value(key) {
+            Some((stored, _)) => {
+                self.counters.read(true);
+                self.policy
+                    .get_mut()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .on_access(stored);
+            }
+            None => self.counters.read(false),
+        }
+
+        self.map.get_mut(key)
+    }
+
+    /// Like `get`, without counting as a read for the stats or an access for the policy.
+    pub fn peek<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if self.is_expired(key) {
+            return None;
+        }
+
+        self.map.get(key)
+    }
+
+    /// Like `get_mut`, without counting as a read for the stats or an access for the policy.
+    pub fn peek_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if self.is_expired(key) {
+            return None;
+        }
+
+        self.map.get_mut(key)
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+    }
+
+    /// Checks `op` against the cache as changed by the operations already recorded in
+    /// `staged`, which maps every key they wrote to whether it still exists.
+    pub fn check(&self, op: &Op<K, V>, staged: &mut HashMap<K, bool>) -> Result<(), Error> {
+        let key = op.key();
+        let exists = match staged.get(key) {
+            Some(exists) => *exists,
+            None => self.contains_key(key),
+        };
+
+        match op {
+            Op::InsertIfNotExists(..) if exists => return Err(Error::SortKeyExists),
+            Op::Remove(_) if !exists => return Err(Error::KeyNotFound),
+            _ => {}
+        }
+
+        staged.insert(key.clone(), !matches!(op, Op::Remove(_)));
+        Ok(())
+    }
+
+    /// Checks that every insert of a new key in `ops`, applied in order, finds room: in a full
+    /// cache, a live entry that is not in `pinned` must be left to evict. Fails with
+    /// `Error::Batch` holding `Error::CapacityExceeded` and the position of the first insert
+    /// that finds none.
+    pub fn check_room<'a, I>(&self, ops: I, pinned: &BTreeSet<K>) -> Result<(), Error>
+    where
+        I: IntoIterator<Item = (usize, &'a Op<K, V>)>,
+        K: 'a,
+        V: 'a,
+    {
+        if self.capacity == 0 {
+            return Ok(());
+        }
+
+        let now = Instant::now();
+        let expired = self
+            .deadlines
+            .iter()
+            .take_while(|(deadline, _)| *deadline <= now)
+            .count();
+        let mut len = self.map.len() - expired;
+        let mut spare = len - pinned.iter().filter(|key| self.contains_key(*key)).count();
+        let mut exists = HashMap::new();
+
+        for (index, op) in ops {
+            let key = op.key();
+            let existed = match exists.get(key) {
+                Some(existed) => *existed,
+                None => self.contains_key(key),
+            };
+
+            match op {
+                Op::Insert(..) | Op::InsertIfNotExists(..) if !existed => {
+                    if len == self.capacity {
+                        if spare == 0 {
+                            return Err(Error::Batch(index, Box::new(Error::CapacityExceeded)));
+                        }
+                        spare -= 1;
+                        len -= 1;
+                    }
+                    len += 1;
+                }
+                Op::Remove(_) if existed => len -= 1,
+                _ => {}
+            }
+
+            exists.insert(key.clone(), !matches!(op, Op::Remove(_)));
+        }
+
+        Ok(())
+    }
+
+    /// Keeps `keys` from being evicted, to make room or to shrink to `max_weight`, until
+    /// `unpin`. An insert that only finds pinned keys to evict fails with
+    /// `Error::CapacityExceeded`.
+    pub fn pin<I>(&mut self, keys: I)
+    where
+        I: IntoIterator<Item = K>,
+    {
+        self.pinned.extend(keys);
+    }
+
+    pub fn unpin(&mut self) {
+        self.pinned.clear();
+    }
+
+    /// Writes `op` without checking it first, and returns the error of the write, if any.
+    pub fn apply_op(&mut self, op: Op<K, V>) -> Result<(), Error> {
+        match op {
+            Op::Insert(key, value) => {
+                let ttl = self.ttl;
+                self.insert_entry(key, value, ttl)
+            }
+            Op::InsertIfNotExists(key, value) => self.insert_if_not_exists(key, value),
+            Op::Remove(key) => self.remove(&key),
+        }
+    }
+
+    /// Applies every operation of `batch` in order, or none of them when one fails its check.
+    ///
+    /// The keys of the batch are pinned while it is applied, so that making room for one of
+    /// its inserts never evicts another of its keys. A batch that cannot make room otherwise
+    /// fails its check with `Error::CapacityExceeded`, see `check_room`.
+    pub fn apply(&mut self, batch: Batch<K, V>) -> Result<(), Error> {
+        let mut staged = HashMap::new();
+
+        for (index, op) in batch.ops().iter().enumerate() {
+            self.check(op, &mut staged)
+                .map_err(|err| Error::Batch(index, Box::new(err)))?;
+        }
+
+        let pinned: BTreeSet<K> = batch.ops().iter().map(|op| op.key().clone()).collect();
+        self.check_room(batch.ops().iter().enumerate(), &pinned)?;
+        self.pin(pinned);
+
+        let mut applied = Ok(());
+        for (index, op) in batch.into_iter().enumerate() {
+            if let Err(err) = self.apply_op(op) {
+                applied = Err(Error::Batch(index, Box::new(err)));
+                break;
+            }
+        }
+
+        self.unpin();
+        applied
+    }
+
+    /// Removes a live entry. An expired entry is a miss, as for `get`, and is dropped as
+    /// expired.
+    pub fn remove<Q>(&mut self, key: &Q) -> Result<(), Error>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Ord + ?Sized,
+    {
+        let expired = self.is_expired(key);
+
+        match self.index.take(key) {
+            Some(removed) => {
+                self.remove_entry(&removed);
+                self.policy().on_remove(&removed);
+
+                if expired {
+                    self.changed(Change::Expire, &removed);
+                    Err(Error::KeyNotFound)
+                } else {
+                    self.changed(Change::Remove, &removed);
+                    Ok(())
+                }
+            }
+            None => Err(Error::KeyNotFound),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        if !self.listeners.is_empty() {
+            for key in &self.index {
+                self.changed(Change::Remove, key);
+            }
+        }
+
+        self.map.clear();
+        self.index.clear();
+        self.expirations.clear();
+        self.deadlines.clear();
+        self.weights.clear();
+        self.versions.clear();
+        self.weight = 0;
+        self.policy().clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.contains_key(key) && !self.is_expired(key)
+    }
+
+    /// Iterates over every entry in no particular order, expired entries included.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&K, &mut V)> {
+        self.map.iter_mut()
+    }
+
+    /// Iterates over every value in no particular order, expired entries included.
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.map.values()
+    }
+
+    /// Iterates lazily over the live entries in key order.
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        self.range::<K, _>(..)
+    }
+
+    /// Iterates lazily over the live entries whose key is within `range`, in key order.
+    pub fn range<Q, R>(&self, range: R) -> Iter<'_, K, V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+        R: RangeBounds<Q>,
+    {
+        let keys = if is_empty_range(range.start_bound(), range.end_bound()) {
+            None
+        } else {
+            Some(self.index.range(range))
+        };
+
+        Iter {
+            cache: self,
+            keys,
+            filter: Filter::None,
+            order: Order::Asc,
+        }
+    }
+
+    /// Lists the entries in key order. Only the keys between `from` and `to` are visited, so
+    /// a bounded list does not scan the whole cache. Props with a clause are refused, see
+    /// `list_where`.
+    pub fn list<T>(&self, props: T) -> Result<Page<(&K, &V), K>, Error>
+    where
+        T: Into<ListProps<K>>,
+    {
+        let props = props.into();
+        if props.clause.is_some() {
+            return Err(Error::ClauseUnsupported);
+        }
+
+        self.list_matching(props, |_| Ok(true))
+    }
+
+    /// Lists the entries like `list`, keeping those whose value matches the clause of the
+    /// props, if any.
+    pub fn list_where<T>(&self, props: T) -> Result<Page<(&K, &V), K>, Error>
+    where
+        T: Into<ListProps<K>>,
+        V: Document,
+    {
+        let props = props.into();
+        let plan = match &props.clause {
+            Some(clause) => clause.compile().map_err(Error::Clause)?,
+            None => return self.list_matching(props, |_| Ok(true)),
+        };
+        let attributes = plan.attributes();
+
+        self.list_matching(props, |value| {
+            plan.execute(&value.to_document(&attributes))
+                .map_err(Error::Clause)
+        })
+    }
+
+    fn list_matching<F>(
+        &self,
+        props: ListProps<K>,
+        mut matches: F,
+    ) -> Result<Page<(&K, &V), K>, Error>
+    where
+        F: FnMut(&V) -> Result<bool, Error>,
+    {
+        if let StartAfter::Key(key) = &props.start_after_key {
+            if !self.index.contains(key) {
+                return Err(Error::SortKeyNotFound);
+            }
+        }
+
+        // the start key and the cursor both skip the keys up to them in the listing order
+        let mut from = props.from.as_ref();
+        let mut to = props.to.as_ref();
+        let starts = [
+            match &props.start_after_key {
+                StartAfter::Key(key) => Some(key),
+                StartAfter::None => None,
+            },
+            props.cursor.as_ref().map(|cursor| &cursor.key),
+        ];
+
+        for key in starts.into_iter().flatten() {
+            match props.order {
+                Order::Asc => from = narrowest(from, Bound::Excluded(key), true),
+                Order::Desc => to = narrowest(to, Bound::Excluded(key), false),
+            }
+        }
+
+        let mut entries = self
+            .range::<K, _>((from, to))
+            .order(props.order.clone())
+            .filter_keys(props.filter.clone())
+            .filter_map(|entry| match matches(entry.1) {
+                Ok(true) => Some(Ok(entry)),
+                Ok(false) => None,
+                Err(err) => Some(Err(err)),
+            });
+
+        let mut items = Vec::new();
+
+        for entry in entries.by_ref() {
+            items.push(entry?);
+            if items.len() == props.limit {
+                break;
+            }
+        }
+
+        let cursor = match items.last() {
+            Some((last, _)) if entries.next().is_some() => Some(Cursor {
+                key: (*last).clone(),
+            }),
+            _ => None,
+        };
+
+        Ok(Page { items, cursor })
+    }
+}
+
+/// Lazy iterator over the live entries of a `Cache`, see `Cache::iter` and `Cache::range`.
+///
+/// Expired entries and keys rejected by the filter are skipped as the iterator advances,
+/// from either end.
+pub struct Iter<'a, K, V>
+where
+    K: CacheKey,
+    V: PartialEq,
+{
+    cache: &'a Cache<K, V>,
+    keys: Option<btree_set::Range<'a, K>>,
+    filter: Filter<K>,
+    order: Order,
+}
+
+impl<'a, K, V> Iter<'a, K, V>
+where
+    K: CacheKey,
+    V: PartialEq,
+{
+    /// `Order::Desc` yields the entries from the last key, `next_back` then starts from the first.
+    pub fn order(mut self, order: Order) -> Self {
+        self.order = order;
+        self
+    }
+
+    /// Only yields the keys matching `filter`.
+    pub fn filter_keys(mut self, filter: Filter<K>) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    fn accepts(&self, key: &K) -> bool {
+        if self.cache.is_expired(key) {
+            return false;
+        }
+
+        match &self.filter {
+            Filter::StartWith(prefix) => key.starts_with(prefix),
+            Filter::EndWith(suffix) => key.ends_with(suffix),
+            Filter::StartAndEndWith(prefix, suffix) => {
+                key.starts_with(prefix) && key.ends_with(suffix)
+            }
+            Filter::None => true,
+        }
+    }
+
+    fn advance(&mut self, back: bool) -> Option<(&'a K, &'a V)> {
+        loop {
+            let keys = self.keys.as_mut()?;
+            let key = if back {
+                keys.next_back()?
+            } else {
+                keys.next()?
+            };
+
+            if self.accepts(key) {
+                return self.cache.map.get(key).map(|value| (key, value));
+            }
+        }
+    }
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V>
+where
+    K: CacheKey,
+    V: PartialEq,
+{
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let back = matches!(self.order, Order::Desc);
+        self.advance(back)
+    }
+}
+
+impl<'a, K, V> DoubleEndedIterator for Iter<'a, K, V>
+where
+    K: CacheKey,
+    V: PartialEq,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let back = matches!(self.order, Order::Asc);
+        self.advance(back)
+    }
+}
+
+impl<'a, K, V> FusedIterator for Iter<'a, K, V>
+where
+    K: CacheKey,
+    V: PartialEq,
+{
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::eviction::{Fifo, Lfu, TtlFirst};
+
+    #[test]
+    fn test_cache_insert() {
+        let mut cache: Cache<String, i32> = Cache::new(2);
+        cache.insert("key1", 1).unwrap();
+        cache.insert("key2", 2).unwrap();
+        cache.insert("key3", 3).unwrap();
+        assert_eq!(cache.get("key1"), None);
+        assert_eq!(cache.get("key2"), Some(&2));
+        assert_eq!(cache.get("key3"), Some(&3));
+    }
+
+    #[test]
+    fn test_cache_remove() {
+        let mut cache: Cache<String, i32> = Cache::new(2);
+        cache.insert("key1", 1).unwrap();
+        cache.insert("key2", 2).unwrap();
+        cache.remove("key1");
+        assert_eq!(cache.get("key1"), None);
+        cache.insert("key3", 3).unwrap();
+        assert_eq!(cache.get("key3"), Some(&3));
+        assert_eq!(cache.get("key2"), Some(&2));
+    }
+
+    #[test]
+    fn test_cache_clear() {
+        let mut cache: Cache<String, i32> = Cache::new(2);
+        cache.insert("key1", 1).unwrap();
+        cache.insert("key2", 2).unwrap();
+        cache.clear();
+        assert_eq!(cache.len(), 0);
+    }
+
+    #[test]
+    fn test_cache_list_asc() {
+        let mut cache: Cache<String, i32> = Cache::new(5);
+        cache.insert("key2", 2).unwrap();
+        cache.insert("key1", 1).unwrap();
+        cache.insert("key5", 5).unwrap();
+        cache.insert("key4", 4).unwrap();
+        cache.insert("key3", 3).unwrap();
+
+        let result_res = cache.list(StartAfter::Key("key2".to_string()));
+
+        assert_eq!(result_res.is_ok(), true);
+
+        let result = match result_res {
+            Ok(result) => result,
+            Err(_) => panic!("Error"),
+        };
+
+        assert_eq!(result.len(), 3);
+        assert_eq!(result.items[0], (&"key3".to_string(), &3));
+        assert_eq!(result.items[1], (&"key4".to_string(), &4));
+        assert_eq!(result.items[2], (&"key5".to_string(), &5));
+    }
+
+    #[test]
+    fn test_cache_list_desc() {
+        let mut cache: Cache<String, i32> = Cache::new(5);
+        cache.insert("key5", 5).unwrap();
+        cache.insert("key1", 1).unwrap();
+        cache.insert("key3", 3).unwrap();
+        cache.insert("key4", 4).unwrap();
+        cache.insert("key2", 2).unwrap();
+
+        let result_res = cache.list(ListProps {
+            order: Order::Desc,
+            filter: Filter::None,
+            start_after_key: StartAfter::Key("key3".to_string()),
+            limit: 10,
+            ..Default::default()
+        });
+
+        assert_eq!(result_res.is_ok(), true);
+
+        let result = match result_res {
+            Ok(result) => result,
+            Err(_) => panic!("Error"),
+        };
+
+        assert_eq!(result.len(), 2);
+        assert_eq!(result.items[0], (&"key2".to_string(), &2));
+        assert_eq!(result.items[1], (&"key1".to_string(), &1));
+    }
+
+    #[test]
+    fn test_filter_start_with() {
+        let mut cache: Cache<String, i32> = Cache::new(10);
+
+        cache.insert("postmodern", 8).unwrap();
+        cache.insert("postpone", 6).unwrap();
+        cache.insert("precept", 2).unwrap();
+        cache.insert("postmortem", 9).unwrap();
+        cache.insert("precaution", 3).unwrap();
+        cache.insert("precede", 1).unwrap();
+        cache.insert("precognition", 5).unwrap();
+        cache.insert("postmark", 10).unwrap();
+        cache.insert("postgraduate", 7).unwrap();
+        cache.insert("preconceive", 4).unwrap();
+
+        let result_res = cache.list(Filter::StartWith("postm".to_string()));
+
+        assert_eq!(result_res.is_ok(), true);
+
+        let result = match result_res {
+            Ok(result) => result,
+            Err(_) => panic!("Error"),
+        };
+
+        assert_eq!(result.len(), 3);
+        assert_eq!(result.items[0], (&"postmark".to_string(), &10));
+        assert_eq!(result.items[1], (&"postmodern".to_string(), &8));
+        assert_eq!(result.items[2], (&"postmortem".to_string(), &9));
+    }
+
+    #[test]
     fn test_filter_ends_with() {
-        let mut cache = Cache::new(10);
+        let mut cache: Cache<String, i32> = Cache::new(10);
 
-        cache.insert("postmodern", 8);
-        cache.insert("postpone", 6);
-        cache.insert("precept", 2);
-        cache.insert("postmortem", 9);
-        cache.insert("precaution", 3);
-        cache.insert("precede", 1);
-        cache.insert("precognition", 5);
-        cache.insert("postmark", 10);
-        cache.insert("postgraduate", 7);
-        cache.insert("preconceive", 4);
+        cache.insert("postmodern", 8).unwrap();
+        cache.insert("postpone", 6).unwrap();
+        cache.insert("precept", 2).unwrap();
+        cache.insert("postmortem", 9).unwrap();
+        cache.insert("precaution", 3).unwrap();
+        cache.insert("precede", 1).unwrap();
+        cache.insert("precognition", 5).unwrap();
+        cache.insert("postmark", 10).unwrap();
+        cache.insert("postgraduate", 7).unwrap();
+        cache.insert("preconceive", 4).unwrap();
 
-        let result_res = cache.list(Filter::EndWith("tion"));
+        let result_res = cache.list(Filter::EndWith("tion".to_string()));
 
         assert_eq!(result_res.is_ok(), true);
 
@@ -582,7 +1620,597 @@ mod test {
         };
 
         assert_eq!(result.len(), 2);
-        assert_eq!(result[0], ("precaution", &3));
-        assert_eq!(result[1], ("precognition", &5));
+        assert_eq!(result.items[0], (&"precaution".to_string(), &3));
+        assert_eq!(result.items[1], (&"precognition".to_string(), &5));
+    }
+
+    #[test]
+    fn test_cache_owned_keys() {
+        let mut cache: Cache<String, i32> = Cache::new(10);
+
+        for i in 0..3 {
+            cache.insert(format!("key{}", i), i).unwrap();
+        }
+
+        cache.insert(String::from("key1"), 10).unwrap();
+
+        assert_eq!(cache.len(), 3);
+        assert_eq!(cache.get("key1"), Some(&10));
+    }
+
+    #[test]
+    fn test_cache_bytes_keys() {
+        let mut cache: Cache<Vec<u8>, i32> = Cache::new(10);
+
+        cache.insert(b"precept".to_vec(), 2).unwrap();
+        cache.insert(b"postmark".to_vec(), 10).unwrap();
+        cache.insert(b"postpone".to_vec(), 6).unwrap();
+
+        let result = match cache.list(Filter::StartWith(b"post".to_vec())) {
+            Ok(result) => result,
+            Err(_) => panic!("Error"),
+        };
+
+        assert_eq!(result.len(), 2);
+        assert_eq!(result.items[0], (&b"postmark".to_vec(), &10));
+        assert_eq!(result.items[1], (&b"postpone".to_vec(), &6));
+    }
+
+    #[test]
+    fn test_cache_list_values_without_document() {
+        #[derive(Debug, PartialEq)]
+        struct Blob(u8);
+
+        let mut cache: Cache<Vec<u8>, Blob> = Cache::new(10);
+        cache.insert(b"a".to_vec(), Blob(1)).unwrap();
+        cache.insert(b"b".to_vec(), Blob(2)).unwrap();
+
+        let page = cache.list(ListProps::default().limit(1)).unwrap();
+        assert_eq!(page.items, vec![(&b"a".to_vec(), &Blob(1))]);
+        assert!(page.cursor.is_some());
+
+        let clause = Clause::condition(condition::Operator::Equal, "a", 1);
+        assert!(matches!(
+            cache.list(ListProps::default().clause(clause)),
+            Err(Error::ClauseUnsupported)
+        ));
+    }
+
+    #[test]
+    fn test_cache_evicts_least_recently_used() {
+        let mut cache: Cache<String, i32> = Cache::new(2);
+        cache.insert("key1", 1).unwrap();
+        cache.insert("key2", 2).unwrap();
+        cache.get("key1");
+        cache.insert("key3", 3).unwrap();
+        assert_eq!(cache.get("key1"), Some(&1));
+        assert_eq!(cache.get("key2"), None);
+        assert_eq!(cache.get("key3"), Some(&3));
+    }
+
+    #[test]
+    fn test_cache_evicts_least_frequently_used() {
+        let mut cache: Cache<String, i32> = Cache::with_policy(2, Lfu::new());
+        cache.insert("key1", 1).unwrap();
+        cache.insert("key2", 2).unwrap();
+        cache.get("key2");
+        cache.get("key1");
+        cache.get("key1");
+        cache.insert("key3", 3).unwrap();
+        assert_eq!(cache.get("key1"), Some(&1));
+        assert_eq!(cache.get("key2"), None);
+        assert_eq!(cache.get("key3"), Some(&3));
+    }
+
+    #[test]
+    fn test_cache_evicts_in_insertion_order() {
+        let mut cache: Cache<String, i32> = Cache::with_policy(2, Fifo::new());
+        cache.insert("key2", 2).unwrap();
+        cache.insert("key1", 1).unwrap();
+        cache.get("key2");
+        cache.insert("key3", 3).unwrap();
+        assert_eq!(cache.get("key2"), None);
+        assert_eq!(cache.get("key1"), Some(&1));
+        assert_eq!(cache.get("key3"), Some(&3));
+    }
+
+    #[test]
+    fn test_cache_evicts_nearest_deadline() {
+        let mut cache: Cache<String, i32> = Cache::with_policy(3, TtlFirst::new());
+        cache.insert("key1", 1).unwrap();
+        cache
+            .insert_with_ttl("key2", 2, Duration::from_secs(60))
+            .unwrap();
+        cache
+            .insert_with_ttl("key3", 3, Duration::from_secs(30))
+            .unwrap();
+        cache.get("key3");
+        cache.insert("key4", 4).unwrap();
+        assert_eq!(cache.get("key3"), None);
+
+        cache.insert("key5", 5).unwrap();
+        assert_eq!(cache.get("key2"), None);
+        assert_eq!(cache.get("key1"), Some(&1));
+
+        cache.insert("key6", 6).unwrap();
+        assert_eq!(cache.get("key4"), None);
+        assert_eq!(cache.len(), 3);
+    }
+
+    /// A policy that never has a victim.
+    #[derive(Debug, Clone)]
+    struct Pinned;
+
+    impl EvictionPolicy<String> for Pinned {
+        fn on_insert(&mut self, _key: &String) {}
+        fn on_access(&mut self, _key: &String) {}
+        fn on_remove(&mut self, _key: &String) {}
+        fn evict(&mut self) -> Option<String> {
+            None
+        }
+        fn clear(&mut self) {}
+        fn clone_box(&self) -> Box<dyn EvictionPolicy<String>> {
+            Box::new(self.clone())
+        }
+    }
+
+    #[test]
+    fn test_cache_refuses_insert_without_victim() {
+        let mut cache: Cache<String, i32> = Cache::with_policy(2, Pinned);
+        cache.insert("key1", 1).unwrap();
+        cache.insert("key2", 2).unwrap();
+        assert!(matches!(
+            cache.insert("key3", 3),
+            Err(Error::CapacityExceeded)
+        ));
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get("key3"), None);
+        assert!(matches!(
+            cache.insert_if_not_exists("key4", 4),
+            Err(Error::CapacityExceeded)
+        ));
+
+        cache.insert("key1", 10).unwrap();
+        assert_eq!(cache.get("key1"), Some(&10));
+    }
+
+    #[test]
+    fn test_cache_insert_with_ttl() {
+        let mut cache: Cache<String, i32> = Cache::new(10);
+        cache.insert("key1", 1).unwrap();
+        cache.insert_with_ttl("key2", 2, Duration::ZERO).unwrap();
+        cache
+            .insert_with_ttl("key3", 3, Duration::from_secs(60))
+            .unwrap();
+
+        assert_eq!(cache.get("key1"), Some(&1));
+        assert_eq!(cache.get("key2"), None);
+        assert_eq!(cache.get("key3"), Some(&3));
+        assert!(!cache.contains_key("key2"));
+        assert_eq!(cache.ttl_of("key1"), None);
+        assert!(cache.ttl_of("key3").is_some());
+
+        let result = match cache.list(Filter::None) {
+            Ok(result) => result,
+            Err(_) => panic!("Error"),
+        };
+
+        assert_eq!(result.len(), 2);
+        assert_eq!(result.items[0], (&"key1".to_string(), &1));
+        assert_eq!(result.items[1], (&"key3".to_string(), &3));
+    }
+
+    #[test]
+    fn test_cache_default_ttl() {
+        let mut cache: Cache<String, i32> = Cache::new(10);
+        cache.set_ttl(Some(Duration::ZERO));
+        cache.insert("key1", 1).unwrap();
+        cache.set_ttl(None);
+        cache.insert("key2", 2).unwrap();
+
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.purge_expired(), vec![("key1".to_string(), 1)]);
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.get("key2"), Some(&2));
+    }
+
+    #[test]
+    fn test_cache_evicts_expired_first() {
+        let mut cache: Cache<String, i32> = Cache::new(2);
+        cache.insert_with_ttl("key1", 1, Duration::ZERO).unwrap();
+        cache.insert("key2", 2).unwrap();
+        cache.get("key1");
+        cache.insert("key3", 3).unwrap();
+
+        assert_eq!(cache.get("key2"), Some(&2));
+        assert_eq!(cache.get("key3"), Some(&3));
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn test_cache_remove_expired_key() {
+        let mut cache: Cache<String, i32> = Cache::new(2);
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        let recorded = changes.clone();
+        cache.add_listener(move |change, key: &String| {
+            recorded.lock().unwrap().push((change, key.clone()));
+        });
+        cache.insert_with_ttl("key1", 1, Duration::ZERO).unwrap();
+
+        assert!(matches!(cache.remove("key1"), Err(Error::KeyNotFound)));
+        assert!(cache.is_empty());
+        assert_eq!(cache.stats().expirations, 1);
+        assert_eq!(
+            changes.lock().unwrap().last(),
+            Some(&(Change::Expire, "key1".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_cache_reinsert_expired_key() {
+        let mut cache: Cache<String, i32> = Cache::new(2);
+        cache.insert_with_ttl("key1", 1, Duration::ZERO).unwrap();
+
+        assert!(cache.insert_if_not_exists("key1", 2).is_ok());
+        assert_eq!(cache.get("key1"), Some(&2));
+        assert_eq!(cache.ttl_of("key1"), None);
+    }
+
+    #[test]
+    fn test_cache_list_keeps_order() {
+        let mut cache: Cache<String, i32> = Cache::new(1000);
+
+        for i in (0..1000).rev() {
+            cache.insert(format!("key{:04}", i), i).unwrap();
+        }
+        cache.remove("key0500").unwrap();
+
+        let result = match cache.list(ListProps {
+            order: Order::Desc,
+            filter: Filter::None,
+            start_after_key: StartAfter::Key("key0502".to_string()),
+            limit: 3,
+            ..Default::default()
+        }) {
+            Ok(result) => result,
+            Err(_) => panic!("Error"),
+        };
+
+        assert_eq!(result.len(), 3);
+        assert_eq!(result.items[0], (&"key0501".to_string(), &501));
+        assert_eq!(result.items[1], (&"key0499".to_string(), &499));
+        assert_eq!(result.items[2], (&"key0498".to_string(), &498));
+        assert!(cache.list(StartAfter::Key("key0500".to_string())).is_err());
+    }
+
+    fn months() -> Cache<String, i32> {
+        let mut cache = Cache::new(12);
+        for month in 1..=12 {
+            cache.insert(format!("2020-{:02}", month), month).unwrap();
+        }
+        cache
+    }
+
+    fn keys(page: &Page<(&String, &i32), String>) -> Vec<String> {
+        page.items.iter().map(|(key, _)| key.to_string()).collect()
+    }
+
+    #[test]
+    fn test_cache_list_range() {
+        let cache = months();
+
+        let page = cache
+            .list(
+                ListProps::default()
+                    .from("2020-01", true)
+                    .to("2020-03", true),
+            )
+            .unwrap();
+        assert_eq!(keys(&page), vec!["2020-01", "2020-02", "2020-03"]);
+        assert_eq!(page.cursor, None);
+
+        let page = cache
+            .list(
+                ListProps::default()
+                    .from("2020-01", false)
+                    .to("2020-03", false),
+            )
+            .unwrap();
+        assert_eq!(keys(&page), vec!["2020-02"]);
+
+        let page = cache
+            .list(
+                ListProps::default()
+                    .from("2020-10", true)
+                    .to("2020-12", false)
+                    .order(Order::Desc),
+            )
+            .unwrap();
+        assert_eq!(keys(&page), vec!["2020-11", "2020-10"]);
+
+        let page = cache
+            .list(
+                ListProps::default()
+                    .from("2020-05", false)
+                    .to("2020-05", true),
+            )
+            .unwrap();
+        assert!(page.is_empty());
+
+        let page = cache
+            .list(
+                ListProps::default()
+                    .from("2020-06", true)
+                    .to("2020-02", true),
+            )
+            .unwrap();
+        assert!(page.is_empty());
+    }
+
+    #[test]
+    fn test_cache_list_default_limit() {
+        let cache = months();
+
+        assert_eq!(cache.list(ListProps::default()).unwrap().len(), 12);
+        assert_eq!(cache.list(Filter::None).unwrap().len(), 12);
+        assert_eq!(cache.list(Order::Desc).unwrap().len(), 12);
+        assert_eq!(cache.list(StartAfter::None).unwrap().len(), 12);
+    }
+
+    #[test]
+    fn test_cache_list_cursor() {
+        let mut cache = months();
+        let props = || {
+            ListProps::default()
+                .from("2020-03", true)
+                .to("2020-09", true)
+                .limit(3)
+        };
+
+        let page = cache.list(props()).unwrap();
+        assert_eq!(keys(&page), vec!["2020-03", "2020-04", "2020-05"]);
+        let cursor = page.cursor.unwrap();
+
+        // the cursor survives the removal of the key it points to
+        cache.remove("2020-05").unwrap();
+
+        let page = cache.list(props().cursor(cursor)).unwrap();
+        assert_eq!(keys(&page), vec!["2020-06", "2020-07", "2020-08"]);
+
+        let cursor = Cursor::<String>::decode(&page.cursor.unwrap().encode()).unwrap();
+        let page = cache.list(props().cursor(cursor)).unwrap();
+        assert_eq!(keys(&page), vec!["2020-09"]);
+        assert_eq!(page.cursor, None);
+
+        let page = cache.list(props().order(Order::Desc).limit(2)).unwrap();
+        assert_eq!(keys(&page), vec!["2020-09", "2020-08"]);
+
+        let page = cache
+            .list(props().order(Order::Desc).cursor(page.cursor.unwrap()))
+            .unwrap();
+        assert_eq!(keys(&page), vec!["2020-07", "2020-06", "2020-04"]);
+        assert!(page.cursor.is_some());
+    }
+
+    #[test]
+    fn test_cache_listeners() {
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        let changes_clone = changes.clone();
+
+        let mut cache: Cache<String, i32> = Cache::new(2);
+        cache.add_listener(move |change, key: &String| {
+            changes_clone.lock().unwrap().push((change, key.clone()));
+        });
+
+        cache.insert("key1", 1).unwrap();
+        cache.insert("key1", 2).unwrap();
+        cache.insert("key2", 2).unwrap();
+        cache.insert("key3", 3).unwrap();
+        cache.remove("key2").unwrap();
+        cache.insert_with_ttl("key4", 4, Duration::ZERO).unwrap();
+        cache.purge_expired();
+        cache.clear();
+
+        // a copy does not share the listeners of the original
+        let mut copy = cache.clone();
+        copy.insert("key5", 5).unwrap();
+
+        let key = |key: &str| key.to_string();
+        assert_eq!(
+            *changes.lock().unwrap(),
+            vec![
+                (Change::Insert, key("key1")),
+                (Change::Update, key("key1")),
+                (Change::Insert, key("key2")),
+                (Change::Evict, key("key1")),
+                (Change::Insert, key("key3")),
+                (Change::Remove, key("key2")),
+                (Change::Insert, key("key4")),
+                (Change::Expire, key("key4")),
+                (Change::Remove, key("key3")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cache_stats() {
+        let mut cache: Cache<String, i32> = Cache::new(2);
+        cache.insert("key1", 1).unwrap();
+        cache.insert("key1", 2).unwrap();
+        cache.insert("key2", 2).unwrap();
+        cache.insert("key3", 3).unwrap();
+        cache.insert_with_ttl("key4", 4, Duration::ZERO).unwrap();
+
+        cache.get("key1");
+        cache.get("key3");
+        cache.get("key4");
+        cache.get_mut("key3");
+        cache.purge_expired();
+
+        assert_eq!(
+            cache.stats(),
+            Stats {
+                hits: 2,
+                misses: 2,
+                inserts: 4,
+                updates: 1,
+                evictions: 2,
+                expirations: 1,
+            }
+        );
+
+        cache.reset_stats();
+        assert_eq!(cache.peek("key3"), Some(&3));
+        assert_eq!(cache.stats(), Stats::default());
+    }
+
+    #[test]
+    fn test_cache_iter_range() {
+        let mut cache: Cache<String, i32> = Cache::new(10);
+        for (i, key) in ["a1", "a2", "b1", "b2", "c1"].iter().enumerate() {
+            cache.insert(*key, i as i32).unwrap();
+        }
+        cache.insert_with_ttl("b3", 5, Duration::ZERO).unwrap();
+
+        fn keys<'a>(iter: impl Iterator<Item = (&'a String, &'a i32)>) -> Vec<String> {
+            iter.map(|(key, _)| key.clone()).collect()
+        }
+
+        assert_eq!(keys(cache.iter()), ["a1", "a2", "b1", "b2", "c1"]);
+        assert_eq!(
+            keys(cache.range::<str, _>((Bound::Included("a2"), Bound::Excluded("c1")))),
+            ["a2", "b1", "b2"]
+        );
+        assert_eq!(keys(cache.range("b".to_string()..)), ["b1", "b2", "c1"]);
+        assert_eq!(
+            keys(cache.range("c".to_string().."a".to_string())),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            keys(cache.iter().filter_keys(Filter::EndWith("1".to_string()))),
+            ["a1", "b1", "c1"]
+        );
+        assert_eq!(
+            keys(
+                cache
+                    .iter()
+                    .order(Order::Desc)
+                    .filter_keys(Filter::StartWith("b".to_string()))
+            ),
+            ["b2", "b1"]
+        );
+
+        // both ends meet in the middle, whatever the order
+        let mut iter = cache.iter().order(Order::Desc);
+        assert_eq!(iter.next().map(|(key, _)| key.as_str()), Some("c1"));
+        assert_eq!(iter.next_back().map(|(key, _)| key.as_str()), Some("a1"));
+        assert_eq!(keys(iter.rev()), ["a2", "b1", "b2"]);
+
+        // stops early without reading further, and counts no hit
+        assert_eq!(cache.iter().map(|(_, value)| value).take(2).sum::<i32>(), 1);
+        assert_eq!(cache.stats().hits, 0);
+    }
+
+    #[test]
+    fn test_cache_versions() {
+        let mut cache: Cache<String, i32> = Cache::new(10);
+        cache.insert("key1", 1).unwrap();
+        cache.insert("key2", 2).unwrap();
+
+        let version = cache.version_of("key1").unwrap();
+        assert!(cache.version_of("key2").unwrap() > version);
+        assert_eq!(cache.version_of("key3"), None);
+
+        let swapped = cache.compare_and_swap("key1", version, 10).unwrap();
+        assert!(swapped > version);
+        assert_eq!(cache.version_of("key1"), Some(swapped));
+        assert!(matches!(
+            cache.compare_and_swap("key1", version, 11),
+            Err(Error::VersionMismatch)
+        ));
+        assert!(matches!(
+            cache.compare_and_swap("key3", version, 3),
+            Err(Error::KeyNotFound)
+        ));
+        assert_eq!(cache.get("key1"), Some(&10));
+
+        cache.remove("key1").unwrap();
+        cache.insert("key1", 1).unwrap();
+        assert!(cache.version_of("key1").unwrap() > swapped);
+
+        let version = cache.version_of("key2").unwrap();
+        *cache.get_mut("key2").unwrap() += 1;
+        assert_eq!(cache.version_of("key2"), Some(version));
+        assert!(cache.bump_version("key2").unwrap() > version);
+
+        // a swap keeps the TTL of the entry, not the default one
+        cache.set_ttl(Some(Duration::ZERO));
+        cache
+            .insert_with_ttl("key4", 4, Duration::from_secs(60))
+            .unwrap();
+        let version = cache.version_of("key4").unwrap();
+        let swapped = cache.compare_and_swap("key4", version, 40).unwrap();
+        assert_eq!(cache.version_of("key4"), Some(swapped));
+        assert!(cache.ttl_of("key4").unwrap() > Duration::from_secs(59));
+        assert_eq!(cache.get("key4"), Some(&40));
+        assert_eq!(cache.bump_version("key3"), None);
+
+        cache.insert_with_ttl("key4", 4, Duration::ZERO).unwrap();
+        assert_eq!(cache.version_of("key4"), None);
+    }
+
+    #[test]
+    fn test_cursor_decode() {
+        let cursor = Cursor::<Vec<u8>>::decode("00ff10").unwrap();
+        assert_eq!(cursor.encode(), "00ff10");
+
+        assert!(Cursor::<String>::decode("abc").is_err());
+        assert!(Cursor::<String>::decode("zz").is_err());
+        assert!(Cursor::<String>::decode("ff").is_err());
+    }
+
+    #[derive(Debug)]
+    struct ValueWeigher;
+
+    impl Weigher<String, i32> for ValueWeigher {
+        fn weigh(&self, _key: &String, value: &i32) -> usize {
+            *value as usize
+        }
+    }
+
+    #[test]
+    fn test_cache_max_weight() {
+        let mut cache: Cache<String, i32> = Cache::new(10);
+        cache.set_weigher(ValueWeigher);
+        cache.set_max_weight(Some(10));
+
+        cache.insert("key1", 4).unwrap();
+        cache.insert("key2", 4).unwrap();
+        assert_eq!(cache.weight(), 8);
+
+        cache.get("key1");
+        cache.insert("key3", 5).unwrap();
+        assert_eq!(cache.get("key2"), None);
+        assert_eq!(cache.weight(), 9);
+
+        cache.insert("key3", 7).unwrap();
+        assert_eq!(cache.get("key1"), None);
+        assert_eq!(cache.get("key3"), Some(&7));
+        assert_eq!(cache.weight(), 7);
+
+        cache.remove("key3").unwrap();
+        assert_eq!(cache.weight(), 0);
+    }
+
+    #[test]
+    fn test_cache_default_weight_counts_entries() {
+        let mut cache: Cache<String, i32> = Cache::new(10);
+        cache.insert("key1", 1).unwrap();
+        cache.insert("key2", 2).unwrap();
+        assert_eq!(cache.weight(), 2);
+
+        cache.set_max_weight(Some(1));
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.get("key2"), Some(&2));
     }
 }
@@ -1,8 +1,28 @@
-use regex::Regex;
+pub mod coerce;
+mod display;
+pub mod explain;
+pub mod expression;
+pub mod function;
+pub mod parser;
+pub mod path;
+pub mod plan;
+pub mod query;
+
+use std::cmp::Ordering;
 use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
 use valu3::prelude::*;
 
-#[derive(ToValue, FromValue, Clone)]
+pub use coerce::Scalar;
+pub use explain::{Step, Trace};
+pub use expression::Expression;
+pub use function::Function;
+pub use parser::ParseError;
+pub use path::{Path, Resolved};
+pub use plan::{Document, LikePattern, Plan};
+pub use query::QueryError;
+
+#[derive(ToValue, FromValue, Clone, PartialEq, Debug)]
 pub enum Operator {
     Equal,
     NotEqual,
@@ -20,15 +40,74 @@ pub enum Operator {
     IsNotNull,
     Regex,
     NotRegex,
+    /// An array has an element equal to the right operand, or a string contains it.
+    Contains,
+    NotContains,
+    StartsWith,
+    NotStartsWith,
+    EndsWith,
+    NotEndsWith,
+    /// `Like`, ignoring case.
+    ILike,
+    NotILike,
+    /// The attribute is present, even if it is `NULL`.
+    Exists,
+    NotExists,
+    /// The number of elements of an array or object, or characters of a string, equals the
+    /// right operand.
+    SizeEq,
+    SizeNe,
+    SizeGt,
+    SizeLe,
+}
+
+impl Operator {
+    /// The operator matching exactly the values this one does not.
+    pub fn negate(&self) -> Self {
+        match self {
+            Operator::Equal => Operator::NotEqual,
+            Operator::NotEqual => Operator::Equal,
+            Operator::GreaterThan => Operator::LessThanOrEqual,
+            Operator::GreaterThanOrEqual => Operator::LessThan,
+            Operator::LessThan => Operator::GreaterThanOrEqual,
+            Operator::LessThanOrEqual => Operator::GreaterThan,
+            Operator::Like => Operator::NotLike,
+            Operator::NotLike => Operator::Like,
+            Operator::In => Operator::NotIn,
+            Operator::NotIn => Operator::In,
+            Operator::Between => Operator::NotBetween,
+            Operator::NotBetween => Operator::Between,
+            Operator::IsNull => Operator::IsNotNull,
+            Operator::IsNotNull => Operator::IsNull,
+            Operator::Regex => Operator::NotRegex,
+            Operator::NotRegex => Operator::Regex,
+            Operator::Contains => Operator::NotContains,
+            Operator::NotContains => Operator::Contains,
+            Operator::StartsWith => Operator::NotStartsWith,
+            Operator::NotStartsWith => Operator::StartsWith,
+            Operator::EndsWith => Operator::NotEndsWith,
+            Operator::NotEndsWith => Operator::EndsWith,
+            Operator::ILike => Operator::NotILike,
+            Operator::NotILike => Operator::ILike,
+            Operator::Exists => Operator::NotExists,
+            Operator::NotExists => Operator::Exists,
+            Operator::SizeEq => Operator::SizeNe,
+            Operator::SizeNe => Operator::SizeEq,
+            Operator::SizeGt => Operator::SizeLe,
+            Operator::SizeLe => Operator::SizeGt,
+        }
+    }
 }
 
-#[derive(ToValue, FromValue, Clone, PartialEq)]
+#[derive(ToValue, FromValue, Clone, PartialEq, Debug)]
 pub enum LogicalOperator {
     And,
     Or,
+    /// Negates the condition or group that follows it.
+    Not,
 }
 
-#[derive(Clone, FromValue, ToValue)]
+#[derive(Clone, FromValue, ToValue, PartialEq, Debug)]
 pub struct Condition {
     pub operator: Operator,
     pub left: Value,
@@ -49,7 +128,7 @@ impl Condition {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Debug)]
 pub enum ConditionToken {
     Condition(Condition),
     LogicalOperator(LogicalOperator),
@@ -65,6 +144,7 @@ impl FromValueBehavior for ConditionToken {
         match value.as_str() {
             "And" => Some(ConditionToken::LogicalOperator(LogicalOperator::And)),
             "Or" => Some(ConditionToken::LogicalOperator(LogicalOperator::Or)),
+            "Not" => Some(ConditionToken::LogicalOperator(LogicalOperator::Not)),
             _ => {
                 let condition = Condition::from_value(value)?;
                 Some(ConditionToken::Condition(condition))
@@ -83,11 +163,12 @@ impl ToValueBehavior for ConditionToken {
     }
 }
 
-#[derive(Clone, FromValue, ToValue)]
+#[derive(Clone, FromValue, ToValue, PartialEq, Debug)]
 pub struct ConditionGroup {
     pub conditions: Vec<ConditionToken>,
 }
 
+#[derive(Clone, PartialEq, Debug)]
 pub enum Clause {
     ConditionGroup(ConditionGroup),
     Condition(Condition),
@@ -99,8 +180,22 @@ pub enum Error {
     RightConditionNotFound,
     LeftConditionNotString,
     RightConditionNotString,
+    RightConditionNotNumber,
     ConditionVariableNotFound,
     BetweenConditionInvalid,
+    /// A group is empty, or its tokens do not alternate between operands and `And` or `Or`.
+    ConditionGroupInvalid,
+    InvalidRegex(String),
+    InvalidLikePattern(String),
+    /// Operands of the named types cannot be compared, see `Plan::strict`.
+    IncomparableTypes(&'static str, &'static str),
+    FunctionNotFound(String),
+    /// The text of an expression operand, see `expression`, does not parse.
+    InvalidExpression(String),
+    /// A function was called with arguments it does not accept.
+    FunctionInvalidArguments(String),
+    /// Values of the named types cannot be added or subtracted.
+    ArithmeticInvalid(&'static str, &'static str),
 }
 
 impl Display for Error {
@@ -110,8 +205,23 @@ impl Display for Error {
             Error::RightConditionNotFound => write!(f, "Right condition not found"),
             Error::LeftConditionNotString => write!(f, "Left condition not string"),
             Error::RightConditionNotString => write!(f, "Right condition not string"),
+            Error::RightConditionNotNumber => write!(f, "Right condition not number"),
             Error::ConditionVariableNotFound => write!(f, "Condition variable not found"),
             Error::BetweenConditionInvalid => write!(f, "Between condition invalid"),
+            Error::ConditionGroupInvalid => write!(f, "Condition group invalid"),
+            Error::InvalidRegex(err) => write!(f, "Invalid regex: {}", err),
+            Error::InvalidLikePattern(pattern) => write!(f, "Invalid like pattern: {}", pattern),
+            Error::IncomparableTypes(left, right) => {
+                write!(f, "Cannot compare {} with {}", left, right)
+            }
+            Error::FunctionNotFound(name) => write!(f, "Function not found: {}", name),
+            Error::InvalidExpression(err) => write!(f, "Invalid expression: {}", err),
+            Error::FunctionInvalidArguments(name) => {
+                write!(f, "Invalid arguments for function {}", name)
+            }
+            Error::ArithmeticInvalid(left, right) => {
+                write!(f, "Cannot add or subtract {} and {}", left, right)
+            }
         }
     }
 }
@@ -129,188 +239,77 @@ impl Clause {
         Self::ConditionGroup(ConditionGroup { conditions })
     }
 
-    pub fn execute(&self, value: &Value) -> Result<bool, Error> {
-        match self {
-            Clause::ConditionGroup(condition_group) => {
-                Self::execute_condition_group(condition_group, value)
-            }
-            Clause::Condition(condition) => Self::execute_condition(condition.clone(), value),
-        }
+    /// Parses the condition of a SQL `WHERE` clause, see `parser`.
+    pub fn parse(text: &str) -> Result<Self, ParseError> {
+        parser::parse(text)
     }
 
-    fn execute_condition_group(
-        condition_group: &ConditionGroup,
-        value: &Value,
-    ) -> Result<bool, Error> {
-        let mut result = false;
+    /// Evaluates the clause against `value`, tracing the result of every condition and group,
+    /// see `explain`. Only compiling the clause can fail, errors evaluating it are in the trace.
+    pub fn explain(&self, value: &Value) -> Result<Trace, Error> {
+        Ok(self.compile()?.explain(value))
+    }
 
-        for condition in &condition_group.conditions {
-            match condition {
-                ConditionToken::Condition(condition) => {
-                    result = Self::execute_condition(condition.clone(), value)?;
-                }
-                ConditionToken::LogicalOperator(operator) => match operator {
-                    LogicalOperator::And => {
-                        if !result {
-                            return Ok(false);
-                        } else {
-                            result = false;
-                        }
-                    }
-                    LogicalOperator::Or => {}
-                },
-                ConditionToken::ConditionGroup(condition_group) => {
-                    result = Self::execute_condition_group(condition_group, value)?;
-                }
-            }
-        }
+    /// Reads a JSON query document, such as `{"age": {"$gt": 30}}`, see `query`.
+    pub fn from_query(document: &Value) -> Result<Self, QueryError> {
+        query::from_query(document)
+    }
 
-        Ok(result)
+    /// Writes the clause as a JSON query document, see `query`.
+    pub fn to_query(&self) -> Result<Value, QueryError> {
+        query::to_query(self)
     }
 
-    pub fn execute_condition(condition: Condition, value: &Value) -> Result<bool, Error> {
-        let value_left = match Self::resolve_condition_variable(&condition.left.to_value(), &value)
-        {
-            Ok(val) => val,
-            Err(_) => return Err(Error::LeftConditionNotFound),
-        };
+    /// Checks the clause and prepares it to run against many values, see `plan`.
+    pub fn compile(&self) -> Result<Plan, Error> {
+        Plan::new(self)
+    }
 
-        let value_right =
-            match Self::resolve_condition_variable(&condition.right.to_value(), &value) {
-                Ok(val) => val,
-                Err(_) => return Err(Error::RightConditionNotFound),
-            };
+    /// Whether `value` matches the clause. An unknown result, see `evaluate`, does not match.
+    ///
+    /// The clause is compiled on every call, prefer `compile` to run it more than once.
+    pub fn execute(&self, value: &Value) -> Result<bool, Error> {
+        Ok(self.evaluate(value)? == Some(true))
+    }
 
-        match condition.operator {
-            Operator::Equal => {
-                if value_left.eq(&value_right) {
-                    Ok(true)
-                } else {
-                    Ok(false)
-                }
-            }
-            Operator::NotEqual => {
-                if value_left.ne(&value_right) {
-                    Ok(true)
-                } else {
-                    Ok(false)
-                }
-            }
-            Operator::GreaterThan => {
-                if value_left.gt(&value_right) {
-                    Ok(true)
-                } else {
-                    Ok(false)
-                }
-            }
-            Operator::GreaterThanOrEqual => {
-                if value_left.ge(&value_right) {
-                    Ok(true)
-                } else {
-                    Ok(false)
-                }
-            }
-            Operator::LessThan => {
-                if value_left.lt(&value_right) {
-                    Ok(true)
-                } else {
-                    Ok(false)
-                }
-            }
-            Operator::LessThanOrEqual => {
-                if value_left.le(&value_right) {
-                    Ok(true)
-                } else {
-                    Ok(false)
-                }
-            }
-            Operator::Like => {
-                if Self::operator_like(&value_left, &value_right)? {
-                    Ok(true)
-                } else {
-                    Ok(false)
-                }
-            }
-            Operator::NotLike => {
-                if !Self::operator_like(&value_left, &value_right)? {
-                    Ok(true)
-                } else {
-                    Ok(false)
-                }
-            }
-            Operator::In => {
-                if Self::operator_in(&value_left, &value_right)? {
-                    Ok(true)
-                } else {
-                    Ok(false)
-                }
-            }
-            Operator::NotIn => {
-                if !Self::operator_in(&value_left, &value_right)? {
-                    Ok(true)
-                } else {
-                    Ok(false)
-                }
-            }
-            Operator::Between => {
-                if Self::operator_between(&value_left, &value_right)? {
-                    Ok(true)
-                } else {
-                    Ok(false)
-                }
-            }
-            Operator::NotBetween => {
-                if !Self::operator_between(&value_left, &value_right)? {
-                    Ok(true)
-                } else {
-                    Ok(false)
-                }
-            }
-            Operator::IsNull => {
-                if value_left.is_null() {
-                    Ok(true)
-                } else {
-                    Ok(false)
-                }
-            }
-            Operator::IsNotNull => {
-                if !value_left.is_null() {
-                    Ok(true)
-                } else {
-                    Ok(false)
-                }
-            }
-            Operator::Regex => {
-                if Self::operator_regex(&value_left, &value_right)? {
-                    Ok(true)
-                } else {
-                    Ok(false)
-                }
-            }
-            Operator::NotRegex => {
-                if !Self::operator_regex(&value_left, &value_right)? {
-                    Ok(true)
-                } else {
-                    Ok(false)
-                }
-            }
-            _ => Ok(false),
-        }
+    /// Evaluates the clause with SQL three-valued logic: a comparison with `NULL` is unknown,
+    /// `None`, and so is `NOT` unknown. `AND` is false when an operand is false, and `OR` true
+    /// when an operand is true, whatever the others are.
+    ///
+    /// `Not` binds tighter than `And`, which binds tighter than `Or`. Operands are evaluated
+    /// from left to right until the result is known, so the remaining ones cannot fail.
+    pub fn evaluate(&self, value: &Value) -> Result<Option<bool>, Error> {
+        self.compile()?.evaluate(value)
+    }
+
+    pub fn execute_condition(condition: Condition, value: &Value) -> Result<bool, Error> {
+        Ok(Self::evaluate_condition(&condition, value)? == Some(true))
+    }
+
+    /// Evaluates a condition, `None` when it compares with `NULL`. `IsNull` and `IsNotNull`
+    /// are always known, and `In` is only unknown when no value matches and one is `NULL`.
+    pub fn evaluate_condition(condition: &Condition, value: &Value) -> Result<Option<bool>, Error> {
+        Plan::condition(condition)?.evaluate(value)
     }
 
+    /// Resolves a path, see `path`, to the value found, with the values found through `[*]`
+    /// or `[all]` in an array.
     pub fn resolve_condition_variable(variable: &Value, value: &Value) -> Result<Value, Error> {
         if variable.is_string() {
             match Self::extract_sql_string(&variable.as_string()) {
                 Some(val) => Ok(Value::from(val)),
                 None => {
                     let variable_str = variable.as_str();
-
-                    match value.get(variable_str) {
-                        Some(val) => Ok(val.clone()),
-                        None => match Value::try_from(variable_str) {
-                            Ok(val) => Ok(val),
-                            Err(_) => Err(Error::ConditionVariableNotFound),
-                        },
+                    let path = Path::new(variable_str);
+
+                    match path.resolve(value) {
+                        Resolved::Missing if path.is_attribute() => {
+                            match Value::try_from(variable_str) {
+                                Ok(val) => Ok(val),
+                                Err(_) => Err(Error::ConditionVariableNotFound),
+                            }
+                        }
+                        resolved => Ok(resolved.to_value()),
                     }
                 }
             }
@@ -326,52 +325,14 @@ impl Clause {
         let last_char = chars.next_back();
 
         if first_char == last_char && (first_char == Some('\'') || first_char == Some('"')) {
-            let mut result = String::new();
-            for c in value.chars().skip(1).take(value.len() - 2) {
-                result.push(c);
-            }
-            Some(result)
+            // both quotes are one byte long
+            Some(value[1..value.len() - 1].to_string())
         } else {
             None
         }
     }
 
     pub fn operator_like(value_left: &Value, value_right: &Value) -> Result<bool, Error> {
-        let left = match value_left.as_string_b() {
-            Some(val) => val.as_string(),
-            None => return Err(Error::LeftConditionNotString),
-        };
-        let mut right = match value_right.as_string_b() {
-            Some(val) => val.as_string(),
-            None => return Err(Error::RightConditionNotString),
-        };
-
-        // no use regex
-        if right.starts_with('%') && right.ends_with('%') {
-            right = right.trim_matches('%').to_string();
-            if left.contains(&right) {
-                return Ok(true);
-            }
-        } else if right.starts_with('%') {
-            right = right.trim_start_matches('%').to_string();
-            if left.ends_with(&right) {
-                return Ok(true);
-            }
-        } else if right.ends_with('%') {
-            right = right.trim_end_matches('%').to_string();
-            if left.starts_with(&right) {
-                return Ok(true);
-            }
-        } else {
-            if left.eq(&right) {
-                return Ok(true);
-            }
-        }
-
-        Ok(false)
-    }
-
-    fn operator_regex(value_left: &Value, value_right: &Value) -> Result<bool, Error> {
         let left = match value_left.as_string_b() {
             Some(val) => val.as_string(),
             None => return Err(Error::LeftConditionNotString),
@@ -381,18 +342,15 @@ impl Clause {
             None => return Err(Error::RightConditionNotString),
         };
 
-        let re = Regex::new(&right).unwrap();
-        if re.is_match(&left) {
-            return Ok(true);
-        }
-
-        Ok(false)
+        Ok(LikePattern::new(&right)?.is_match(&left))
     }
 
+    /// Whether `value_left` equals a value of the array `value_right`, see `coerce`. `NULL`
+    /// values and values that cannot be compared with `value_left` are skipped.
     pub fn operator_in(value_left: &Value, value_right: &Value) -> Result<bool, Error> {
-        let left = match value_left.as_string_b() {
-            Some(val) => val.as_string(),
-            None => return Err(Error::LeftConditionNotString),
+        let left = match Scalar::new(value_left) {
+            Some(val) => val,
+            None => return Ok(false),
         };
         let right = match value_right.as_array() {
             Some(val) => val,
@@ -400,11 +358,11 @@ impl Clause {
         };
 
         for value in right {
-            let value = match value.as_string_b() {
-                Some(val) => val.as_string(),
-                None => return Err(Error::RightConditionNotString),
+            let value = match Scalar::new(value) {
+                Some(val) => val,
+                None => continue,
             };
-            if left.eq(&value) {
+            if left.equals(&value) == Some(true) {
                 return Ok(true);
             }
         }
@@ -427,14 +385,30 @@ impl Clause {
             None => return Err(Error::BetweenConditionInvalid),
         };
 
-        if value_left.ge(&value1) && value_left.le(&value2) {
-            Ok(true)
-        } else {
-            Ok(false)
+        let (left, low, high) = match (
+            Scalar::new(value_left),
+            Scalar::new(value1),
+            Scalar::new(value2),
+        ) {
+            (Some(left), Some(low), Some(high)) => (left, low, high),
+            _ => return Ok(false),
+        };
+
+        match (left.compare(&low), left.compare(&high)) {
+            (Some(from), Some(to)) => Ok(from != Ordering::Less && to != Ordering::Greater),
+            _ => Ok(false),
         }
     }
 }
 
+impl FromStr for Clause {
+    type Err = ParseError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        parser::parse(text)
+    }
+}
+
 #[macro_export]
 macro_rules! sql_string {
     ($string:expr) => {
@@ -444,9 +418,32 @@ macro_rules! sql_string {
 
 #[cfg(test)]
 mod tests {
-    use super::{Clause, Condition, ConditionGroup, ConditionToken, LogicalOperator, Operator};
+    use super::{
+        Clause, Condition, ConditionGroup, ConditionToken, Error, LogicalOperator, Operator,
+    };
     use valu3::prelude::*;
 
+    const T: Option<bool> = Some(true);
+    const F: Option<bool> = Some(false);
+    const U: Option<bool> = None;
+
+    fn equal(left: &str, right: i32) -> ConditionToken {
+        ConditionToken::Condition(Condition::new(Operator::Equal, left, right))
+    }
+
+    fn logical(operator: LogicalOperator) -> ConditionToken {
+        ConditionToken::LogicalOperator(operator)
+    }
+
+    // A value where the condition `name = 1` is `truth`.
+    fn truth_value(truth: Option<bool>) -> Value {
+        match truth {
+            Some(true) => Value::from(1),
+            Some(false) => Value::from(0),
+            None => Value::Null,
+        }
+    }
+
     // Clause: name = 'John'
     #[test]
     fn test_condition_equal() {
@@ -671,6 +668,126 @@ mod tests {
         assert!(!result);
     }
 
+    // Clause: tags CONTAINS 'b', name CONTAINS 'oh', tags NOT CONTAINS 'c'
+    #[test]
+    fn test_condition_contains() {
+        let value = Value::from(vec![
+            ("name", Value::from("John")),
+            ("tags", Value::from(vec!["a", "b"])),
+        ]);
+
+        let contains = |left: &str, operator: Operator, right: &str| {
+            Clause::condition(operator, left, sql_string!(right))
+                .execute(&value)
+                .unwrap()
+        };
+
+        assert!(contains("tags", Operator::Contains, "b"));
+        assert!(contains("name", Operator::Contains, "oh"));
+        assert!(contains("tags", Operator::NotContains, "c"));
+        assert!(!contains("name", Operator::Contains, "x"));
+    }
+
+    // Clause: name STARTS WITH 'Jo', name ENDS WITH 'hn', name ILIKE 'j%N'
+    #[test]
+    fn test_condition_starts_ends_with_and_ilike() {
+        let value = Value::from(vec![("name", "John")]);
+
+        let execute = |operator: Operator, right: &str| {
+            Clause::condition(operator, "name", sql_string!(right))
+                .execute(&value)
+                .unwrap()
+        };
+
+        assert!(execute(Operator::StartsWith, "Jo"));
+        assert!(!execute(Operator::StartsWith, "jo"));
+        assert!(execute(Operator::NotStartsWith, "hn"));
+        assert!(execute(Operator::EndsWith, "hn"));
+        assert!(execute(Operator::NotEndsWith, "Jo"));
+        assert!(execute(Operator::ILike, "j%N"));
+        assert!(!execute(Operator::Like, "j%N"));
+        assert!(execute(Operator::NotILike, "j_"));
+    }
+
+    // Clause: email EXISTS, email IS NULL, phone NOT EXISTS
+    #[test]
+    fn test_condition_exists() {
+        let value = Value::from(vec![("email", Value::Null)]);
+
+        let execute = |operator: Operator, left: &str| {
+            Clause::condition(operator, left, Value::Null)
+                .execute(&value)
+                .unwrap()
+        };
+
+        assert!(execute(Operator::Exists, "email"));
+        assert!(execute(Operator::IsNull, "email"));
+        assert!(!execute(Operator::Exists, "phone"));
+        assert!(execute(Operator::NotExists, "phone"));
+        assert!(!execute(Operator::NotExists, "email"));
+    }
+
+    // Clause: SIZE(tags) = 2, SIZE(name) > 3, SIZE(address) <= 1, SIZE(age) = 1
+    #[test]
+    fn test_condition_size() {
+        let value = Value::from(vec![
+            ("name", Value::from("João")),
+            ("tags", Value::from(vec!["a", "b"])),
+            ("address", Value::from(vec![("city", "Lisbon")])),
+            ("age", Value::from(18)),
+        ]);
+
+        let evaluate = |operator: Operator, left: &str, right: i32| {
+            Clause::condition(operator, left, right)
+                .evaluate(&value)
+                .unwrap()
+        };
+
+        assert_eq!(evaluate(Operator::SizeEq, "tags", 2), T);
+        assert_eq!(evaluate(Operator::SizeNe, "tags", 2), F);
+        assert_eq!(evaluate(Operator::SizeGt, "name", 3), T);
+        assert_eq!(evaluate(Operator::SizeLe, "address", 1), T);
+        assert_eq!(evaluate(Operator::SizeGt, "address", 1), F);
+        assert_eq!(evaluate(Operator::SizeEq, "age", 1), U);
+
+        let clause = Clause::condition(Operator::SizeEq, "tags", sql_string!("2"));
+        assert!(matches!(
+            clause.execute(&value),
+            Err(Error::RightConditionNotNumber)
+        ));
+    }
+
+    #[test]
+    fn test_operator_round_trip() {
+        let operators = [
+            Operator::Contains,
+            Operator::NotContains,
+            Operator::StartsWith,
+            Operator::NotStartsWith,
+            Operator::EndsWith,
+            Operator::NotEndsWith,
+            Operator::ILike,
+            Operator::NotILike,
+            Operator::Exists,
+            Operator::NotExists,
+            Operator::SizeEq,
+            Operator::SizeNe,
+            Operator::SizeGt,
+            Operator::SizeLe,
+        ];
+
+        for operator in operators {
+            assert_eq!(
+                Operator::from_value(operator.to_value()),
+                Some(operator.clone())
+            );
+            assert_eq!(operator.negate().negate(), operator);
+
+            let condition = Condition::new(operator, "tags", sql_string!("a"));
+            assert_eq!(Condition::from_value(condition.to_value()), Some(condition));
+        }
+    }
+
     // Clause: ((name = 'John' AND age = 18) OR name NOT REGEX 'A.*') AND birth_date BETWEEN '1980-01-01' AND '1990-01-01'
     #[test]
     fn test_condition_complex() {
@@ -760,5 +877,195 @@ mod tests {
         };
 
         assert!(!result); // name is not John and name is start with A.*
-    } 
+    }
+
+    // Clause: p = 1 AND q = 1, p = 1 OR q = 1
+    #[test]
+    fn test_truth_table_and_or() {
+        let and = Clause::group(vec![
+            equal("p", 1),
+            logical(LogicalOperator::And),
+            equal("q", 1),
+        ]);
+        let or = Clause::group(vec![
+            equal("p", 1),
+            logical(LogicalOperator::Or),
+            equal("q", 1),
+        ]);
+
+        // p, q, p AND q, p OR q
+        let table = [
+            (T, T, T, T),
+            (T, F, F, T),
+            (T, U, U, T),
+            (F, T, F, T),
+            (F, F, F, F),
+            (F, U, F, U),
+            (U, T, U, T),
+            (U, F, F, U),
+            (U, U, U, U),
+        ];
+
+        for (p, q, expected_and, expected_or) in table {
+            let value = Value::from(vec![("p", truth_value(p)), ("q", truth_value(q))]);
+
+            assert_eq!(
+                and.evaluate(&value).unwrap(),
+                expected_and,
+                "{:?} AND {:?}",
+                p,
+                q
+            );
+            assert_eq!(
+                or.evaluate(&value).unwrap(),
+                expected_or,
+                "{:?} OR {:?}",
+                p,
+                q
+            );
+            assert_eq!(and.execute(&value).unwrap(), expected_and == T);
+            assert_eq!(or.execute(&value).unwrap(), expected_or == T);
+        }
+    }
+
+    // Clause: NOT p = 1, NOT (p = 1 AND q = 1), NOT NOT p = 1
+    #[test]
+    fn test_truth_table_not() {
+        let not = Clause::group(vec![logical(LogicalOperator::Not), equal("p", 1)]);
+        let not_group = Clause::group(vec![
+            logical(LogicalOperator::Not),
+            ConditionToken::ConditionGroup(ConditionGroup {
+                conditions: vec![equal("p", 1), logical(LogicalOperator::And), equal("q", 1)],
+            }),
+        ]);
+        let not_not = Clause::group(vec![
+            logical(LogicalOperator::Not),
+            logical(LogicalOperator::Not),
+            equal("p", 1),
+        ]);
+
+        // p, NOT p
+        for (p, expected) in [(T, F), (F, T), (U, U)] {
+            let value = Value::from(vec![("p", truth_value(p)), ("q", truth_value(T))]);
+
+            assert_eq!(not.evaluate(&value).unwrap(), expected, "NOT {:?}", p);
+            assert_eq!(
+                not_group.evaluate(&value).unwrap(),
+                expected,
+                "NOT ({:?} AND T)",
+                p
+            );
+            assert_eq!(not_not.evaluate(&value).unwrap(), p, "NOT NOT {:?}", p);
+        }
+    }
+
+    // Clause: a = 1 OR b = 1 AND c = 1, NOT a = 1 AND b = 1
+    #[test]
+    fn test_condition_precedence() {
+        let clause = Clause::group(vec![
+            equal("a", 1),
+            logical(LogicalOperator::Or),
+            equal("b", 1),
+            logical(LogicalOperator::And),
+            equal("c", 1),
+        ]);
+        let not = Clause::group(vec![
+            logical(LogicalOperator::Not),
+            equal("a", 1),
+            logical(LogicalOperator::And),
+            equal("b", 1),
+        ]);
+
+        // a, b, c, a OR b AND c, NOT a AND b
+        let table = [
+            (1, 1, 0, true, false),
+            (0, 1, 1, true, true),
+            (0, 1, 0, false, true),
+            (1, 0, 0, true, false),
+            (0, 0, 1, false, false),
+        ];
+
+        for (a, b, c, expected, expected_not) in table {
+            let value = Value::from(vec![("a", a), ("b", b), ("c", c)]);
+
+            assert_eq!(clause.execute(&value).unwrap(), expected);
+            assert_eq!(not.execute(&value).unwrap(), expected_not);
+        }
+    }
+
+    // Clause: age = 18 OR age LIKE 'J%', age = 18 AND age LIKE 'J%'
+    #[test]
+    fn test_condition_short_circuit() {
+        let like =
+            ConditionToken::Condition(Condition::new(Operator::Like, "age", sql_string!("J%")));
+        let or = Clause::group(vec![
+            equal("age", 18),
+            logical(LogicalOperator::Or),
+            like.clone(),
+        ]);
+        let and = Clause::group(vec![equal("age", 18), logical(LogicalOperator::And), like]);
+
+        let value = Value::from(vec![("age", 18)]);
+        assert!(or.execute(&value).unwrap());
+        assert!(matches!(
+            and.execute(&value),
+            Err(Error::LeftConditionNotString)
+        ));
+
+        let value = Value::from(vec![("age", 19)]);
+        assert!(matches!(
+            or.execute(&value),
+            Err(Error::LeftConditionNotString)
+        ));
+        assert!(!and.execute(&value).unwrap());
+    }
+
+    // Clause: age <> 18, age IS NULL, name IN ('Jane', NULL), name IN ('John', NULL)
+    #[test]
+    fn test_condition_null_comparisons() {
+        let value = Value::from(vec![("age", Value::Null), ("name", Value::from("John"))]);
+
+        let clause = Clause::condition(Operator::NotEqual, "age", 18);
+        assert_eq!(clause.evaluate(&value).unwrap(), U);
+        assert!(!clause.execute(&value).unwrap());
+
+        let clause = Clause::condition(Operator::Equal, "name", Value::Null);
+        assert_eq!(clause.evaluate(&value).unwrap(), U);
+
+        let clause = Clause::condition(Operator::IsNull, "age", Value::Null);
+        assert_eq!(clause.evaluate(&value).unwrap(), T);
+
+        let in_list = |operator: Operator, name: &str| {
+            Clause::condition(
+                operator,
+                "name",
+                vec![Value::from(name), Value::Null].to_value(),
+            )
+            .evaluate(&value)
+            .unwrap()
+        };
+        assert_eq!(in_list(Operator::In, "Jane"), U);
+        assert_eq!(in_list(Operator::In, "John"), T);
+        assert_eq!(in_list(Operator::NotIn, "Jane"), U);
+        assert_eq!(in_list(Operator::NotIn, "John"), F);
+    }
+
+    #[test]
+    fn test_condition_group_invalid() {
+        let value = Value::from(vec![("a", 1)]);
+        let groups = [
+            vec![],
+            vec![equal("a", 1), equal("a", 1)],
+            vec![equal("a", 1), logical(LogicalOperator::And)],
+            vec![logical(LogicalOperator::Or), equal("a", 1)],
+            vec![equal("a", 1), logical(LogicalOperator::Not)],
+        ];
+
+        for conditions in groups {
+            assert!(matches!(
+                Clause::group(conditions).execute(&value),
+                Err(Error::ConditionGroupInvalid)
+            ));
+        }
+    }
 }
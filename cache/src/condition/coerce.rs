@@ -0,0 +1,332 @@
+//! How condition operators compare values of different types.
+//!
+//! Both operands are first read as a `Scalar`, where a string holding an ISO 8601 date, such
+//! as `2020-01-01` or `2020-01-01T12:30:00.000Z`, is a date. Then:
+//!
+//! | left \ right | number         | string           | date           | boolean          |
+//! |--------------|----------------|------------------|----------------|------------------|
+//! | number       | numeric        | numeric, if the string is a number | numeric, the number being epoch milliseconds | - |
+//! | string       | numeric, if the string is a number | text | text   | boolean, if the string is `true` or `false` |
+//! | date         | numeric        | text             | instant        | -                |
+//! | boolean      | -              | boolean, if the string is `true` or `false` | - | `false < true` |
+//!
+//! Integers and floats are both numbers, so `20 = 20.0`, and `"20" = 20` too. Dates without
+//! an offset are in UTC, so `2020-01-01` equals `2020-01-01T00:00:00Z`. Arrays and objects
+//! only equal arrays and objects with the same contents, and cannot be ordered.
+//!
+//! Operands of other pairs of types are incomparable: unless the plan is strict, see
+//! `Plan::strict`, `=` and ordering operators are false on them, `<>` true, and `IN` skips the
+//! values it cannot compare. A strict plan fails with `Error::IncomparableTypes` instead.
+//! `NULL` operands are handled before coercion, see `Clause::evaluate`.
+
+use std::cmp::Ordering;
+
+use valu3::prelude::*;
+
+/// A non-null operand, read for comparison.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Scalar {
+    Boolean(bool),
+    Number(f64),
+    String(String),
+    /// A date string, with its instant in milliseconds since the Unix epoch.
+    Date(i64, String),
+    /// Arrays, objects and any other value.
+    Other(Value),
+}
+
+impl Scalar {
+    /// `None` for `NULL`.
+    pub fn new(value: &Value) -> Option<Self> {
+        if value.is_null() {
+            return None;
+        }
+
+        let scalar = match value {
+            Value::Boolean(_) => bool::from_value(value.clone()).map(Scalar::Boolean),
+            Value::Number(_) => f64::from_value(value.clone()).map(Scalar::Number),
+            Value::String(_) => {
+                let string = value.as_string();
+                Some(match parse_date(&string) {
+                    Some(instant) => Scalar::Date(instant, string),
+                    None => Scalar::String(string),
+                })
+            }
+            _ => None,
+        };
+
+        Some(scalar.unwrap_or_else(|| Scalar::Other(value.clone())))
+    }
+
+    /// The name of the type of the scalar, for errors.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Scalar::Boolean(_) => "boolean",
+            Scalar::Number(_) => "number",
+            Scalar::String(_) => "string",
+            Scalar::Date(_, _) => "date",
+            Scalar::Other(value) if value.is_array() => "array",
+            Scalar::Other(value) if value.is_object() => "object",
+            Scalar::Other(_) => "value",
+        }
+    }
+
+    /// Orders two scalars, `None` when they are incomparable.
+    pub fn compare(&self, other: &Scalar) -> Option<Ordering> {
+        match (self, other) {
+            (Scalar::Number(left), Scalar::Number(right)) => left.partial_cmp(right),
+            (Scalar::Date(left, _), Scalar::Date(right, _)) => Some(left.cmp(right)),
+            (Scalar::Number(left), Scalar::Date(right, _)) => left.partial_cmp(&(*right as f64)),
+            (Scalar::Date(left, _), Scalar::Number(right)) => (*left as f64).partial_cmp(right),
+            (Scalar::String(left), Scalar::String(right))
+            | (Scalar::String(left), Scalar::Date(_, right))
+            | (Scalar::Date(_, left), Scalar::String(right)) => Some(left.cmp(right)),
+            (Scalar::Number(left), Scalar::String(right)) => {
+                left.partial_cmp(&parse_number(right)?)
+            }
+            (Scalar::String(left), Scalar::Number(right)) => parse_number(left)?.partial_cmp(right),
+            (Scalar::Boolean(left), Scalar::Boolean(right)) => Some(left.cmp(right)),
+            (Scalar::Boolean(left), Scalar::String(right)) => Some(left.cmp(&parse_bool(right)?)),
+            (Scalar::String(left), Scalar::Boolean(right)) => Some(parse_bool(left)?.cmp(right)),
+            (Scalar::Other(left), Scalar::Other(right)) if left == right => Some(Ordering::Equal),
+            _ => None,
+        }
+    }
+
+    /// Whether two scalars are equal, `None` when they are incomparable.
+    pub fn equals(&self, other: &Scalar) -> Option<bool> {
+        match (self, other) {
+            (Scalar::Other(left), Scalar::Other(right))
+                if left.is_array() == right.is_array() && left.is_object() == right.is_object() =>
+            {
+                Some(left == right)
+            }
+            _ => self
+                .compare(other)
+                .map(|ordering| ordering == Ordering::Equal),
+        }
+    }
+}
+
+fn parse_number(text: &str) -> Option<f64> {
+    text.trim().parse().ok()
+}
+
+fn parse_bool(text: &str) -> Option<bool> {
+    if text.eq_ignore_ascii_case("true") {
+        Some(true)
+    } else if text.eq_ignore_ascii_case("false") {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+/// Days from 1970-01-01 to the date, in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146097 + day_of_era - 719468
+}
+
+/// The year, month and day of a number of days from 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = if days >= 0 { days } else { days - 146096 } / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Parses an ISO 8601 date, `YYYY-MM-DD`, optionally followed by `T` or a space and a time,
+/// `HH:MM`, `HH:MM:SS` or `HH:MM:SS.fff`, and an offset, `Z` or `+HH:MM`. Returns its instant
+/// in milliseconds since the Unix epoch.
+pub fn parse_date(text: &str) -> Option<i64> {
+    fn number(text: &str, range: std::ops::Range<usize>) -> Option<i64> {
+        let digits = text.get(range)?;
+        if digits.bytes().all(|byte| byte.is_ascii_digit()) {
+            digits.parse().ok()
+        } else {
+            None
+        }
+    }
+
+    let (year, month, day) = (
+        number(text, 0..4)?,
+        number(text, 5..7)?,
+        number(text, 8..10)?,
+    );
+    if text.get(4..5)? != "-" || text.get(7..8)? != "-" {
+        return None;
+    }
+    if !(1..=12).contains(&month) || !(1..=days_in_month(year, month)).contains(&day) {
+        return None;
+    }
+
+    let mut millis = days_from_civil(year, month, day) * 86_400_000;
+    let mut rest = &text[10..];
+
+    if let Some(time) = rest.strip_prefix('T').or_else(|| rest.strip_prefix(' ')) {
+        let (hour, minute) = (number(time, 0..2)?, number(time, 3..5)?);
+        if time.get(2..3)? != ":" || hour > 23 || minute > 59 {
+            return None;
+        }
+        millis += (hour * 60 + minute) * 60_000;
+        rest = &time[5..];
+
+        if let Some(seconds) = rest.strip_prefix(':') {
+            let second = number(seconds, 0..2)?;
+            if second > 59 {
+                return None;
+            }
+            millis += second * 1000;
+            rest = &seconds[2..];
+
+            if let Some(fraction) = rest.strip_prefix('.') {
+                let digits = fraction.bytes().take_while(u8::is_ascii_digit).count();
+                if digits == 0 {
+                    return None;
+                }
+                let padded = format!("{:0<3}", &fraction[..digits.min(3)]);
+                millis += padded.parse::<i64>().ok()?;
+                rest = &fraction[digits..];
+            }
+        }
+
+        if rest == "Z" {
+            rest = "";
+        } else if let Some(sign) = rest.chars().next().filter(|c| *c == '+' || *c == '-') {
+            let offset = rest[1..].replace(':', "");
+            if offset.len() != 4 {
+                return None;
+            }
+            let (hours, minutes) = (number(&offset, 0..2)?, number(&offset, 2..4)?);
+            let offset = (hours * 60 + minutes) * 60_000;
+            millis += if sign == '+' { -offset } else { offset };
+            rest = "";
+        }
+    }
+
+    if rest.is_empty() {
+        Some(millis)
+    } else {
+        None
+    }
+}
+
+/// Writes an instant, in milliseconds since the Unix epoch, as an ISO 8601 date in UTC,
+/// `YYYY-MM-DDTHH:MM:SS.fffZ`.
+pub fn format_date(millis: i64) -> String {
+    let (year, month, day) = civil_from_days(millis.div_euclid(86_400_000));
+    let time = millis.rem_euclid(86_400_000);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        time / 3_600_000,
+        time / 60_000 % 60,
+        time / 1000 % 60,
+        time % 1000
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scalar<T: Into<Value>>(value: T) -> Scalar {
+        Scalar::new(&value.into()).unwrap()
+    }
+
+    #[test]
+    fn test_parse_date() {
+        assert_eq!(parse_date("1970-01-01"), Some(0));
+        assert_eq!(parse_date("1970-01-02T00:00:00Z"), Some(86_400_000));
+        assert_eq!(
+            parse_date("2020-01-01T00:00:00.000Z"),
+            Some(1_577_836_800_000)
+        );
+        assert_eq!(
+            parse_date("2020-01-01 01:30+01:30"),
+            Some(1_577_836_800_000)
+        );
+        assert_eq!(
+            parse_date("2019-12-31T23:00:00-01:00"),
+            Some(1_577_836_800_000)
+        );
+        assert_eq!(parse_date("2020-02-29T12:00:00.5"), Some(1_582_977_600_500));
+        assert_eq!(parse_date("1969-12-31T23:59:59.999Z"), Some(-1));
+
+        assert_eq!(format_date(0), "1970-01-01T00:00:00.000Z");
+        assert_eq!(format_date(-1), "1969-12-31T23:59:59.999Z");
+        assert_eq!(format_date(1_582_977_600_500), "2020-02-29T12:00:00.500Z");
+        for millis in [951_782_400_000, 4_107_542_400_123, -62_135_596_800_000] {
+            assert_eq!(parse_date(&format_date(millis)), Some(millis));
+        }
+
+        for text in [
+            "2019-02-29",
+            "2020-13-01",
+            "2020-1-01",
+            "2020-01-01T24:00",
+            "2020-01-01Z",
+        ] {
+            assert_eq!(parse_date(text), None, "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_coercion_matrix() {
+        let date = scalar("2020-01-01T00:00:00Z");
+
+        assert_eq!(scalar(20).equals(&scalar(20.0)), Some(true));
+        assert_eq!(scalar("20").equals(&scalar(20)), Some(true));
+        assert_eq!(scalar(3).compare(&scalar("20")), Some(Ordering::Less));
+        assert_eq!(scalar("3").compare(&scalar("20")), Some(Ordering::Greater));
+        assert_eq!(date.equals(&scalar("2020-01-01")), Some(true));
+        assert_eq!(
+            date.compare(&scalar("2020-01-01T00:00:00.001Z")),
+            Some(Ordering::Less)
+        );
+        assert_eq!(date.equals(&scalar(1_577_836_800_000_i64)), Some(true));
+        assert_eq!(date.compare(&scalar("2020-01-02x")), Some(Ordering::Less));
+        assert_eq!(
+            scalar(true).compare(&scalar(false)),
+            Some(Ordering::Greater)
+        );
+        assert_eq!(scalar(true).equals(&scalar("TRUE")), Some(true));
+        assert_eq!(scalar(vec![1, 2]).equals(&scalar(vec![1, 2])), Some(true));
+        assert_eq!(scalar(vec![1, 2]).equals(&scalar(vec![2])), Some(false));
+
+        assert_eq!(scalar(vec![1, 2]).compare(&scalar(vec![2])), None);
+        assert_eq!(scalar(1).equals(&scalar(true)), None);
+        assert_eq!(scalar(1).equals(&scalar("one")), None);
+        assert_eq!(scalar("yes").equals(&scalar(true)), None);
+        assert_eq!(scalar(vec![1]).equals(&scalar(1)), None);
+        assert_eq!(scalar(vec![1]).kind(), "array");
+        assert_eq!(date.kind(), "date");
+        assert_eq!(Scalar::new(&Value::Null), None);
+    }
+}
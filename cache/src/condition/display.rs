@@ -0,0 +1,243 @@
+//! Writing a `Clause` as the text of a SQL `WHERE` clause, which the parser, see `parser`,
+//! reads back as the same clause.
+//!
+//! ```text
+//! (status = 'a' OR status = 'b') AND age > 30
+//! ```
+//!
+//! Groups nested in a group are written between parentheses. The alternate form, `{:#}`,
+//! writes every operand of `AND` and `OR` on a line of its own, and indents nested groups:
+//!
+//! ```text
+//! (
+//!     status = 'a'
+//!     OR status = 'b'
+//! )
+//! AND age > 30
+//! ```
+
+use std::fmt::{self, Display, Formatter};
+
+use valu3::prelude::*;
+
+use super::expression::{marked_operand, Expression, ATTRIBUTE, EXPRESSION};
+use super::{Clause, Condition, ConditionGroup, ConditionToken, LogicalOperator, Operator};
+
+const INDENT: &str = "    ";
+
+impl Display for Operator {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let keyword = match self {
+            Operator::Equal | Operator::SizeEq => "=",
+            Operator::NotEqual | Operator::SizeNe => "<>",
+            Operator::GreaterThan | Operator::SizeGt => ">",
+            Operator::GreaterThanOrEqual => ">=",
+            Operator::LessThan => "<",
+            Operator::LessThanOrEqual | Operator::SizeLe => "<=",
+            Operator::IsNull => "IS NULL",
+            Operator::IsNotNull => "IS NOT NULL",
+            Operator::In => "IN",
+            Operator::NotIn => "NOT IN",
+            Operator::Between => "BETWEEN",
+            Operator::NotBetween => "NOT BETWEEN",
+            Operator::Like => "LIKE",
+            Operator::NotLike => "NOT LIKE",
+            Operator::ILike => "ILIKE",
+            Operator::NotILike => "NOT ILIKE",
+            Operator::Regex => "REGEX",
+            Operator::NotRegex => "NOT REGEX",
+            Operator::Contains => "CONTAINS",
+            Operator::NotContains => "NOT CONTAINS",
+            Operator::StartsWith => "STARTS WITH",
+            Operator::NotStartsWith => "NOT STARTS WITH",
+            Operator::EndsWith => "ENDS WITH",
+            Operator::NotEndsWith => "NOT ENDS WITH",
+            Operator::Exists => "EXISTS",
+            Operator::NotExists => "NOT EXISTS",
+        };
+
+        write!(f, "{}", keyword)
+    }
+}
+
+impl Display for LogicalOperator {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            LogicalOperator::And => write!(f, "AND"),
+            LogicalOperator::Or => write!(f, "OR"),
+            LogicalOperator::Not => write!(f, "NOT"),
+        }
+    }
+}
+
+/// A literal, with strings quoted.
+fn literal(value: &Value) -> String {
+    Expression::Literal(value.clone()).to_string()
+}
+
+/// Quoted strings, see `sql_string!`, are literals, other strings name an attribute, and
+/// expression operands, see `expression`, are written as their text.
+fn operand(value: &Value) -> String {
+    if let Some(text) = marked_operand(value, EXPRESSION).or(marked_operand(value, ATTRIBUTE)) {
+        return text.to_string();
+    }
+
+    match value.as_string_b() {
+        Some(string) => match Clause::extract_sql_string(&string.as_string()) {
+            Some(string) => literal(&Value::from(string)),
+            None => string.as_string(),
+        },
+        None => literal(value),
+    }
+}
+
+impl Display for Condition {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let left = operand(&self.left);
+        let values = self
+            .right
+            .as_array()
+            .map(|values| values.into_iter().map(literal).collect::<Vec<_>>());
+
+        match (&self.operator, values) {
+            (
+                Operator::IsNull | Operator::IsNotNull | Operator::Exists | Operator::NotExists,
+                _,
+            ) => write!(f, "{} {}", left, self.operator),
+            (Operator::SizeEq | Operator::SizeNe | Operator::SizeGt | Operator::SizeLe, _) => {
+                write!(
+                    f,
+                    "SIZE({}) {} {}",
+                    left,
+                    self.operator,
+                    literal(&self.right)
+                )
+            }
+            (Operator::In | Operator::NotIn, Some(values)) => {
+                write!(f, "{} {} ({})", left, self.operator, values.join(", "))
+            }
+            (Operator::Between | Operator::NotBetween, Some(values)) if values.len() == 2 => {
+                write!(
+                    f,
+                    "{} {} {} AND {}",
+                    left, self.operator, values[0], values[1]
+                )
+            }
+            _ => write!(f, "{} {} {}", left, self.operator, operand(&self.right)),
+        }
+    }
+}
+
+impl ConditionGroup {
+    /// Writes the tokens of the group, with the alternate form nesting at `depth`.
+    fn write(&self, f: &mut Formatter, depth: usize) -> fmt::Result {
+        let pretty = f.alternate();
+        let newline = |f: &mut Formatter, depth: usize| {
+            if pretty {
+                write!(f, "\n{}", INDENT.repeat(depth))
+            } else {
+                Ok(())
+            }
+        };
+
+        for token in &self.conditions {
+            match token {
+                ConditionToken::Condition(condition) => write!(f, "{}", condition)?,
+                ConditionToken::LogicalOperator(LogicalOperator::Not) => write!(f, "NOT ")?,
+                ConditionToken::LogicalOperator(operator) => {
+                    if pretty {
+                        newline(f, depth)?;
+                    } else {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{} ", operator)?;
+                }
+                ConditionToken::ConditionGroup(condition_group) => {
+                    write!(f, "(")?;
+                    newline(f, depth + 1)?;
+                    condition_group.write(f, depth + 1)?;
+                    newline(f, depth)?;
+                    write!(f, ")")?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Display for ConditionGroup {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        self.write(f, 0)
+    }
+}
+
+impl Display for Clause {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Clause::ConditionGroup(condition_group) => condition_group.write(f, 0),
+            Clause::Condition(condition) => condition.fmt(f),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql_string;
+
+    #[test]
+    fn test_display_clause() {
+        let clause = Clause::parse(
+            "where (status = 'a' or status = \"it's\") and not age>30 and name is not null",
+        )
+        .unwrap();
+        assert_eq!(
+            clause.to_string(),
+            "(status = 'a' OR status = 'it''s') AND NOT age > 30 AND name IS NOT NULL"
+        );
+        assert_eq!(
+            format!("{:#}", clause),
+            "(\n    status = 'a'\n    OR status = 'it''s'\n)\nAND NOT age > 30\nAND name IS NOT NULL"
+        );
+
+        let condition = Condition::new(Operator::NotIn, "tags", vec!["a", "b"].to_value());
+        assert_eq!(condition.to_string(), "tags NOT IN ('a', 'b')");
+        let condition = Condition::new(Operator::SizeLe, "tags", 2);
+        assert_eq!(condition.to_string(), "SIZE(tags) <= 2");
+        let condition = Condition::new(Operator::Equal, 1, sql_string!("a"));
+        assert_eq!(condition.to_string(), "1 = 'a'");
+    }
+
+    #[test]
+    fn test_display_round_trip() {
+        let clauses = [
+            "name = 'John'",
+            "a = 1 OR b = 2 AND NOT (c <> 'x' OR d <= 1.5)",
+            "NOT NOT a >= 1 AND (b < 2 OR (c > 3 AND d = TRUE)) OR e = NULL",
+            "age NOT BETWEEN 18 AND 65 OR name ILIKE 'j%' OR name NOT REGEX '^J'",
+            "c IN (1, 'x', NULL, FALSE) AND d NOT LIKE 'J\\%%'",
+            "email IS NULL AND phone IS NOT NULL AND tags EXISTS AND fax NOT EXISTS",
+            "SIZE(tags) > 2 AND SIZE(tags) <= 5 AND SIZE(name) = 4 AND SIZE(name) <> 3",
+            "name STARTS WITH 'J' AND name NOT ENDS WITH 'x' AND tags NOT CONTAINS 'y'",
+            "lower(name) = 'bob' AND created > now() - interval '7 days' AND a = b",
+            "orders[*].total > 100 AND address.city NOT IN ('Lisbon')",
+        ];
+
+        for text in clauses {
+            let clause = Clause::parse(text).unwrap();
+            assert_eq!(
+                Clause::parse(&clause.to_string()).unwrap(),
+                clause,
+                "{}",
+                text
+            );
+            assert_eq!(
+                Clause::parse(&format!("{:#}", clause)).unwrap(),
+                clause,
+                "{}",
+                text
+            );
+        }
+    }
+}
@@ -0,0 +1,178 @@
+//! Traces of the evaluation of a `Clause`, to find out why a value does or does not match it.
+//!
+//! `Clause::explain` evaluates a clause as `Clause::evaluate` does, and records, for every
+//! condition, the values its operands resolved to and its result or error, and for every
+//! group, the traces of its operands. Unlike `evaluate`, every operand of a group is traced,
+//! even once the result of the group is known. The result of a group is still the one of
+//! `evaluate`, so an operand that fails after the result is known does not fail the group.
+//!
+//! `Display` writes the trace as a tree, a line for each step:
+//!
+//! ```text
+//! AND: false
+//!     OR: true
+//!         status = 'a': false (left: "b", right: "a")
+//!         status = 'b': true (left: "b", right: "b")
+//!     age > 30: false (left: 18, right: 30)
+//! ```
+
+use std::fmt::{self, Display, Formatter};
+
+use valu3::prelude::*;
+
+use super::{Condition, Error};
+
+#[derive(Debug)]
+pub enum Step {
+    /// A condition, with the values its operands resolved to, missing values being `NULL`
+    /// and those found through a wildcard an array, see `Resolved::to_value`.
+    Condition {
+        condition: Condition,
+        left: Result<Value, Error>,
+        right: Result<Value, Error>,
+    },
+    Not(Box<Trace>),
+    And(Vec<Trace>),
+    Or(Vec<Trace>),
+}
+
+#[derive(Debug)]
+pub struct Trace {
+    pub step: Step,
+    /// The result of the step, `None` being unknown.
+    pub result: Result<Option<bool>, Error>,
+}
+
+impl Trace {
+    /// The traces of the conditions, from left to right.
+    pub fn conditions(&self) -> Vec<&Trace> {
+        match &self.step {
+            Step::Condition { .. } => vec![self],
+            Step::Not(trace) => trace.conditions(),
+            Step::And(traces) | Step::Or(traces) => {
+                traces.iter().flat_map(Trace::conditions).collect()
+            }
+        }
+    }
+
+    fn write(&self, f: &mut Formatter, depth: usize) -> fmt::Result {
+        write!(f, "{}", "    ".repeat(depth))?;
+
+        match &self.step {
+            Step::Condition { condition, .. } => write!(f, "{}: ", condition)?,
+            Step::Not(_) => write!(f, "NOT: ")?,
+            Step::And(_) => write!(f, "AND: ")?,
+            Step::Or(_) => write!(f, "OR: ")?,
+        }
+
+        match &self.result {
+            Ok(Some(result)) => write!(f, "{}", result)?,
+            Ok(None) => write!(f, "unknown")?,
+            Err(err) => write!(f, "error: {}", err)?,
+        }
+
+        match &self.step {
+            Step::Condition { left, right, .. } => {
+                write!(f, " (left: {}, right: {})", operand(left), operand(right))
+            }
+            Step::Not(trace) => {
+                writeln!(f)?;
+                trace.write(f, depth + 1)
+            }
+            Step::And(traces) | Step::Or(traces) => {
+                for trace in traces {
+                    writeln!(f)?;
+                    trace.write(f, depth + 1)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+fn operand(value: &Result<Value, Error>) -> String {
+    match value {
+        Ok(value) => value.to_json(JsonMode::Inline),
+        Err(err) => format!("error: {}", err),
+    }
+}
+
+impl Display for Trace {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        self.write(f, 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::condition::{Clause, Error, Step};
+    use valu3::prelude::*;
+
+    #[test]
+    fn test_explain() {
+        let clause = Clause::parse("(status = 'a' OR status = 'b') AND age > 30").unwrap();
+        let value = Value::from(vec![("status", Value::from("b")), ("age", Value::from(18))]);
+
+        let trace = clause.explain(&value).unwrap();
+        assert_eq!(trace.result.as_ref().unwrap(), &Some(false));
+        assert!(matches!(&trace.step, Step::And(traces) if traces.len() == 2));
+        assert_eq!(
+            trace.to_string(),
+            "AND: false\n    OR: true\n        \
+             status = 'a': false (left: \"b\", right: \"a\")\n        \
+             status = 'b': true (left: \"b\", right: \"b\")\n    \
+             age > 30: false (left: 18, right: 30)"
+        );
+
+        let failed: Vec<String> = trace
+            .conditions()
+            .into_iter()
+            .filter(|trace| matches!(trace.result, Ok(Some(false))))
+            .map(|trace| match &trace.step {
+                Step::Condition { condition, .. } => condition.to_string(),
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(failed, vec!["status = 'a'", "age > 30"]);
+    }
+
+    #[test]
+    fn test_explain_errors() {
+        let clause =
+            Clause::parse("NOT lower(age) = 'x' OR tags[*] IS NULL OR score EXISTS").unwrap();
+        let value = Value::from(vec![
+            ("age", Value::from(18)),
+            ("tags", vec![Value::from("a"), Value::Null].to_value()),
+        ]);
+
+        let trace = clause.explain(&value).unwrap();
+        assert!(matches!(
+            trace.result,
+            Err(Error::FunctionInvalidArguments(_))
+        ));
+
+        let conditions = trace.conditions();
+        assert_eq!(conditions.len(), 3);
+        match &conditions[0].step {
+            Step::Condition { left, right, .. } => {
+                assert!(matches!(left, Err(Error::FunctionInvalidArguments(_))));
+                assert_eq!(right.as_ref().unwrap(), &Value::from("x"));
+            }
+            _ => unreachable!(),
+        }
+        match &conditions[1].step {
+            Step::Condition { left, .. } => assert_eq!(
+                left.as_ref().unwrap(),
+                &vec![Value::from("a"), Value::Null].to_value()
+            ),
+            _ => unreachable!(),
+        }
+        assert!(matches!(conditions[1].result, Ok(Some(true))));
+        assert!(matches!(conditions[2].result, Ok(Some(false))));
+
+        assert!(matches!(
+            Clause::parse("name REGEX '('").unwrap().explain(&value),
+            Err(Error::InvalidRegex(_))
+        ));
+    }
+}
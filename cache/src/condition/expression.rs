@@ -0,0 +1,339 @@
+//! Operands of a `Condition` computed from the value it runs against.
+//!
+//! Besides an attribute or a literal, an operand can call a function, see `function`, or add
+//! or subtract two operands: `lower(name)`, `length(tags)`, `coalesce(nickname, name)`,
+//! `now() - interval '7 days'`. The condition stores such an operand as a document holding the
+//! text of the expression, `{ "$expression": "lower(name)" }`, see `expression_operand`, which
+//! the `Clause` parser writes as `Display` does, and compiling the clause parses it again. An
+//! unquoted string operand always names an attribute, so `2020-01-01` or `created-at` is never
+//! arithmetic.
+//!
+//! An interval, `interval '1 day 12 hours'`, is a number of milliseconds. Its units go from
+//! milliseconds to weeks, as months and years have no fixed length. Adding a number to a date
+//! moves the date by that many milliseconds, and subtracting two dates gives the milliseconds
+//! between them. Arithmetic with `NULL` is `NULL`.
+
+use std::fmt::{self, Display, Formatter};
+
+use valu3::prelude::*;
+
+use super::coerce::{format_date, Scalar};
+use super::parser::{self, ParseError};
+use super::path::Path;
+use super::{Clause, Error};
+
+/// The key of the document a `Condition` holds a computed operand in.
+pub const EXPRESSION: &str = "$expression";
+
+/// The key of the document a `Condition` holds an attribute in, when its name reads as a
+/// quoted literal.
+pub const ATTRIBUTE: &str = "$attribute";
+
+/// The operand of a `Condition` computing the expression in `text`.
+pub fn expression_operand(text: &str) -> Value {
+    Value::from(vec![(EXPRESSION, Value::from(text))])
+}
+
+/// The operand of a `Condition` naming the attribute `name`, whatever its text.
+pub fn attribute_operand(name: &str) -> Value {
+    match Clause::extract_sql_string(&name.to_string()) {
+        Some(_) => Value::from(vec![(ATTRIBUTE, Value::from(name))]),
+        None => Value::from(name),
+    }
+}
+
+/// The text an operand written as a document holds under `key`, see `EXPRESSION` and
+/// `ATTRIBUTE`.
+pub fn marked_operand<'a>(operand: &'a Value, key: &str) -> Option<&'a str> {
+    if !operand.is_object() || operand.len() != 1 {
+        return None;
+    }
+
+    operand
+        .get(key)
+        .filter(|text| text.is_string())
+        .map(Value::as_str)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArithmeticOperator {
+    Add,
+    Subtract,
+}
+
+impl ArithmeticOperator {
+    pub fn apply(&self, left: &Value, right: &Value) -> Result<Value, Error> {
+        let (left, right) = match (Scalar::new(left), Scalar::new(right)) {
+            (Some(left), Some(right)) => (left, right),
+            _ => return Ok(Value::Null),
+        };
+        let sign = match self {
+            ArithmeticOperator::Add => 1.0,
+            ArithmeticOperator::Subtract => -1.0,
+        };
+
+        match (&left, &right, self) {
+            (Scalar::Number(left), Scalar::Number(right), _) => Ok(number(left + sign * right)),
+            (Scalar::Date(date, _), Scalar::Number(millis), _)
+            | (Scalar::Number(millis), Scalar::Date(date, _), ArithmeticOperator::Add) => {
+                let millis = if matches!(left, Scalar::Date(_, _)) {
+                    sign * millis
+                } else {
+                    *millis
+                };
+                Ok(Value::from(format_date(*date + millis as i64)))
+            }
+            (Scalar::Date(left, _), Scalar::Date(right, _), ArithmeticOperator::Subtract) => {
+                Ok(Value::from(left - right))
+            }
+            _ => Err(Error::ArithmeticInvalid(left.kind(), right.kind())),
+        }
+    }
+}
+
+/// Whole numbers are kept as integers.
+fn number(number: f64) -> Value {
+    if number.fract() == 0.0 && number.abs() < 9_007_199_254_740_992.0 {
+        Value::from(number as i64)
+    } else {
+        Value::from(number)
+    }
+}
+
+impl Display for ArithmeticOperator {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            ArithmeticOperator::Add => write!(f, "+"),
+            ArithmeticOperator::Subtract => write!(f, "-"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expression {
+    Literal(Value),
+    Attribute(Path),
+    /// An interval, in milliseconds.
+    Interval(i64),
+    /// A function name and its arguments.
+    Call(String, Vec<Expression>),
+    Arithmetic(Box<Expression>, ArithmeticOperator, Box<Expression>),
+}
+
+impl Expression {
+    pub fn parse(text: &str) -> Result<Self, ParseError> {
+        parser::parse_expression(text)
+    }
+
+    /// Whether the expression is computed, rather than an attribute or a literal.
+    pub fn is_computed(&self) -> bool {
+        matches!(
+            self,
+            Expression::Interval(_) | Expression::Call(_, _) | Expression::Arithmetic(_, _, _)
+        )
+    }
+}
+
+impl Display for Expression {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Expression::Literal(value) => match value {
+                Value::Null => write!(f, "NULL"),
+                Value::Boolean(_) => match bool::from_value(value.clone()) {
+                    Some(true) => write!(f, "TRUE"),
+                    _ => write!(f, "FALSE"),
+                },
+                Value::String(_) => write!(f, "'{}'", value.as_str().replace('\'', "''")),
+                _ => write!(f, "{}", value.to_json(JsonMode::Inline)),
+            },
+            Expression::Attribute(path) => write!(f, "{}", path),
+            Expression::Interval(millis) => write!(f, "interval '{}'", format_interval(*millis)),
+            Expression::Call(name, arguments) => {
+                write!(f, "{}(", name)?;
+                for (position, argument) in arguments.iter().enumerate() {
+                    if position > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", argument)?;
+                }
+                write!(f, ")")
+            }
+            Expression::Arithmetic(left, operator, right) => {
+                write!(f, "{} {} {}", left, operator, right)
+            }
+        }
+    }
+}
+
+const UNITS: [(&str, i64); 6] = [
+    ("week", 604_800_000),
+    ("day", 86_400_000),
+    ("hour", 3_600_000),
+    ("minute", 60_000),
+    ("second", 1000),
+    ("millisecond", 1),
+];
+
+/// Parses the text of an interval, amounts followed by units such as `1 day 12 hours`, to
+/// milliseconds. Units can be singular or plural.
+pub fn parse_interval(text: &str) -> Option<i64> {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let pairs = words.chunks_exact(2);
+    if words.is_empty() || !pairs.remainder().is_empty() {
+        return None;
+    }
+
+    let mut millis: i64 = 0;
+    for pair in pairs {
+        let amount: i64 = pair[0].parse().ok()?;
+        let unit = pair[1].to_lowercase();
+        let unit = unit.strip_suffix('s').unwrap_or(&unit);
+        let (_, size) = UNITS.iter().find(|(name, _)| *name == unit)?;
+        millis = millis.checked_add(amount.checked_mul(*size)?)?;
+    }
+
+    Some(millis)
+}
+
+/// Writes milliseconds as the text of an interval, in days and smaller units.
+pub fn format_interval(millis: i64) -> String {
+    let mut parts = Vec::new();
+    let mut rest = millis.unsigned_abs();
+    let sign = if millis < 0 { "-" } else { "" };
+
+    for (unit, size) in &UNITS[1..] {
+        let amount = rest / *size as u64;
+        rest %= *size as u64;
+
+        if amount > 0 {
+            let plural = if amount == 1 { "" } else { "s" };
+            parts.push(format!("{}{} {}{}", sign, amount, unit, plural));
+        }
+    }
+
+    if parts.is_empty() {
+        "0 milliseconds".to_string()
+    } else {
+        parts.join(" ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interval() {
+        assert_eq!(parse_interval("7 days"), Some(604_800_000));
+        assert_eq!(parse_interval("1 Week -1 day"), Some(518_400_000));
+        assert_eq!(parse_interval("1 hour 30 minutes"), Some(5_400_000));
+        assert_eq!(parse_interval("2 seconds 5 milliseconds"), Some(2005));
+        for text in ["", "7", "days", "7 months", "1.5 hours", "7 days 1"] {
+            assert_eq!(parse_interval(text), None, "{}", text);
+        }
+
+        assert_eq!(format_interval(604_800_000), "7 days");
+        assert_eq!(
+            format_interval(90_061_001),
+            "1 day 1 hour 1 minute 1 second 1 millisecond"
+        );
+        assert_eq!(format_interval(-5_400_000), "-1 hour -30 minutes");
+        assert_eq!(format_interval(0), "0 milliseconds");
+        for millis in [1, -90_061_001, 604_800_000] {
+            assert_eq!(parse_interval(&format_interval(millis)), Some(millis));
+        }
+    }
+
+    #[test]
+    fn test_expression_parse_and_display() {
+        let expression = Expression::parse("now()-interval '7 days'").unwrap();
+        assert_eq!(
+            expression,
+            Expression::Arithmetic(
+                Box::new(Expression::Call("now".to_string(), vec![])),
+                ArithmeticOperator::Subtract,
+                Box::new(Expression::Interval(604_800_000)),
+            )
+        );
+        assert_eq!(expression.to_string(), "now() - interval '7 days'");
+
+        for text in [
+            "lower(name)",
+            "coalesce(address.city, 'it''s', NULL, TRUE, -1.5) + 1",
+            "length(orders[*].total)",
+        ] {
+            let expression = Expression::parse(text).unwrap();
+            assert!(expression.is_computed());
+            assert_eq!(expression.to_string(), text);
+            assert_eq!(Expression::parse(text).unwrap(), expression);
+        }
+
+        assert!(!Expression::parse("name").unwrap().is_computed());
+        assert!(!Expression::parse("'name'").unwrap().is_computed());
+        assert!(Expression::parse("first name").is_err());
+        assert!(Expression::parse("interval '7 moons'").is_err());
+    }
+
+    #[test]
+    fn test_operands() {
+        let operand = expression_operand("lower(name)");
+        assert_eq!(marked_operand(&operand, EXPRESSION), Some("lower(name)"));
+        assert_eq!(marked_operand(&operand, ATTRIBUTE), None);
+
+        assert_eq!(attribute_operand("created-at"), Value::from("created-at"));
+        let operand = attribute_operand("'quoted'");
+        assert_eq!(marked_operand(&operand, ATTRIBUTE), Some("'quoted'"));
+        assert_eq!(marked_operand(&Value::from("name"), ATTRIBUTE), None);
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let apply =
+            |left: Value, operator: ArithmeticOperator, right: Value| operator.apply(&left, &right);
+        let date = Value::from("2020-01-01T00:00:00Z");
+
+        assert_eq!(
+            apply(Value::from(1), ArithmeticOperator::Add, Value::from(2.5)).unwrap(),
+            Value::from(3.5)
+        );
+        assert_eq!(
+            apply(Value::from(3), ArithmeticOperator::Subtract, Value::from(1)).unwrap(),
+            Value::from(2_i64)
+        );
+        assert_eq!(
+            apply(
+                date.clone(),
+                ArithmeticOperator::Subtract,
+                Value::from(86_400_000)
+            )
+            .unwrap(),
+            Value::from("2019-12-31T00:00:00.000Z")
+        );
+        assert_eq!(
+            apply(
+                Value::from(3_600_000),
+                ArithmeticOperator::Add,
+                date.clone()
+            )
+            .unwrap(),
+            Value::from("2020-01-01T01:00:00.000Z")
+        );
+        assert_eq!(
+            apply(
+                date.clone(),
+                ArithmeticOperator::Subtract,
+                Value::from("2019-12-31")
+            )
+            .unwrap(),
+            Value::from(86_400_000_i64)
+        );
+        assert_eq!(
+            apply(Value::Null, ArithmeticOperator::Add, Value::from(1)).unwrap(),
+            Value::Null
+        );
+        assert!(matches!(
+            apply(Value::from(1), ArithmeticOperator::Subtract, date),
+            Err(Error::ArithmeticInvalid("number", "date"))
+        ));
+    }
+}
@@ -0,0 +1,212 @@
+//! Functions that can be called in the operands of a `Condition`, see `expression`.
+//!
+//! The registry starts with the built-in functions:
+//!
+//! - `lower(text)` and `upper(text)` change the case of a string.
+//! - `length(value)` counts the characters of a string or the elements of an array or object.
+//! - `now()` is the current time, as an ISO 8601 date in UTC.
+//! - `coalesce(value, ...)` is the first of its arguments that is not `NULL`.
+//!
+//! `register` adds a function, or replaces one with the same name, for every clause compiled
+//! afterwards. Function names are case-insensitive. Arguments naming an attribute the value
+//! does not have are `NULL`, and the built-in functions return `NULL` for a `NULL` argument,
+//! except `coalesce`.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use valu3::prelude::*;
+
+use super::coerce::format_date;
+use super::Error;
+
+/// A function receives the values of its arguments, and should fail with
+/// `Error::FunctionInvalidArguments` when they are not what it expects.
+pub type Function = Arc<dyn Fn(&[Value]) -> Result<Value, Error> + Send + Sync>;
+
+static FUNCTIONS: RwLock<BTreeMap<String, Function>> = RwLock::new(BTreeMap::new());
+
+/// Adds `function` to the registry as `name`.
+pub fn register<F>(name: &str, function: F)
+where
+    F: Fn(&[Value]) -> Result<Value, Error> + Send + Sync + 'static,
+{
+    FUNCTIONS
+        .write()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert(name.to_lowercase(), Arc::new(function));
+}
+
+/// The function registered as `name`, or the built-in one.
+pub fn get(name: &str) -> Option<Function> {
+    let name = name.to_lowercase();
+
+    if let Some(function) = FUNCTIONS
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get(&name)
+    {
+        return Some(function.clone());
+    }
+
+    let function: Function = match name.as_str() {
+        "lower" => Arc::new(lower),
+        "upper" => Arc::new(upper),
+        "length" => Arc::new(length),
+        "now" => Arc::new(now),
+        "coalesce" => Arc::new(coalesce),
+        _ => return None,
+    };
+
+    Some(function)
+}
+
+fn invalid(name: &str) -> Error {
+    Error::FunctionInvalidArguments(name.to_string())
+}
+
+fn argument<'a>(name: &str, arguments: &'a [Value]) -> Result<&'a Value, Error> {
+    match arguments {
+        [argument] => Ok(argument),
+        _ => Err(invalid(name)),
+    }
+}
+
+fn map_string<F>(name: &str, arguments: &[Value], map: F) -> Result<Value, Error>
+where
+    F: Fn(&str) -> String,
+{
+    let argument = argument(name, arguments)?;
+
+    if argument.is_null() {
+        return Ok(Value::Null);
+    }
+
+    match argument.as_string_b() {
+        Some(string) => Ok(Value::from(map(string.as_str()))),
+        None => Err(invalid(name)),
+    }
+}
+
+fn lower(arguments: &[Value]) -> Result<Value, Error> {
+    map_string("lower", arguments, str::to_lowercase)
+}
+
+fn upper(arguments: &[Value]) -> Result<Value, Error> {
+    map_string("upper", arguments, str::to_uppercase)
+}
+
+fn length(arguments: &[Value]) -> Result<Value, Error> {
+    let argument = argument("length", arguments)?;
+
+    match argument {
+        Value::Null => Ok(Value::Null),
+        Value::String(_) => Ok(Value::from(argument.as_str().chars().count() as i64)),
+        Value::Array(_) | Value::Object(_) => Ok(Value::from(argument.len() as i64)),
+        _ => Err(invalid("length")),
+    }
+}
+
+fn now(arguments: &[Value]) -> Result<Value, Error> {
+    if !arguments.is_empty() {
+        return Err(invalid("now"));
+    }
+
+    let millis = match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_millis() as i64,
+        Err(err) => -(err.duration().as_millis() as i64),
+    };
+
+    Ok(Value::from(format_date(millis)))
+}
+
+fn coalesce(arguments: &[Value]) -> Result<Value, Error> {
+    if arguments.is_empty() {
+        return Err(invalid("coalesce"));
+    }
+
+    Ok(arguments
+        .iter()
+        .find(|argument| !argument.is_null())
+        .cloned()
+        .unwrap_or(Value::Null))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::condition::coerce::parse_date;
+    use crate::condition::Clause;
+
+    fn call(name: &str, arguments: Vec<Value>) -> Result<Value, Error> {
+        get(name).unwrap()(&arguments)
+    }
+
+    #[test]
+    fn test_builtin_functions() {
+        assert_eq!(
+            call("LOWER", vec![Value::from("BoB")]).unwrap(),
+            Value::from("bob")
+        );
+        assert_eq!(
+            call("upper", vec![Value::from("ação")]).unwrap(),
+            Value::from("AÇÃO")
+        );
+        assert_eq!(call("lower", vec![Value::Null]).unwrap(), Value::Null);
+        assert_eq!(
+            call("length", vec![Value::from("ação")]).unwrap(),
+            Value::from(4_i64)
+        );
+        assert_eq!(
+            call("length", vec![Value::from(vec![1, 2, 3])]).unwrap(),
+            Value::from(3_i64)
+        );
+        assert_eq!(
+            call(
+                "coalesce",
+                vec![Value::Null, Value::from(1), Value::from(2)]
+            )
+            .unwrap(),
+            Value::from(1)
+        );
+        assert!(parse_date(call("now", vec![]).unwrap().as_str()).is_some());
+
+        assert!(matches!(
+            call("lower", vec![Value::from(1)]),
+            Err(Error::FunctionInvalidArguments(name)) if name == "lower"
+        ));
+        assert!(matches!(
+            call("length", vec![]),
+            Err(Error::FunctionInvalidArguments(_))
+        ));
+        assert!(get("missing").is_none());
+    }
+
+    #[test]
+    fn test_register_function() {
+        register("Initial", |arguments: &[Value]| match arguments {
+            [name] if name.is_string() => Ok(Value::from(
+                name.as_str().chars().take(1).collect::<String>(),
+            )),
+            _ => Err(Error::FunctionInvalidArguments("initial".to_string())),
+        });
+
+        let clause = Clause::parse("initial(name) = 'J' AND INITIAL(upper(city)) = 'L'").unwrap();
+        let value = Value::from(vec![("name", "John"), ("city", "lisbon")]);
+        assert!(clause.execute(&value).unwrap());
+
+        let clause = Clause::parse("initial(age) = 'J'").unwrap();
+        let value = Value::from(vec![("age", 18)]);
+        assert!(matches!(
+            clause.execute(&value),
+            Err(Error::FunctionInvalidArguments(_))
+        ));
+
+        let clause = Clause::parse("unknown(name) = 'J'").unwrap();
+        assert!(matches!(
+            clause.compile(),
+            Err(Error::FunctionNotFound(name)) if name == "unknown"
+        ));
+    }
+}
@@ -0,0 +1,924 @@
+//! Parsing of SQL `WHERE` clauses into a `Clause`.
+//!
+//! ```text
+//! condition := [WHERE] or
+//! or        := and (OR and)*
+//! and       := not (AND not)*
+//! not       := NOT not | '(' or ')' | predicate
+//! predicate := operand (= | <> | != | < | <= | > | >=) operand
+//!            | operand [NOT] (LIKE | ILIKE | REGEX | CONTAINS) operand
+//!            | operand [NOT] (STARTS | ENDS) WITH operand
+//!            | operand [NOT] IN '(' literal (',' literal)* ')'
+//!            | operand [NOT] BETWEEN literal AND literal
+//!            | operand IS [NOT] NULL
+//!            | operand [NOT] EXISTS
+//!            | SIZE '(' operand ')' (= | <> | != | > | <=) literal
+//! operand   := term ((+ | -) term)*
+//! term      := function '(' [operand (',' operand)*] ')' | INTERVAL string | attribute | literal
+//! ```
+//!
+//! Keywords are case-insensitive. An attribute is a name or a path to a nested attribute, such
+//! as `orders[*].total`, see `path`, and a literal is a string between single or double
+//! quotes, with the quote doubled inside it, a number, `TRUE`, `FALSE` or `NULL`. String
+//! operands are kept quoted, as `sql_string!` does, so they are not looked up as attributes.
+//! Operands calling a function or doing arithmetic are stored as expression operands, see
+//! `expression`. The literals of `IN` and `BETWEEN` are stored unquoted in an array.
+//!
+//! `NOT` before a condition or a group becomes a `LogicalOperator::Not` token, while the
+//! `NOT` after an operand, as in `NOT LIKE` or `NOT EXISTS`, picks the negated operator.
+
+use std::fmt::{self, Display, Formatter};
+
+use valu3::prelude::*;
+
+use super::expression::{expression_operand, parse_interval, ArithmeticOperator, Expression};
+use super::path::Path;
+use super::{Clause, Condition, ConditionGroup, ConditionToken, LogicalOperator, Operator};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseErrorKind {
+    UnexpectedCharacter(char),
+    UnterminatedString,
+    InvalidNumber(String),
+    InvalidInterval(String),
+    /// What the parser expected and the token it found instead.
+    Expected(String, String),
+}
+
+/// A syntax error, at the line and column of the character or token it was found at, both
+/// starting at 1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match &self.kind {
+            ParseErrorKind::UnexpectedCharacter(c) => write!(f, "Unexpected character '{}'", c)?,
+            ParseErrorKind::UnterminatedString => write!(f, "Unterminated string")?,
+            ParseErrorKind::InvalidNumber(number) => write!(f, "Invalid number {}", number)?,
+            ParseErrorKind::InvalidInterval(interval) => {
+                write!(f, "Invalid interval '{}'", interval)?
+            }
+            ParseErrorKind::Expected(expected, found) => {
+                write!(f, "Expected {}, found {}", expected, found)?
+            }
+        }
+
+        write!(f, " at line {}, column {}", self.line, self.column)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+const KEYWORDS: [&str; 17] = [
+    "WHERE", "AND", "OR", "NOT", "IN", "BETWEEN", "LIKE", "ILIKE", "REGEX", "CONTAINS", "STARTS",
+    "ENDS", "EXISTS", "IS", "NULL", "TRUE", "FALSE",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    String(String),
+    Number(String),
+    Symbol(&'static str),
+    End,
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "{}", word),
+            Token::String(string) => write!(f, "'{}'", string.replace('\'', "''")),
+            Token::Number(number) => write!(f, "{}", number),
+            Token::Symbol(symbol) => write!(f, "'{}'", symbol),
+            Token::End => write!(f, "end of input"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Spanned {
+    token: Token,
+    line: usize,
+    column: usize,
+}
+
+const SYMBOLS: [&str; 12] = [
+    "<=", ">=", "<>", "!=", "=", "<", ">", "(", ")", ",", "-", "+",
+];
+
+struct Lexer {
+    chars: Vec<char>,
+    position: usize,
+    line: usize,
+    column: usize,
+}
+
+impl Lexer {
+    fn new(text: &str) -> Self {
+        Self {
+            chars: text.chars().collect(),
+            position: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+
+    fn peek(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.position + offset).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek(0)?;
+        self.position += 1;
+
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+
+        Some(c)
+    }
+
+    fn take_while<F>(&mut self, predicate: F) -> String
+    where
+        F: Fn(char) -> bool,
+    {
+        let mut taken = String::new();
+
+        while let Some(c) = self.peek(0).filter(|c| predicate(*c)) {
+            taken.push(c);
+            self.bump();
+        }
+
+        taken
+    }
+
+    fn error(kind: ParseErrorKind, line: usize, column: usize) -> ParseError {
+        ParseError { kind, line, column }
+    }
+
+    fn tokenize(mut self) -> Result<Vec<Spanned>, ParseError> {
+        let mut tokens = Vec::new();
+
+        loop {
+            self.take_while(char::is_whitespace);
+
+            let (line, column) = (self.line, self.column);
+            let token = match self.peek(0) {
+                None => Token::End,
+                Some(c) if c.is_alphabetic() || c == '_' => Token::Word(self.take_while(|c| {
+                    c.is_alphanumeric() || matches!(c, '_' | '.' | '[' | ']' | '*')
+                })),
+                Some(c) if c.is_ascii_digit() => self.number(line, column)?,
+                Some(quote) if quote == '\'' || quote == '"' => self.string(quote, line, column)?,
+                Some(c) => match SYMBOLS.iter().find(|symbol| self.starts_with(symbol)) {
+                    Some(symbol) => {
+                        for _ in 0..symbol.len() {
+                            self.bump();
+                        }
+                        Token::Symbol(symbol)
+                    }
+                    None => {
+                        return Err(Self::error(
+                            ParseErrorKind::UnexpectedCharacter(c),
+                            line,
+                            column,
+                        ))
+                    }
+                },
+            };
+
+            let end = token == Token::End;
+            tokens.push(Spanned {
+                token,
+                line,
+                column,
+            });
+
+            if end {
+                return Ok(tokens);
+            }
+        }
+    }
+
+    fn starts_with(&self, symbol: &str) -> bool {
+        symbol
+            .chars()
+            .enumerate()
+            .all(|(offset, c)| self.peek(offset) == Some(c))
+    }
+
+    fn number(&mut self, line: usize, column: usize) -> Result<Token, ParseError> {
+        let mut number = self.take_while(|c| c.is_ascii_digit());
+
+        if self.peek(0) == Some('.') {
+            number.push('.');
+            self.bump();
+            number.push_str(&self.take_while(|c| c.is_ascii_digit()));
+        }
+
+        if matches!(self.peek(0), Some('e') | Some('E')) {
+            number.push('e');
+            self.bump();
+            if let Some(sign) = self.peek(0).filter(|c| *c == '+' || *c == '-') {
+                number.push(sign);
+                self.bump();
+            }
+            number.push_str(&self.take_while(|c| c.is_ascii_digit()));
+        }
+
+        number.push_str(&self.take_while(|c| c.is_alphanumeric() || c == '_' || c == '.'));
+
+        if number.parse::<f64>().is_err() {
+            return Err(Self::error(
+                ParseErrorKind::InvalidNumber(number),
+                line,
+                column,
+            ));
+        }
+
+        Ok(Token::Number(number))
+    }
+
+    fn string(&mut self, quote: char, line: usize, column: usize) -> Result<Token, ParseError> {
+        let mut string = String::new();
+        self.bump();
+
+        loop {
+            match self.bump() {
+                Some(c) if c == quote => {
+                    if self.peek(0) == Some(quote) {
+                        string.push(quote);
+                        self.bump();
+                    } else {
+                        return Ok(Token::String(string));
+                    }
+                }
+                Some(c) => string.push(c),
+                None => {
+                    return Err(Self::error(
+                        ParseErrorKind::UnterminatedString,
+                        line,
+                        column,
+                    ))
+                }
+            }
+        }
+    }
+}
+
+/// A parsed condition, before it is laid out as the tokens of a `ConditionGroup`.
+pub(super) enum Expr {
+    Condition(Condition),
+    Not(Box<Expr>),
+    Group(LogicalOperator, Vec<Expr>),
+}
+
+impl Expr {
+    pub(super) fn group(operator: LogicalOperator, mut operands: Vec<Expr>) -> Self {
+        if operands.len() == 1 {
+            operands.remove(0)
+        } else {
+            Expr::Group(operator, operands)
+        }
+    }
+
+    /// Appends the tokens of the expression, where a group is a single token.
+    fn push_tokens(self, tokens: &mut Vec<ConditionToken>) {
+        match self {
+            Expr::Condition(condition) => tokens.push(ConditionToken::Condition(condition)),
+            Expr::Not(expr) => {
+                tokens.push(ConditionToken::LogicalOperator(LogicalOperator::Not));
+                expr.push_tokens(tokens);
+            }
+            Expr::Group(operator, operands) => tokens.push(ConditionToken::ConditionGroup(
+                Self::into_condition_group(operator, operands),
+            )),
+        }
+    }
+
+    fn into_condition_group(operator: LogicalOperator, operands: Vec<Expr>) -> ConditionGroup {
+        let mut conditions = Vec::new();
+
+        for operand in operands {
+            if !conditions.is_empty() {
+                conditions.push(ConditionToken::LogicalOperator(operator.clone()));
+            }
+            operand.push_tokens(&mut conditions);
+        }
+
+        ConditionGroup { conditions }
+    }
+
+    pub(super) fn into_clause(self) -> Clause {
+        match self {
+            Expr::Condition(condition) => Clause::Condition(condition),
+            Expr::Group(operator, operands) => {
+                Clause::ConditionGroup(Self::into_condition_group(operator, operands))
+            }
+            Expr::Not(_) => {
+                let mut conditions = Vec::new();
+                self.push_tokens(&mut conditions);
+                Clause::ConditionGroup(ConditionGroup { conditions })
+            }
+        }
+    }
+}
+
+struct Parser {
+    tokens: Vec<Spanned>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Spanned {
+        &self.tokens[self.position]
+    }
+
+    /// Takes the current token. The last token, `Token::End`, is never consumed.
+    fn next(&mut self) -> Spanned {
+        let spanned = self.tokens[self.position].clone();
+        if self.position + 1 < self.tokens.len() {
+            self.position += 1;
+        }
+        spanned
+    }
+
+    fn expected(spanned: &Spanned, expected: &str) -> ParseError {
+        ParseError {
+            kind: ParseErrorKind::Expected(expected.to_string(), spanned.token.to_string()),
+            line: spanned.line,
+            column: spanned.column,
+        }
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(&self.peek().token, Token::Word(word) if word.eq_ignore_ascii_case(keyword))
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        let found = self.is_keyword(keyword);
+        if found {
+            self.next();
+        }
+        found
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), ParseError> {
+        if self.eat_keyword(keyword) {
+            Ok(())
+        } else {
+            Err(Self::expected(self.peek(), keyword))
+        }
+    }
+
+    fn eat_symbol(&mut self, symbol: &'static str) -> bool {
+        let found = self.peek().token == Token::Symbol(symbol);
+        if found {
+            self.next();
+        }
+        found
+    }
+
+    fn expect_symbol(&mut self, symbol: &'static str) -> Result<(), ParseError> {
+        if self.eat_symbol(symbol) {
+            Ok(())
+        } else {
+            Err(Self::expected(self.peek(), &format!("'{}'", symbol)))
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, ParseError> {
+        let mut operands = vec![self.parse_and()?];
+
+        while self.eat_keyword("OR") {
+            operands.push(self.parse_and()?);
+        }
+
+        Ok(Expr::group(LogicalOperator::Or, operands))
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, ParseError> {
+        let mut operands = vec![self.parse_not()?];
+
+        while self.eat_keyword("AND") {
+            operands.push(self.parse_not()?);
+        }
+
+        Ok(Expr::group(LogicalOperator::And, operands))
+    }
+
+    fn parse_not(&mut self) -> Result<Expr, ParseError> {
+        if self.eat_keyword("NOT") {
+            Ok(Expr::Not(Box::new(self.parse_not()?)))
+        } else if self.eat_symbol("(") {
+            let expr = self.parse_or()?;
+            self.expect_symbol(")")?;
+            Ok(expr)
+        } else {
+            self.parse_predicate()
+        }
+    }
+
+    fn parse_predicate(&mut self) -> Result<Expr, ParseError> {
+        let left = match self.parse_expression()? {
+            Expression::Call(name, mut arguments)
+                if name.eq_ignore_ascii_case("SIZE") && arguments.len() == 1 =>
+            {
+                return self.parse_size(Self::operand(arguments.remove(0)));
+            }
+            expression => Self::operand(expression),
+        };
+
+        if self.eat_keyword("IS") {
+            let operator = if self.eat_keyword("NOT") {
+                Operator::IsNotNull
+            } else {
+                Operator::IsNull
+            };
+            self.expect_keyword("NULL")?;
+
+            return Ok(Expr::Condition(Condition::new(operator, left, Value::Null)));
+        }
+
+        let negated = self.eat_keyword("NOT");
+        let (operator, right) = if self.eat_keyword("LIKE") {
+            (Operator::Like, self.parse_operand()?)
+        } else if self.eat_keyword("ILIKE") {
+            (Operator::ILike, self.parse_operand()?)
+        } else if self.eat_keyword("REGEX") {
+            (Operator::Regex, self.parse_operand()?)
+        } else if self.eat_keyword("CONTAINS") {
+            (Operator::Contains, self.parse_operand()?)
+        } else if self.eat_keyword("STARTS") {
+            self.expect_keyword("WITH")?;
+            (Operator::StartsWith, self.parse_operand()?)
+        } else if self.eat_keyword("ENDS") {
+            self.expect_keyword("WITH")?;
+            (Operator::EndsWith, self.parse_operand()?)
+        } else if self.eat_keyword("EXISTS") {
+            (Operator::Exists, Value::Null)
+        } else if self.eat_keyword("IN") {
+            (Operator::In, self.parse_list()?)
+        } else if self.eat_keyword("BETWEEN") {
+            let low = self.parse_literal()?;
+            self.expect_keyword("AND")?;
+            let high = self.parse_literal()?;
+            (Operator::Between, vec![low, high].to_value())
+        } else if negated {
+            return Err(Self::expected(
+                self.peek(),
+                "LIKE, ILIKE, REGEX, CONTAINS, STARTS WITH, ENDS WITH, IN, BETWEEN or EXISTS",
+            ));
+        } else {
+            let operator = match self.peek().token {
+                Token::Symbol("=") => Operator::Equal,
+                Token::Symbol("<>") | Token::Symbol("!=") => Operator::NotEqual,
+                Token::Symbol("<") => Operator::LessThan,
+                Token::Symbol("<=") => Operator::LessThanOrEqual,
+                Token::Symbol(">") => Operator::GreaterThan,
+                Token::Symbol(">=") => Operator::GreaterThanOrEqual,
+                _ => return Err(Self::expected(self.peek(), "an operator")),
+            };
+            self.next();
+            (operator, self.parse_operand()?)
+        };
+
+        let operator = if negated { operator.negate() } else { operator };
+
+        Ok(Expr::Condition(Condition::new(operator, left, right)))
+    }
+
+    /// The comparison of `SIZE(left)` with a number.
+    fn parse_size(&mut self, left: Value) -> Result<Expr, ParseError> {
+        let operator = match self.peek().token {
+            Token::Symbol("=") => Operator::SizeEq,
+            Token::Symbol("<>") | Token::Symbol("!=") => Operator::SizeNe,
+            Token::Symbol(">") => Operator::SizeGt,
+            Token::Symbol("<=") => Operator::SizeLe,
+            _ => return Err(Self::expected(self.peek(), "=, <>, > or <=")),
+        };
+        self.next();
+
+        Ok(Expr::Condition(Condition::new(
+            operator,
+            left,
+            self.parse_literal()?,
+        )))
+    }
+
+    fn parse_list(&mut self) -> Result<Value, ParseError> {
+        self.expect_symbol("(")?;
+        let mut values = vec![self.parse_literal()?];
+
+        while self.eat_symbol(",") {
+            values.push(self.parse_literal()?);
+        }
+        self.expect_symbol(")")?;
+
+        Ok(values.to_value())
+    }
+
+    fn parse_operand(&mut self) -> Result<Value, ParseError> {
+        Ok(Self::operand(self.parse_expression()?))
+    }
+
+    /// An attribute name, a quoted string, a literal or the text of an expression.
+    fn operand(expression: Expression) -> Value {
+        match expression {
+            Expression::Attribute(path) => Value::from(path.to_string()),
+            Expression::Literal(value) if value.is_string() => {
+                Value::from(crate::sql_string!(value.as_str()))
+            }
+            Expression::Literal(value) => value,
+            expression => expression_operand(&expression.to_string()),
+        }
+    }
+
+    fn parse_expression(&mut self) -> Result<Expression, ParseError> {
+        let mut expression = self.parse_term()?;
+
+        loop {
+            let operator = if self.eat_symbol("+") {
+                ArithmeticOperator::Add
+            } else if self.eat_symbol("-") {
+                ArithmeticOperator::Subtract
+            } else {
+                return Ok(expression);
+            };
+
+            expression = Expression::Arithmetic(
+                Box::new(expression),
+                operator,
+                Box::new(self.parse_term()?),
+            );
+        }
+    }
+
+    fn parse_term(&mut self) -> Result<Expression, ParseError> {
+        let word = match &self.peek().token {
+            Token::Word(word) if !is_keyword(word) => word.clone(),
+            _ => return Ok(Expression::Literal(self.parse_literal()?)),
+        };
+        self.next();
+
+        if word.eq_ignore_ascii_case("INTERVAL") {
+            if let Token::String(interval) = self.peek().token.clone() {
+                let spanned = self.next();
+                return match parse_interval(&interval) {
+                    Some(millis) => Ok(Expression::Interval(millis)),
+                    None => Err(Lexer::error(
+                        ParseErrorKind::InvalidInterval(interval),
+                        spanned.line,
+                        spanned.column,
+                    )),
+                };
+            }
+        }
+
+        if !self.eat_symbol("(") {
+            return Ok(Expression::Attribute(Path::new(&word)));
+        }
+
+        let mut arguments = Vec::new();
+        if !self.eat_symbol(")") {
+            arguments.push(self.parse_expression()?);
+            while self.eat_symbol(",") {
+                arguments.push(self.parse_expression()?);
+            }
+            self.expect_symbol(")")?;
+        }
+
+        Ok(Expression::Call(word, arguments))
+    }
+
+    /// A literal, with strings unquoted.
+    fn parse_literal(&mut self) -> Result<Value, ParseError> {
+        let negative = self.eat_symbol("-");
+        let spanned = self.next();
+
+        match &spanned.token {
+            Token::Number(number) => {
+                let number = if negative {
+                    format!("-{}", number)
+                } else {
+                    number.clone()
+                };
+                match number.parse::<i64>() {
+                    Ok(number) => Ok(Value::from(number)),
+                    Err(_) => Ok(Value::from(number.parse::<f64>().unwrap_or_default())),
+                }
+            }
+            Token::String(string) if !negative => Ok(Value::from(string.as_str())),
+            Token::Word(word) if !negative && word.eq_ignore_ascii_case("NULL") => Ok(Value::Null),
+            Token::Word(word) if !negative && word.eq_ignore_ascii_case("TRUE") => {
+                Ok(Value::from(true))
+            }
+            Token::Word(word) if !negative && word.eq_ignore_ascii_case("FALSE") => {
+                Ok(Value::from(false))
+            }
+            _ if negative => Err(Self::expected(&spanned, "a number")),
+            _ => Err(Self::expected(&spanned, "a value")),
+        }
+    }
+}
+
+fn is_keyword(word: &str) -> bool {
+    KEYWORDS
+        .iter()
+        .any(|keyword| keyword.eq_ignore_ascii_case(word))
+}
+
+/// Parses the condition of a `WHERE` clause, with or without the `WHERE` keyword.
+pub fn parse(text: &str) -> Result<Clause, ParseError> {
+    let mut parser = Parser {
+        tokens: Lexer::new(text).tokenize()?,
+        position: 0,
+    };
+
+    parser.eat_keyword("WHERE");
+    let expr = parser.parse_or()?;
+
+    match parser.peek().token {
+        Token::End => Ok(expr.into_clause()),
+        _ => Err(Parser::expected(
+            parser.peek(),
+            "AND, OR or the end of the clause",
+        )),
+    }
+}
+
+/// Parses an operand, see `expression`.
+pub fn parse_expression(text: &str) -> Result<Expression, ParseError> {
+    let mut parser = Parser {
+        tokens: Lexer::new(text).tokenize()?,
+        position: 0,
+    };
+
+    let expression = parser.parse_expression()?;
+
+    match parser.peek().token {
+        Token::End => Ok(expression),
+        _ => Err(Parser::expected(parser.peek(), "the end of the expression")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql_string;
+
+    fn condition<L, R>(operator: Operator, left: L, right: R) -> ConditionToken
+    where
+        L: Into<Value>,
+        R: Into<Value>,
+    {
+        ConditionToken::Condition(Condition::new(operator, left, right))
+    }
+
+    fn logical(operator: LogicalOperator) -> ConditionToken {
+        ConditionToken::LogicalOperator(operator)
+    }
+
+    #[test]
+    fn test_parse_condition() {
+        assert_eq!(
+            parse("WHERE name = 'John'").unwrap(),
+            Clause::condition(Operator::Equal, "name", sql_string!("John"))
+        );
+        assert_eq!(
+            parse("age>=-18.5").unwrap(),
+            Clause::condition(Operator::GreaterThanOrEqual, "age", -18.5)
+        );
+        assert_eq!(
+            parse("\"it's\" != name").unwrap(),
+            Clause::condition(Operator::NotEqual, "'it's'", "name")
+        );
+        assert_eq!(
+            parse("email is not null").unwrap(),
+            Clause::condition(Operator::IsNotNull, "email", Value::Null)
+        );
+        assert_eq!(
+            parse("LOWER( name )='bob' AND created > now()-interval '1 day 2 hours'").unwrap(),
+            Clause::group(vec![
+                condition(
+                    Operator::Equal,
+                    expression_operand("LOWER(name)"),
+                    sql_string!("bob")
+                ),
+                logical(LogicalOperator::And),
+                condition(
+                    Operator::GreaterThan,
+                    "created",
+                    expression_operand("now() - interval '1 day 2 hours'")
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_precedence_and_not() {
+        assert_eq!(
+            parse("a = 1 OR b = 2 AND c = 3").unwrap(),
+            Clause::group(vec![
+                condition(Operator::Equal, "a", 1),
+                logical(LogicalOperator::Or),
+                ConditionToken::ConditionGroup(ConditionGroup {
+                    conditions: vec![
+                        condition(Operator::Equal, "b", 2),
+                        logical(LogicalOperator::And),
+                        condition(Operator::Equal, "c", 3),
+                    ],
+                }),
+            ])
+        );
+
+        assert_eq!(
+            parse("NOT (a > 1 OR b NOT LIKE 'J%') AND NOT NOT c IN (1, 'x')").unwrap(),
+            Clause::group(vec![
+                logical(LogicalOperator::Not),
+                ConditionToken::ConditionGroup(ConditionGroup {
+                    conditions: vec![
+                        condition(Operator::GreaterThan, "a", 1),
+                        logical(LogicalOperator::Or),
+                        condition(Operator::NotLike, "b", sql_string!("J%")),
+                    ],
+                }),
+                logical(LogicalOperator::And),
+                logical(LogicalOperator::Not),
+                logical(LogicalOperator::Not),
+                condition(
+                    Operator::In,
+                    "c",
+                    vec![Value::from(1), Value::from("x")].to_value()
+                ),
+            ])
+        );
+
+        assert_eq!(
+            parse("not a is null").unwrap(),
+            Clause::group(vec![
+                logical(LogicalOperator::Not),
+                condition(Operator::IsNull, "a", Value::Null),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_operators() {
+        let cases = [
+            (
+                "tags CONTAINS 'a'",
+                Operator::Contains,
+                Value::from(sql_string!("a")),
+            ),
+            (
+                "name NOT ILIKE 'j%'",
+                Operator::NotILike,
+                Value::from(sql_string!("j%")),
+            ),
+            (
+                "name starts with 'J'",
+                Operator::StartsWith,
+                Value::from(sql_string!("J")),
+            ),
+            (
+                "name NOT ENDS WITH 'n'",
+                Operator::NotEndsWith,
+                Value::from(sql_string!("n")),
+            ),
+            ("tags EXISTS", Operator::Exists, Value::Null),
+            ("tags NOT EXISTS", Operator::NotExists, Value::Null),
+            ("size(tags) = 2", Operator::SizeEq, Value::from(2_i64)),
+            ("SIZE(tags) != 2", Operator::SizeNe, Value::from(2_i64)),
+            ("Size(tags) > 2", Operator::SizeGt, Value::from(2_i64)),
+            ("size(tags) <= 2", Operator::SizeLe, Value::from(2_i64)),
+        ];
+
+        for (text, operator, right) in cases {
+            let name = if text.contains("name") {
+                "name"
+            } else {
+                "tags"
+            };
+            assert_eq!(
+                parse(text).unwrap(),
+                Clause::condition(operator, name, right),
+                "{}",
+                text
+            );
+        }
+
+        assert_eq!(
+            parse("size(tags) >= 2").unwrap_err().to_string(),
+            "Expected =, <>, > or <=, found '>=' at line 1, column 12"
+        );
+        assert_eq!(
+            parse("name STARTS 'J'").unwrap_err().to_string(),
+            "Expected WITH, found 'J' at line 1, column 13"
+        );
+    }
+
+    #[test]
+    fn test_parse_and_execute() {
+        let clause = parse(
+            "name NOT IN ('John', 'Jane')
+               AND age BETWEEN 18 AND 20
+               AND email IS NOT NULL
+               AND email LIKE '%@example.com'",
+        )
+        .unwrap();
+
+        let value = Value::from(vec![
+            ("name", Value::from("Ana")),
+            ("age", Value::from(19)),
+            ("email", Value::from("ana@example.com")),
+        ]);
+        assert!(clause.execute(&value).unwrap());
+
+        let value = Value::from(vec![
+            ("name", Value::from("Jane")),
+            ("age", Value::from(19)),
+            ("email", Value::from("jane@example.com")),
+        ]);
+        assert!(!clause.execute(&value).unwrap());
+    }
+
+    #[test]
+    fn test_parse_non_ascii_literal() {
+        let clause = parse("name = 'João' OR city IN ('Zürich', \"東京\")").unwrap();
+
+        let value = Value::from(vec![("name", Value::from("João"))]);
+        assert!(clause.execute(&value).unwrap());
+        let value = Value::from(vec![("city", Value::from("東京"))]);
+        assert!(clause.execute(&value).unwrap());
+        let value = Value::from(vec![("name", Value::from("João'"))]);
+        assert!(!clause.execute(&value).unwrap());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let error = |text: &str| parse(text).unwrap_err();
+
+        assert_eq!(
+            error("name = "),
+            ParseError {
+                kind: ParseErrorKind::Expected("a value".to_string(), "end of input".to_string()),
+                line: 1,
+                column: 8,
+            }
+        );
+        assert_eq!(
+            error("name = 'John'\n  AND age >> 3").to_string(),
+            "Expected a value, found '>' at line 2, column 12"
+        );
+        assert_eq!(
+            error("name = 'John").kind,
+            ParseErrorKind::UnterminatedString
+        );
+        assert_eq!(
+            error("name = #").kind,
+            ParseErrorKind::UnexpectedCharacter('#')
+        );
+        assert_eq!(
+            error("age = 1x").kind,
+            ParseErrorKind::InvalidNumber("1x".to_string())
+        );
+        assert_eq!(
+            error("(a = 1 OR b = 2").to_string(),
+            "Expected ')', found end of input at line 1, column 16"
+        );
+        assert_eq!(
+            error("a = 1 b = 2").to_string(),
+            "Expected AND, OR or the end of the clause, found b at line 1, column 7"
+        );
+        assert_eq!(
+            error("a BETWEEN 1 OR 2").to_string(),
+            "Expected AND, found OR at line 1, column 13"
+        );
+        assert_eq!(
+            error("a NOT = 1").to_string(),
+            "Expected LIKE, ILIKE, REGEX, CONTAINS, STARTS WITH, ENDS WITH, IN, BETWEEN or EXISTS, \
+             found '=' at line 1, column 7"
+        );
+        assert_eq!(
+            error("created > now() - interval '1 month'").to_string(),
+            "Invalid interval '1 month' at line 1, column 28"
+        );
+        assert_eq!(
+            error("lower(name = 'bob'").to_string(),
+            "Expected ')', found '=' at line 1, column 12"
+        );
+        assert_eq!(
+            error("AND = 1").to_string(),
+            "Expected a value, found AND at line 1, column 1"
+        );
+    }
+}
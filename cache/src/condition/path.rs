@@ -0,0 +1,317 @@
+//! Paths to nested attributes in the operands of a `Condition`.
+//!
+//! A path is a chain of attribute names separated by dots, each optionally followed by
+//! indexes in brackets: `address.city`, `tags[0]`, `orders[*].total`. Attribute names are made
+//! of letters, digits and underscores. A text that is not a valid path, such as
+//! `first name`, is the name of a single top-level attribute.
+//!
+//! `[*]` and `[all]` step into every element of an array. With `[*]` a condition holds when
+//! it holds for any element, and with `[all]` when it holds for all of them, so
+//! `orders[*].total > 100` matches a value with at least one order above 100 and
+//! `orders[all].total > 100` one where every order is. No element matches `[*]`, while all
+//! elements of an empty array match `[all]`. Quantifiers nest: `teams[*].members[all].active
+//! = true` matches when some team has only active members.
+//!
+//! Stepping into an attribute a value does not have, or indexing what is not an array, finds
+//! nothing, which conditions compare as `NULL`.
+
+use std::borrow::Cow;
+use std::fmt::{self, Display, Formatter};
+
+use valu3::prelude::*;
+
+use super::Error;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Segment {
+    Key(String),
+    Index(usize),
+    /// `[*]`, any element of an array.
+    Any,
+    /// `[all]`, every element of an array.
+    All,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Path {
+    segments: Vec<Segment>,
+}
+
+impl Path {
+    /// Parses a path, or takes the whole text as one attribute name when it is not a path.
+    pub fn new(text: &str) -> Self {
+        match Self::parse(text) {
+            Some(segments) => Self { segments },
+            None => Self {
+                segments: vec![Segment::Key(text.to_string())],
+            },
+        }
+    }
+
+    fn parse(text: &str) -> Option<Vec<Segment>> {
+        let mut segments = Vec::new();
+
+        for part in text.split('.') {
+            let (key, mut indexes) = match part.find('[') {
+                Some(position) => part.split_at(position),
+                None => (part, ""),
+            };
+
+            if key.is_empty() || !key.chars().all(|c| c.is_alphanumeric() || c == '_') {
+                return None;
+            }
+            segments.push(Segment::Key(key.to_string()));
+
+            while !indexes.is_empty() {
+                let end = indexes.find(']')?;
+                let segment = match &indexes[1..end] {
+                    "*" => Segment::Any,
+                    "all" => Segment::All,
+                    index => Segment::Index(index.parse().ok()?),
+                };
+                segments.push(segment);
+
+                indexes = &indexes[end + 1..];
+                if !indexes.is_empty() && !indexes.starts_with('[') {
+                    return None;
+                }
+            }
+        }
+
+        Some(segments)
+    }
+
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    /// Whether the path is a single top-level attribute name.
+    pub fn is_attribute(&self) -> bool {
+        matches!(self.segments.as_slice(), [Segment::Key(_)])
+    }
+
+    /// Whether the path steps into every element of an array.
+    pub fn is_quantified(&self) -> bool {
+        self.segments
+            .iter()
+            .any(|segment| matches!(segment, Segment::Any | Segment::All))
+    }
+
+    pub fn resolve<'a>(&self, value: &'a Value) -> Resolved<'a> {
+        Self::resolve_segments(&self.segments, value)
+    }
+
+    fn resolve_segments<'a>(segments: &[Segment], value: &'a Value) -> Resolved<'a> {
+        let (segment, rest) = match segments.split_first() {
+            Some(split) => split,
+            None => return Resolved::Value(Cow::Borrowed(value)),
+        };
+
+        let element = match segment {
+            Segment::Key(key) if value.is_object() => value.get(key.as_str()),
+            Segment::Index(index) if value.is_array() => value.get(*index),
+            Segment::Any | Segment::All => {
+                let elements = match value.as_array() {
+                    Some(array) => array
+                        .into_iter()
+                        .map(|element| Self::resolve_segments(rest, element))
+                        .collect(),
+                    None => return Resolved::Missing,
+                };
+
+                return match segment {
+                    Segment::Any => Resolved::Any(elements),
+                    _ => Resolved::All(elements),
+                };
+            }
+            _ => None,
+        };
+
+        match element {
+            Some(element) => Self::resolve_segments(rest, element),
+            None => Resolved::Missing,
+        }
+    }
+}
+
+impl Display for Path {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for (position, segment) in self.segments.iter().enumerate() {
+            match segment {
+                Segment::Key(key) if position == 0 => write!(f, "{}", key)?,
+                Segment::Key(key) => write!(f, ".{}", key)?,
+                Segment::Index(index) => write!(f, "[{}]", index)?,
+                Segment::Any => write!(f, "[*]")?,
+                Segment::All => write!(f, "[all]")?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// What a `Path` finds in a value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Resolved<'a> {
+    Value(Cow<'a, Value>),
+    Missing,
+    /// The elements stepped into by `[*]`.
+    Any(Vec<Resolved<'a>>),
+    /// The elements stepped into by `[all]`.
+    All(Vec<Resolved<'a>>),
+}
+
+impl Resolved<'_> {
+    /// Runs `f` on every value found, `None` for missing ones, and combines the results of the
+    /// elements of `Any` with `OR` and those of `All` with `AND`, in three-valued logic.
+    pub fn evaluate<F>(&self, f: &mut F) -> Result<Option<bool>, Error>
+    where
+        F: FnMut(Option<&Value>) -> Result<Option<bool>, Error>,
+    {
+        let (elements, short_circuit) = match self {
+            Resolved::Value(value) => return f(Some(value)),
+            Resolved::Missing => return f(None),
+            Resolved::Any(elements) => (elements, true),
+            Resolved::All(elements) => (elements, false),
+        };
+
+        let mut result = Some(!short_circuit);
+        for element in elements {
+            match element.evaluate(f)? {
+                Some(found) if found == short_circuit => return Ok(Some(found)),
+                Some(_) => {}
+                None => result = None,
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// The value found, with missing values as `NULL` and the elements of `Any` and `All` in
+    /// an array.
+    pub fn to_value(&self) -> Value {
+        match self {
+            Resolved::Value(value) => value.as_ref().clone(),
+            Resolved::Missing => Value::Null,
+            Resolved::Any(elements) | Resolved::All(elements) => elements
+                .iter()
+                .map(Resolved::to_value)
+                .collect::<Vec<_>>()
+                .to_value(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::condition::Clause;
+
+    fn order(total: i32) -> Value {
+        Value::from(vec![("total", total)])
+    }
+
+    fn value() -> Value {
+        Value::from(vec![
+            (
+                "address",
+                Value::from(vec![("city", "Lisbon"), ("country", "PT")]),
+            ),
+            ("tags", Value::from(vec!["a", "b"])),
+            ("orders", Value::from(vec![order(50), order(150)])),
+        ])
+    }
+
+    #[test]
+    fn test_path_parse() {
+        let path = Path::new("orders[*].items[0][all].price");
+        assert_eq!(
+            path.segments(),
+            &[
+                Segment::Key("orders".to_string()),
+                Segment::Any,
+                Segment::Key("items".to_string()),
+                Segment::Index(0),
+                Segment::All,
+                Segment::Key("price".to_string()),
+            ]
+        );
+        assert_eq!(path.to_string(), "orders[*].items[0][all].price");
+        assert!(path.is_quantified());
+
+        for text in ["A.*", "a..b", "a[", "a[x]", "a[0]b", "first name", ".a", ""] {
+            assert_eq!(
+                Path::new(text).segments(),
+                &[Segment::Key(text.to_string())]
+            );
+        }
+    }
+
+    #[test]
+    fn test_path_resolve() {
+        let value = value();
+        let resolve = |path: &str| Path::new(path).resolve(&value).to_value();
+
+        assert_eq!(resolve("address.city"), Value::from("Lisbon"));
+        assert_eq!(resolve("tags[1]"), Value::from("b"));
+        assert_eq!(resolve("orders[*].total"), Value::from(vec![50, 150]));
+        assert_eq!(resolve("tags[2]"), Value::Null);
+        assert_eq!(resolve("address.city.name"), Value::Null);
+        assert_eq!(resolve("address[0]"), Value::Null);
+        assert_eq!(
+            Path::new("tags[*].x").resolve(&value),
+            Resolved::Any(vec![Resolved::Missing; 2])
+        );
+        assert_eq!(Path::new("address[*]").resolve(&value), Resolved::Missing);
+    }
+
+    #[test]
+    fn test_path_conditions() {
+        let members = |active: &[bool]| {
+            let members: Vec<Value> = active
+                .iter()
+                .map(|active| Value::from(vec![("active", *active)]))
+                .collect();
+            Value::from(vec![("members", members.to_value())])
+        };
+        let value = Value::from(vec![
+            (
+                "address",
+                Value::from(vec![("city", "Lisbon"), ("country", "PT")]),
+            ),
+            ("tags", Value::from(vec!["a", "b"])),
+            ("codes", Value::from(vec!["ES", "PT"])),
+            ("orders", Value::from(vec![order(50), order(150)])),
+            ("empty", Value::from(Vec::<i32>::new())),
+            (
+                "teams",
+                Value::from(vec![members(&[true, false]), members(&[true])]),
+            ),
+        ]);
+
+        let conditions = [
+            ("address.city = 'Lisbon'", true),
+            ("tags[0] = 'a' AND tags[1] <> 'a'", true),
+            ("orders[*].total > 100", true),
+            ("orders[all].total > 100", false),
+            ("orders[all].total > 10", true),
+            ("NOT orders[*].total > 200", true),
+            ("tags[*] IN ('b', 'c')", true),
+            ("tags[all] LIKE '_'", true),
+            ("address.country = codes[*]", true),
+            ("address.country = codes[all]", false),
+            ("address.zip IS NULL", true),
+            ("address.zip = 'x' OR NOT address.zip = 'x'", false),
+            ("missing[*].total > 0", false),
+            ("empty[*] = 1", false),
+            ("empty[all] = 1", true),
+            ("teams[*].members[all].active = true", true),
+            ("teams[all].members[*].active = false", false),
+        ];
+
+        for (text, expected) in conditions {
+            let clause = Clause::parse(text).unwrap();
+            assert_eq!(clause.execute(&value).unwrap(), expected, "{}", text);
+        }
+    }
+}
@@ -0,0 +1,982 @@
+//! Executable plans of a `Clause`.
+//!
+//! `Clause::compile` checks a clause once and prepares it to run against many values: quoted
+//! strings and other literal operands are resolved, regular expressions and `LIKE` patterns
+//! are compiled, and the operands of `IN` and `BETWEEN` are checked. Operands naming an
+//! attribute, or a path to one, see `path`, are looked up in each value, and a pattern or list
+//! read from an attribute is only checked when the condition runs. A top-level attribute the
+//! value does not have is taken as a string literal of its name.
+//!
+//! Expression operands, see `expression`, are parsed when the clause is compiled, and the
+//! functions they call are looked up in the registry then and run on every value.
+//!
+//! Operands of different types are compared as described in `coerce`.
+//!
+//! `Exists` tells a missing attribute from one holding `NULL`, so its operand is never taken
+//! as a literal. The size of a value other than an array, an object or a string is unknown.
+//!
+//! A plan only reads the top-level attributes named by its operands, see `Plan::attributes`,
+//! so a `Document` can be converted to a value in those attributes alone.
+//!
+//! A `LIKE` pattern matches the whole string: `%` matches any sequence of characters, `_` any
+//! single character, and a backslash makes the character after it match literally.
+
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
+use std::fmt::{self, Debug, Formatter};
+
+use regex::Regex;
+use valu3::prelude::*;
+
+use super::coerce::Scalar;
+use super::explain::{Step, Trace};
+use super::expression::{marked_operand, ArithmeticOperator, Expression, ATTRIBUTE, EXPRESSION};
+use super::function::{self, Function};
+use super::path::{Path, Resolved, Segment};
+use super::{Clause, Condition, ConditionGroup, ConditionToken, Error, LogicalOperator, Operator};
+
+/// A `LIKE` pattern, compiled to a regular expression.
+#[derive(Debug, Clone)]
+pub struct LikePattern {
+    regex: Regex,
+}
+
+impl LikePattern {
+    pub fn new(pattern: &str) -> Result<Self, Error> {
+        Self::build(pattern, "(?s)^")
+    }
+
+    /// A pattern matching letters in any case, for `ILike`.
+    pub fn case_insensitive(pattern: &str) -> Result<Self, Error> {
+        Self::build(pattern, "(?si)^")
+    }
+
+    fn build(pattern: &str, prefix: &str) -> Result<Self, Error> {
+        let mut regex = String::from(prefix);
+        let mut chars = pattern.chars();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '%' => regex.push_str(".*"),
+                '_' => regex.push('.'),
+                '\\' => match chars.next() {
+                    Some(c) => regex.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+                    None => return Err(Error::InvalidLikePattern(pattern.to_string())),
+                },
+                c => regex.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+            }
+        }
+        regex.push('$');
+
+        match Regex::new(&regex) {
+            Ok(regex) => Ok(Self { regex }),
+            Err(err) => Err(Error::InvalidLikePattern(err.to_string())),
+        }
+    }
+
+    pub fn is_match(&self, value: &str) -> bool {
+        self.regex.is_match(value)
+    }
+}
+
+#[derive(Clone)]
+struct Call {
+    name: String,
+    function: Function,
+    arguments: Vec<Operand>,
+}
+
+impl Debug for Call {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("Call")
+            .field("name", &self.name)
+            .field("arguments", &self.arguments)
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Operand {
+    Literal(Value),
+    Attribute(Path),
+    Call(Call),
+    Arithmetic(Box<Operand>, ArithmeticOperator, Box<Operand>),
+}
+
+impl Operand {
+    fn new(operand: &Value) -> Result<Self, Error> {
+        if let Some(text) = marked_operand(operand, EXPRESSION) {
+            return match Expression::parse(text) {
+                Ok(expression) => Self::expression(expression),
+                Err(err) => Err(Error::InvalidExpression(err.to_string())),
+            };
+        }
+
+        if let Some(name) = marked_operand(operand, ATTRIBUTE) {
+            return Ok(Operand::Attribute(Path::new(name)));
+        }
+
+        if !operand.is_string() {
+            return Ok(Operand::Literal(operand.clone()));
+        }
+
+        match Clause::extract_sql_string(&operand.as_string()) {
+            Some(string) => Ok(Operand::Literal(Value::from(string))),
+            None => Ok(Operand::Attribute(Path::new(operand.as_str()))),
+        }
+    }
+
+    fn expression(expression: Expression) -> Result<Self, Error> {
+        match expression {
+            Expression::Literal(literal) => Ok(Operand::Literal(literal)),
+            Expression::Attribute(path) => Ok(Operand::Attribute(path)),
+            Expression::Interval(millis) => Ok(Operand::Literal(Value::from(millis))),
+            Expression::Call(name, arguments) => {
+                let function = match function::get(&name) {
+                    Some(function) => function,
+                    None => return Err(Error::FunctionNotFound(name)),
+                };
+                let arguments = arguments
+                    .into_iter()
+                    .map(Self::expression)
+                    .collect::<Result<_, _>>()?;
+
+                Ok(Operand::Call(Call {
+                    name,
+                    function,
+                    arguments,
+                }))
+            }
+            Expression::Arithmetic(left, operator, right) => Ok(Operand::Arithmetic(
+                Box::new(Self::expression(*left)?),
+                operator,
+                Box::new(Self::expression(*right)?),
+            )),
+        }
+    }
+
+    fn resolve<'a>(&'a self, value: &'a Value) -> Result<Resolved<'a>, Error> {
+        match self {
+            Operand::Attribute(path) if path.is_attribute() => match self.find(value)? {
+                Resolved::Missing => Ok(Resolved::Value(Cow::Owned(Value::from(path.to_string())))),
+                resolved => Ok(resolved),
+            },
+            _ => self.find(value),
+        }
+    }
+
+    /// Adds the top-level attributes the operand reads to `names`.
+    fn attributes<'a>(&'a self, names: &mut BTreeSet<&'a str>) {
+        match self {
+            Operand::Literal(_) => {}
+            Operand::Attribute(path) => {
+                if let Some(Segment::Key(name)) = path.segments().first() {
+                    names.insert(name);
+                }
+            }
+            Operand::Call(call) => {
+                for argument in &call.arguments {
+                    argument.attributes(names);
+                }
+            }
+            Operand::Arithmetic(left, _, right) => {
+                left.attributes(names);
+                right.attributes(names);
+            }
+        }
+    }
+
+    /// Like `resolve`, with missing attributes left missing.
+    fn find<'a>(&'a self, value: &'a Value) -> Result<Resolved<'a>, Error> {
+        match self {
+            Operand::Literal(literal) => Ok(Resolved::Value(Cow::Borrowed(literal))),
+            Operand::Attribute(path) => Ok(path.resolve(value)),
+            _ => Ok(Resolved::Value(Cow::Owned(self.compute(value)?))),
+        }
+    }
+
+    /// The value of the operand as an argument of a function or of arithmetic, where a missing
+    /// attribute is `NULL`.
+    fn compute(&self, value: &Value) -> Result<Value, Error> {
+        match self {
+            Operand::Literal(literal) => Ok(literal.clone()),
+            Operand::Attribute(path) => Ok(path.resolve(value).to_value()),
+            Operand::Call(call) => {
+                let arguments = call
+                    .arguments
+                    .iter()
+                    .map(|argument| argument.compute(value))
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                (call.function)(&arguments)
+            }
+            Operand::Arithmetic(left, operator, right) => {
+                operator.apply(&left.compute(value)?, &right.compute(value)?)
+            }
+        }
+    }
+}
+
+/// The right operand of a condition, prepared for its operator.
+#[derive(Debug, Clone)]
+enum Matcher {
+    /// The right operand is `NULL`, so the condition is unknown.
+    Null,
+    /// `IsNull` and `IsNotNull` have no right operand.
+    None,
+    Value(Scalar),
+    Like(LikePattern),
+    Regex(Regex),
+    /// The prefix or suffix of `StartsWith` and `EndsWith`.
+    Text(String),
+    Size(f64),
+    /// The values of the list, with whether one of them is `NULL`.
+    List(Vec<Scalar>, bool),
+    Range(Scalar, Scalar),
+}
+
+impl Matcher {
+    fn new(operator: &Operator, right: &Value) -> Result<Self, Error> {
+        if matches!(
+            operator,
+            Operator::IsNull | Operator::IsNotNull | Operator::Exists | Operator::NotExists
+        ) {
+            return Ok(Matcher::None);
+        }
+
+        if right.is_null() {
+            return Ok(Matcher::Null);
+        }
+
+        match operator {
+            Operator::Like | Operator::NotLike => match right.as_string_b() {
+                Some(pattern) => Ok(Matcher::Like(LikePattern::new(pattern.as_str())?)),
+                None => Err(Error::RightConditionNotString),
+            },
+            Operator::ILike | Operator::NotILike => match right.as_string_b() {
+                Some(pattern) => Ok(Matcher::Like(LikePattern::case_insensitive(
+                    pattern.as_str(),
+                )?)),
+                None => Err(Error::RightConditionNotString),
+            },
+            Operator::StartsWith
+            | Operator::NotStartsWith
+            | Operator::EndsWith
+            | Operator::NotEndsWith => match right.as_string_b() {
+                Some(text) => Ok(Matcher::Text(text.as_string())),
+                None => Err(Error::RightConditionNotString),
+            },
+            Operator::SizeEq | Operator::SizeNe | Operator::SizeGt | Operator::SizeLe => {
+                match f64::from_value(right.clone()) {
+                    Some(size) if right.is_number() => Ok(Matcher::Size(size)),
+                    _ => Err(Error::RightConditionNotNumber),
+                }
+            }
+            Operator::Regex | Operator::NotRegex => match right.as_string_b() {
+                Some(pattern) => match Regex::new(pattern.as_str()) {
+                    Ok(regex) => Ok(Matcher::Regex(regex)),
+                    Err(err) => Err(Error::InvalidRegex(err.to_string())),
+                },
+                None => Err(Error::RightConditionNotString),
+            },
+            Operator::In | Operator::NotIn => {
+                let values = match right.as_array() {
+                    Some(values) => values,
+                    None => return Err(Error::RightConditionNotString),
+                };
+
+                let list = values.into_iter().filter_map(Scalar::new).collect();
+                let has_null = values.into_iter().any(Value::is_null);
+
+                Ok(Matcher::List(list, has_null))
+            }
+            Operator::Between | Operator::NotBetween => {
+                match (right.as_array(), right.get(0), right.get(1)) {
+                    (Some(values), Some(low), Some(high)) if values.len() == 2 => {
+                        match (Scalar::new(low), Scalar::new(high)) {
+                            (Some(low), Some(high)) => Ok(Matcher::Range(low, high)),
+                            _ => Ok(Matcher::Null),
+                        }
+                    }
+                    _ => Err(Error::BetweenConditionInvalid),
+                }
+            }
+            _ => match Scalar::new(right) {
+                Some(right) => Ok(Matcher::Value(right)),
+                None => Ok(Matcher::Null),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct CompiledCondition {
+    /// The condition it was compiled from, for `explain`.
+    condition: Condition,
+    operator: Operator,
+    left: Operand,
+    right: Operand,
+    /// The matcher of a literal right operand.
+    matcher: Option<Matcher>,
+}
+
+impl CompiledCondition {
+    fn new(condition: &Condition) -> Result<Self, Error> {
+        let right = Operand::new(&condition.right)?;
+        let matcher = match &right {
+            Operand::Literal(literal) => Some(Matcher::new(&condition.operator, literal)?),
+            _ => None,
+        };
+
+        Ok(Self {
+            condition: condition.clone(),
+            operator: condition.operator.clone(),
+            left: Operand::new(&condition.left)?,
+            right,
+            matcher,
+        })
+    }
+
+    fn resolve_left<'a>(&'a self, value: &'a Value) -> Result<Resolved<'a>, Error> {
+        match self.operator {
+            Operator::Exists | Operator::NotExists => self.left.find(value),
+            _ => self.left.resolve(value),
+        }
+    }
+
+    /// Runs the condition on every value found by its operands, see `Resolved::evaluate`.
+    fn evaluate(&self, value: &Value, strict: bool) -> Result<Option<bool>, Error> {
+        let left = self.resolve_left(value)?;
+
+        match &self.matcher {
+            Some(matcher) => left.evaluate(&mut |left| self.matches(left, matcher, strict)),
+            None => {
+                let right = self.right.resolve(value)?;
+                left.evaluate(&mut |left| {
+                    right.evaluate(&mut |right| {
+                        let null = Value::Null;
+                        let matcher = Matcher::new(&self.operator, right.unwrap_or(&null))?;
+                        self.matches(left, &matcher, strict)
+                    })
+                })
+            }
+        }
+    }
+
+    fn explain(&self, value: &Value, strict: bool) -> Trace {
+        Trace {
+            step: Step::Condition {
+                condition: self.condition.clone(),
+                left: self.resolve_left(value).map(|left| left.to_value()),
+                right: self.right.resolve(value).map(|right| right.to_value()),
+            },
+            result: self.evaluate(value, strict),
+        }
+    }
+
+    /// Missing values are compared as `NULL`. Operands that cannot be compared fail when
+    /// `strict`, see `coerce`.
+    fn matches(
+        &self,
+        left: Option<&Value>,
+        matcher: &Matcher,
+        strict: bool,
+    ) -> Result<Option<bool>, Error> {
+        let exists = left.is_some();
+        let null = Value::Null;
+        let left = left.unwrap_or(&null);
+
+        if let Matcher::None = matcher {
+            return Ok(Some(match self.operator {
+                Operator::IsNotNull => !left.is_null(),
+                Operator::Exists => exists,
+                Operator::NotExists => !exists,
+                _ => left.is_null(),
+            }));
+        }
+
+        let scalar = match Scalar::new(left) {
+            Some(scalar) => scalar,
+            None => return Ok(None),
+        };
+        let incomparable = |right: &Scalar| {
+            if strict {
+                Err(Error::IncomparableTypes(scalar.kind(), right.kind()))
+            } else {
+                Ok(())
+            }
+        };
+
+        let result = match matcher {
+            Matcher::None | Matcher::Null => None,
+            Matcher::Value(right)
+                if matches!(self.operator, Operator::Contains | Operator::NotContains) =>
+            {
+                match left.as_array() {
+                    Some(elements) => {
+                        let values: Vec<Scalar> =
+                            elements.into_iter().filter_map(Scalar::new).collect();
+                        let has_null = elements.into_iter().any(Value::is_null);
+                        Self::find(right, &values, has_null, strict)?
+                    }
+                    None => match right {
+                        Scalar::String(text) | Scalar::Date(_, text) => {
+                            Some(Self::string(left)?.contains(text.as_str()))
+                        }
+                        _ => return Err(Error::RightConditionNotString),
+                    },
+                }
+            }
+            Matcher::Value(right) => {
+                let result = match self.operator {
+                    Operator::Equal | Operator::NotEqual => scalar
+                        .equals(right)
+                        .map(|equal| equal == (self.operator == Operator::Equal)),
+                    _ => scalar.compare(right).map(|ordering| match self.operator {
+                        Operator::GreaterThan => ordering == Ordering::Greater,
+                        Operator::GreaterThanOrEqual => ordering != Ordering::Less,
+                        Operator::LessThan => ordering == Ordering::Less,
+                        _ => ordering != Ordering::Greater,
+                    }),
+                };
+
+                match result {
+                    Some(result) => Some(result),
+                    None => {
+                        incomparable(right)?;
+                        Some(self.operator == Operator::NotEqual)
+                    }
+                }
+            }
+            Matcher::Like(pattern) => Some(pattern.is_match(Self::string(left)?)),
+            Matcher::Regex(regex) => Some(regex.is_match(Self::string(left)?)),
+            Matcher::Text(text) => match self.operator {
+                Operator::StartsWith | Operator::NotStartsWith => {
+                    Some(Self::string(left)?.starts_with(text.as_str()))
+                }
+                _ => Some(Self::string(left)?.ends_with(text.as_str())),
+            },
+            Matcher::Size(size) => {
+                let length = match left {
+                    Value::Array(_) | Value::Object(_) => Some(left.len()),
+                    Value::String(_) => Some(left.as_str().chars().count()),
+                    _ => None,
+                };
+
+                length.map(|length| match self.operator {
+                    Operator::SizeEq | Operator::SizeNe => length as f64 == *size,
+                    _ => length as f64 > *size,
+                })
+            }
+            Matcher::List(values, has_null) => Self::find(&scalar, values, *has_null, strict)?,
+            Matcher::Range(low, high) => match (scalar.compare(low), scalar.compare(high)) {
+                (Some(from), Some(to)) => Some(from != Ordering::Less && to != Ordering::Greater),
+                (None, _) => {
+                    incomparable(low)?;
+                    Some(false)
+                }
+                (_, None) => {
+                    incomparable(high)?;
+                    Some(false)
+                }
+            },
+        };
+
+        match self.operator {
+            Operator::NotLike
+            | Operator::NotIn
+            | Operator::NotBetween
+            | Operator::NotRegex
+            | Operator::NotContains
+            | Operator::NotStartsWith
+            | Operator::NotEndsWith
+            | Operator::NotILike
+            | Operator::SizeNe
+            | Operator::SizeLe => Ok(result.map(|result| !result)),
+            _ => Ok(result),
+        }
+    }
+
+    /// Whether `value` equals one of `values`, unknown when none does and `has_null`.
+    fn find(
+        value: &Scalar,
+        values: &[Scalar],
+        has_null: bool,
+        strict: bool,
+    ) -> Result<Option<bool>, Error> {
+        for other in values {
+            match value.equals(other) {
+                Some(true) => return Ok(Some(true)),
+                Some(false) => {}
+                None if strict => return Err(Error::IncomparableTypes(value.kind(), other.kind())),
+                None => {}
+            }
+        }
+
+        Ok(if has_null { None } else { Some(false) })
+    }
+
+    fn string(value: &Value) -> Result<&str, Error> {
+        match value.as_string_b() {
+            Some(string) => Ok(string.as_str()),
+            None => Err(Error::LeftConditionNotString),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Condition(Box<CompiledCondition>),
+    Not(Box<Node>),
+    And(Vec<Node>),
+    Or(Vec<Node>),
+}
+
+impl Node {
+    /// `Not` binds tighter than `And`, which binds tighter than `Or`.
+    fn group(condition_group: &ConditionGroup) -> Result<Self, Error> {
+        let mut or = Vec::new();
+
+        for tokens in condition_group
+            .conditions
+            .split(|token| matches!(token, ConditionToken::LogicalOperator(LogicalOperator::Or)))
+        {
+            let mut and = Vec::new();
+
+            for tokens in tokens.split(|token| {
+                matches!(token, ConditionToken::LogicalOperator(LogicalOperator::And))
+            }) {
+                and.push(Self::not(tokens)?);
+            }
+
+            or.push(Self::flatten(and, Node::And));
+        }
+
+        Ok(Self::flatten(or, Node::Or))
+    }
+
+    fn not(tokens: &[ConditionToken]) -> Result<Self, Error> {
+        match tokens {
+            [ConditionToken::LogicalOperator(LogicalOperator::Not), tokens @ ..] => {
+                Ok(Node::Not(Box::new(Self::not(tokens)?)))
+            }
+            [ConditionToken::Condition(condition)] => Self::condition(condition),
+            [ConditionToken::ConditionGroup(condition_group)] => Self::group(condition_group),
+            _ => Err(Error::ConditionGroupInvalid),
+        }
+    }
+
+    fn condition(condition: &Condition) -> Result<Self, Error> {
+        Ok(Node::Condition(Box::new(CompiledCondition::new(
+            condition,
+        )?)))
+    }
+
+    fn flatten(mut nodes: Vec<Node>, group: fn(Vec<Node>) -> Node) -> Node {
+        if nodes.len() == 1 {
+            nodes.remove(0)
+        } else {
+            group(nodes)
+        }
+    }
+
+    /// Operands are evaluated from left to right until the result is known, so the remaining
+    /// ones cannot fail.
+    fn evaluate(&self, value: &Value, strict: bool) -> Result<Option<bool>, Error> {
+        match self {
+            Node::Condition(condition) => condition.evaluate(value, strict),
+            Node::Not(node) => Ok(node.evaluate(value, strict)?.map(|result| !result)),
+            Node::And(nodes) => {
+                let mut result = Some(true);
+                for node in nodes {
+                    match node.evaluate(value, strict)? {
+                        Some(false) => return Ok(Some(false)),
+                        Some(true) => {}
+                        None => result = None,
+                    }
+                }
+                Ok(result)
+            }
+            Node::Or(nodes) => {
+                let mut result = Some(false);
+                for node in nodes {
+                    match node.evaluate(value, strict)? {
+                        Some(true) => return Ok(Some(true)),
+                        Some(false) => {}
+                        None => result = None,
+                    }
+                }
+                Ok(result)
+            }
+        }
+    }
+
+    fn attributes<'a>(&'a self, names: &mut BTreeSet<&'a str>) {
+        match self {
+            Node::Condition(condition) => {
+                condition.left.attributes(names);
+                condition.right.attributes(names);
+            }
+            Node::Not(node) => node.attributes(names),
+            Node::And(nodes) | Node::Or(nodes) => {
+                for node in nodes {
+                    node.attributes(names);
+                }
+            }
+        }
+    }
+
+    /// Traces every operand of a group, with the result of the group being the one of
+    /// `evaluate`.
+    fn explain(&self, value: &Value, strict: bool) -> Trace {
+        let step = match self {
+            Node::Condition(condition) => return condition.explain(value, strict),
+            Node::Not(node) => Step::Not(Box::new(node.explain(value, strict))),
+            Node::And(nodes) => Step::And(Self::explain_all(nodes, value, strict)),
+            Node::Or(nodes) => Step::Or(Self::explain_all(nodes, value, strict)),
+        };
+
+        Trace {
+            step,
+            result: self.evaluate(value, strict),
+        }
+    }
+
+    fn explain_all(nodes: &[Node], value: &Value, strict: bool) -> Vec<Trace> {
+        nodes
+            .iter()
+            .map(|node| node.explain(value, strict))
+            .collect()
+    }
+}
+
+/// A compiled `Clause`, see `Clause::compile`.
+#[derive(Debug, Clone)]
+pub struct Plan {
+    root: Node,
+    strict: bool,
+}
+
+impl Plan {
+    pub fn new(clause: &Clause) -> Result<Self, Error> {
+        let root = match clause {
+            Clause::ConditionGroup(condition_group) => Node::group(condition_group)?,
+            Clause::Condition(condition) => Node::condition(condition)?,
+        };
+
+        Ok(Self {
+            root,
+            strict: false,
+        })
+    }
+
+    pub(super) fn condition(condition: &Condition) -> Result<Self, Error> {
+        Ok(Self {
+            root: Node::condition(condition)?,
+            strict: false,
+        })
+    }
+
+    /// Makes comparisons of operands of incomparable types, see `coerce`, fail with
+    /// `Error::IncomparableTypes` instead of being false.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Whether `value` matches the plan. An unknown result, see `evaluate`, does not match.
+    pub fn execute(&self, value: &Value) -> Result<bool, Error> {
+        Ok(self.evaluate(value)? == Some(true))
+    }
+
+    /// Evaluates the plan with SQL three-valued logic, `None` being unknown.
+    pub fn evaluate(&self, value: &Value) -> Result<Option<bool>, Error> {
+        self.root.evaluate(value, self.strict)
+    }
+
+    /// The top-level attributes the operands of the plan read, the value it runs against can
+    /// leave out any other.
+    pub fn attributes(&self) -> BTreeSet<&str> {
+        let mut names = BTreeSet::new();
+        self.root.attributes(&mut names);
+        names
+    }
+
+    /// Evaluates the plan and traces how, see `explain`.
+    pub fn explain(&self, value: &Value) -> Trace {
+        self.root.explain(value, self.strict)
+    }
+}
+
+/// What a `Plan` can run against, converted to a value in only the top-level attributes it
+/// reads, see `Plan::attributes`.
+pub trait Document {
+    fn to_document(&self, attributes: &BTreeSet<&str>) -> Value;
+}
+
+impl<T> Document for T
+where
+    T: ToValueBehavior,
+{
+    fn to_document(&self, _attributes: &BTreeSet<&str>) -> Value {
+        self.to_value()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::condition::coerce::format_date;
+    use crate::condition::expression::expression_operand;
+    use crate::sql_string;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    #[test]
+    fn test_like_pattern() {
+        let is_match =
+            |pattern: &str, value: &str| LikePattern::new(pattern).unwrap().is_match(value);
+
+        assert!(is_match("J%", "John"));
+        assert!(is_match("%n", "John"));
+        assert!(is_match("J%n", "John"));
+        assert!(is_match("J_hn", "John"));
+        assert!(is_match("%", ""));
+        assert!(is_match("a.c%", "a.cd\ne"));
+        assert!(is_match("100\\%", "100%"));
+        assert!(is_match("a\\_c", "a_c"));
+        assert!(is_match("a\\\\c", "a\\c"));
+
+        assert!(!is_match("J_n", "John"));
+        assert!(!is_match("a.c", "abc"));
+        assert!(!is_match("100\\%", "1000"));
+        assert!(!is_match("a\\_c", "abc"));
+        assert!(!is_match("john", "John"));
+
+        assert!(matches!(
+            LikePattern::new("abc\\"),
+            Err(Error::InvalidLikePattern(_))
+        ));
+    }
+
+    #[test]
+    fn test_compile_errors() {
+        let compile = |operator: Operator, right: Value| {
+            Clause::condition(operator, "name", right)
+                .compile()
+                .unwrap_err()
+        };
+
+        assert!(matches!(
+            compile(Operator::Regex, Value::from(sql_string!("(a"))),
+            Error::InvalidRegex(_)
+        ));
+        assert!(matches!(
+            compile(Operator::NotLike, Value::from(sql_string!("a\\"))),
+            Error::InvalidLikePattern(_)
+        ));
+        assert!(matches!(
+            compile(Operator::Between, Value::from(vec![1])),
+            Error::BetweenConditionInvalid
+        ));
+        assert!(matches!(
+            compile(Operator::In, Value::from(1)),
+            Error::RightConditionNotString
+        ));
+        assert!(matches!(
+            Clause::group(vec![]).compile(),
+            Err(Error::ConditionGroupInvalid)
+        ));
+
+        // a pattern read from the value is only checked when the condition runs
+        let clause = Clause::condition(Operator::Regex, "name", "pattern");
+        let plan = clause.compile().unwrap();
+        let value = Value::from(vec![("name", "John"), ("pattern", "(a")]);
+        assert!(matches!(plan.execute(&value), Err(Error::InvalidRegex(_))));
+        let value = Value::from(vec![("name", "John"), ("pattern", "^J")]);
+        assert!(plan.execute(&value).unwrap());
+    }
+
+    // Clause: name LIKE 'J_n%' AND status IN ('open', 'closed') AND name REGEX '^J'
+    #[test]
+    fn test_plan_execute() {
+        let clause =
+            Clause::parse("name LIKE 'J_n%' AND status IN ('open', 'closed') AND name REGEX '^J'")
+                .unwrap();
+        let plan = clause.compile().unwrap();
+
+        let values = [
+            (vec![("name", "Jane"), ("status", "open")], true),
+            (vec![("name", "Jenny"), ("status", "closed")], true),
+            (vec![("name", "John"), ("status", "open")], false),
+            (vec![("name", "Jane"), ("status", "draft")], false),
+        ];
+
+        for (value, expected) in values {
+            let value = Value::from(value);
+            assert_eq!(plan.execute(&value).unwrap(), expected);
+            assert_eq!(clause.execute(&value).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_plan_non_ascii_literal() {
+        let clause = Clause::ConditionGroup(ConditionGroup {
+            conditions: vec![
+                ConditionToken::Condition(Condition::new(
+                    Operator::Equal,
+                    "city",
+                    sql_string!("São Paulo"),
+                )),
+                ConditionToken::LogicalOperator(LogicalOperator::Or),
+                ConditionToken::Condition(Condition::new(
+                    Operator::StartsWith,
+                    "name",
+                    sql_string!("Joã"),
+                )),
+            ],
+        });
+        let plan = clause.compile().unwrap();
+
+        let values = [
+            (vec![("city", "São Paulo"), ("name", "Ana")], true),
+            (vec![("city", "Lisbon"), ("name", "João")], true),
+            (vec![("city", "São Paulo'"), ("name", "Jo")], false),
+        ];
+
+        for (value, expected) in values {
+            let value = Value::from(value);
+            assert_eq!(plan.execute(&value).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_plan_plain_operands() {
+        let value = Value::from(vec![
+            ("date", Value::from("2020-01-01")),
+            ("created-at", Value::from(5)),
+            ("user", Value::from(2)),
+            ("id", Value::from(1)),
+        ]);
+
+        let clause = Clause::condition(Operator::Equal, "date", "2020-01-01");
+        assert!(clause.execute(&value).unwrap());
+
+        let clause = Clause::condition(Operator::Equal, "created-at", 5);
+        assert!(clause.execute(&value).unwrap());
+        let clause = Clause::condition(Operator::Equal, "user-id", 1);
+        assert!(!clause.execute(&value).unwrap());
+        assert_eq!(
+            clause.compile().unwrap().attributes(),
+            ["user-id"].into_iter().collect()
+        );
+    }
+
+    #[test]
+    fn test_plan_coercion() {
+        let value = Value::from(vec![
+            ("age", Value::from(20)),
+            ("score", Value::from("7.5")),
+            ("created", Value::from("2020-06-01T12:00:00+02:00")),
+            ("active", Value::from(true)),
+        ]);
+
+        let conditions = [
+            ("age = 20.0", true),
+            ("age = '20'", true),
+            ("score > 7", true),
+            ("age IN (18, '20', 'x')", true),
+            ("age NOT IN (1, 2)", true),
+            ("age BETWEEN 18.5 AND '21'", true),
+            ("score BETWEEN 1 AND 5", false),
+            ("created = '2020-06-01T10:00:00Z'", true),
+            ("created > '2020-06-01'", true),
+            (
+                "created BETWEEN '2020-01-01' AND '2020-12-31T23:59:59.999Z'",
+                true,
+            ),
+            ("created IN ('2020-06-01 10:00', '2021-01-01')", true),
+            ("active = 'true'", true),
+            ("age = 'twenty'", false),
+            ("age <> 'twenty'", true),
+            ("age > active", false),
+            ("age IN ('twenty', NULL)", false),
+        ];
+
+        for (text, expected) in conditions {
+            let plan = Clause::parse(text).unwrap().compile().unwrap();
+            assert_eq!(plan.execute(&value).unwrap(), expected, "{}", text);
+        }
+
+        let strict = |text: &str| {
+            let plan = Clause::parse(text).unwrap().compile().unwrap();
+            plan.strict(true).evaluate(&value)
+        };
+
+        assert!(matches!(
+            strict("age = 'twenty'"),
+            Err(Error::IncomparableTypes("number", "string"))
+        ));
+        assert!(matches!(
+            strict("active BETWEEN 1 AND 2"),
+            Err(Error::IncomparableTypes("boolean", "number"))
+        ));
+        assert!(matches!(
+            strict("created IN ('x', true)"),
+            Err(Error::IncomparableTypes("date", "boolean"))
+        ));
+        assert_eq!(strict("age IN (20, 'twenty')").unwrap(), Some(true));
+        assert_eq!(strict("missing = 'twenty'").unwrap(), Some(false));
+        assert_eq!(strict("age = NULL").unwrap(), None);
+    }
+
+    #[test]
+    fn test_plan_functions() {
+        let day = 86_400_000;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as i64;
+        let value = Value::from(vec![
+            ("name", Value::from("Bob")),
+            ("nickname", Value::Null),
+            ("tags", Value::from(vec!["a", "b", "c"])),
+            ("createdAt", Value::from(format_date(now - 3 * day))),
+            ("updatedAt", Value::from(format_date(now - 10 * day))),
+        ]);
+
+        let conditions = [
+            ("lower(name) = 'bob'", true),
+            ("upper(name) = name", false),
+            ("length(tags) > 2", true),
+            ("length(name) + 1 = 4", true),
+            ("createdAt > now() - interval '7 days'", true),
+            ("updatedAt > now() - interval '1 week'", false),
+            ("createdAt - updatedAt = interval '7 days'", true),
+            ("coalesce(nickname, name) = 'Bob'", true),
+            ("coalesce(nickname, missing) IS NULL", true),
+            ("lower(missing) = 'missing'", false),
+        ];
+
+        for (text, expected) in conditions {
+            let plan = Clause::parse(text).unwrap().compile().unwrap();
+            assert_eq!(plan.execute(&value).unwrap(), expected, "{}", text);
+        }
+
+        let clause = Clause::condition(
+            Operator::Equal,
+            expression_operand("lower(name)"),
+            sql_string!("bob"),
+        );
+        assert!(clause.execute(&value).unwrap());
+
+        let clause = Clause::condition(Operator::Equal, expression_operand("lower("), 1);
+        assert!(matches!(clause.compile(), Err(Error::InvalidExpression(_))));
+
+        let clause = Clause::parse("length(tags) - name > 1").unwrap();
+        assert!(matches!(
+            clause.execute(&value),
+            Err(Error::ArithmeticInvalid("number", "string"))
+        ));
+    }
+}
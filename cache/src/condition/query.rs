@@ -0,0 +1,618 @@
+//! Conversion between a `Clause` and a JSON query document, in the style of MongoDB filters.
+//!
+//! ```text
+//! { "age": { "$gt": 30 }, "$or": [{ "status": "a" }, { "status": "b" }] }
+//! ```
+//!
+//! Every field of a document is a condition, and they must all hold. A field names an
+//! attribute, or a path to one, see `path`, whatever its text, and holds either the value the attribute equals or
+//! a document of operators:
+//!
+//! | operator                          | condition                           |
+//! |-----------------------------------|-------------------------------------|
+//! | `$eq`, `$ne`                      | `=`, `<>`, or `IS [NOT] NULL` with `null` |
+//! | `$gt`, `$gte`, `$lt`, `$lte`      | `>`, `>=`, `<`, `<=`                |
+//! | `$in`, `$nin`                     | `[NOT] IN`, with an array           |
+//! | `$between`                        | `BETWEEN`, with an array of two values |
+//! | `$like`, `$ilike`, `$regex`       | `LIKE`, `ILIKE`, `REGEX`            |
+//! | `$contains`, `$startsWith`, `$endsWith` | `CONTAINS`, `STARTS WITH`, `ENDS WITH` |
+//! | `$exists`                         | `EXISTS` with `true`, `NOT EXISTS` with `false` |
+//! | `$size`, `$sizeGt`                | `SIZE() =`, `SIZE() >`              |
+//! | `$not`                            | the operators of the document it holds, negated |
+//!
+//! `$and` and `$or` hold an array of documents, and `$nor` one of documents that must all be
+//! false. A value `{ "$field": "name" }` is the attribute `name` rather than a literal, and
+//! `{ "$expression": "now() - interval '7 days'" }` is an expression, see `expression`.
+//! A condition whose left operand is an expression has no document form.
+//!
+//! The fields of a document are taken in key order. Writing a clause as a document keeps the
+//! order of its conditions, so a clause read from a document is written back as the same
+//! document, and a clause written by the parser, see `parser`, is read back as the same clause.
+
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+
+use valu3::prelude::*;
+
+use super::expression::{attribute_operand, marked_operand, ATTRIBUTE, EXPRESSION};
+use super::parser::Expr;
+use super::{Clause, Condition, ConditionToken, LogicalOperator, Operator};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueryError {
+    /// A document, or an array of documents, was expected where the value, as JSON, is.
+    NotADocument(String),
+    Empty,
+    UnknownOperator(String),
+    /// The operator or field whose value is invalid.
+    InvalidOperand(String),
+    /// A part of the clause that has no document form.
+    Unsupported(String),
+}
+
+impl Display for QueryError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            QueryError::NotADocument(value) => {
+                write!(f, "Expected a query document, found {}", value)
+            }
+            QueryError::Empty => write!(f, "Empty query document"),
+            QueryError::UnknownOperator(operator) => {
+                write!(f, "Unknown query operator {}", operator)
+            }
+            QueryError::InvalidOperand(key) => write!(f, "Invalid operand for {}", key),
+            QueryError::Unsupported(part) => {
+                write!(f, "Clause cannot be written as a query document: {}", part)
+            }
+        }
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+/// The fields of a document, in order.
+type Entries = Vec<(String, Value)>;
+
+const FIELD: &str = "$field";
+
+fn object(entries: Entries) -> Value {
+    Value::from(entries.into_iter().collect::<HashMap<String, Value>>())
+}
+
+fn entries(document: &Value) -> Result<Vec<(String, &Value)>, QueryError> {
+    let object = match document.as_object() {
+        Some(object) => object,
+        None => return Err(QueryError::NotADocument(document.to_json(JsonMode::Inline))),
+    };
+
+    let mut entries: Vec<(String, &Value)> = object
+        .iter()
+        .map(|(key, value)| (key.to_string(), value))
+        .collect();
+    entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    Ok(entries)
+}
+
+/// Reads a query document.
+pub fn from_query(document: &Value) -> Result<Clause, QueryError> {
+    Ok(read_document(document)?.into_clause())
+}
+
+fn read_document(document: &Value) -> Result<Expr, QueryError> {
+    let entries = entries(document)?;
+    if entries.is_empty() {
+        return Err(QueryError::Empty);
+    }
+
+    let mut operands = Vec::new();
+    for (key, value) in entries {
+        match key.as_str() {
+            "$and" => operands.push(Expr::group(
+                LogicalOperator::And,
+                read_documents(&key, value)?,
+            )),
+            "$or" => operands.push(Expr::group(
+                LogicalOperator::Or,
+                read_documents(&key, value)?,
+            )),
+            "$nor" => operands.push(Expr::Not(Box::new(Expr::group(
+                LogicalOperator::Or,
+                read_documents(&key, value)?,
+            )))),
+            _ if key.starts_with('$') => return Err(QueryError::UnknownOperator(key)),
+            _ => operands.extend(read_field(&key, value)?),
+        }
+    }
+
+    Ok(Expr::group(LogicalOperator::And, operands))
+}
+
+fn read_documents(key: &str, documents: &Value) -> Result<Vec<Expr>, QueryError> {
+    match documents.as_array() {
+        Some(documents) if !documents.is_empty() => {
+            documents.into_iter().map(read_document).collect()
+        }
+        _ => Err(QueryError::InvalidOperand(key.to_string())),
+    }
+}
+
+/// Whether `value` is a document of operators, rather than a literal.
+fn is_operators(value: &Value) -> bool {
+    match value.as_object() {
+        Some(object) => object
+            .iter()
+            .any(|(key, _)| key.to_string().starts_with('$')),
+        None => false,
+    }
+}
+
+fn read_field(field: &str, value: &Value) -> Result<Vec<Expr>, QueryError> {
+    if !is_operators(value) || is_operand(value) {
+        let left = attribute_operand(field);
+        let condition = match value {
+            Value::Null => Condition::new(Operator::IsNull, left, Value::Null),
+            _ => Condition::new(Operator::Equal, left, read_literal(value)),
+        };
+        return Ok(vec![Expr::Condition(condition)]);
+    }
+
+    let mut conditions = Vec::new();
+    for (operator, operand) in entries(value)? {
+        if !operator.starts_with('$') {
+            return Err(QueryError::InvalidOperand(field.to_string()));
+        }
+
+        if operator == "$not" {
+            if !is_operators(operand) {
+                return Err(QueryError::InvalidOperand(operator));
+            }
+
+            let mut negated = read_field(field, operand)?;
+            conditions.push(match negated.as_slice() {
+                [Expr::Condition(_)] => match negated.remove(0) {
+                    Expr::Condition(condition) => Expr::Condition(Condition::new(
+                        condition.operator.negate(),
+                        condition.left,
+                        condition.right,
+                    )),
+                    expr => expr,
+                },
+                _ => Expr::Not(Box::new(Expr::group(LogicalOperator::And, negated))),
+            });
+        } else {
+            conditions.push(Expr::Condition(read_operator(field, &operator, operand)?));
+        }
+    }
+
+    Ok(conditions)
+}
+
+fn read_operator(field: &str, key: &str, operand: &Value) -> Result<Condition, QueryError> {
+    let invalid = || QueryError::InvalidOperand(key.to_string());
+
+    let (operator, right) = match key {
+        "$eq" if operand.is_null() => (Operator::IsNull, Value::Null),
+        "$ne" if operand.is_null() => (Operator::IsNotNull, Value::Null),
+        "$in" | "$nin" if !operand.is_array() => return Err(invalid()),
+        "$between" if operand.as_array().map(|values| values.len()) != Some(2) => {
+            return Err(invalid())
+        }
+        "$in" => (Operator::In, operand.clone()),
+        "$nin" => (Operator::NotIn, operand.clone()),
+        "$between" => (Operator::Between, operand.clone()),
+        "$exists" => match bool::from_value(operand.clone()) {
+            Some(true) if operand.is_boolean() => (Operator::Exists, Value::Null),
+            Some(false) if operand.is_boolean() => (Operator::NotExists, Value::Null),
+            _ => return Err(invalid()),
+        },
+        "$size" | "$sizeGt" if !operand.is_number() => return Err(invalid()),
+        "$size" => (Operator::SizeEq, operand.clone()),
+        "$sizeGt" => (Operator::SizeGt, operand.clone()),
+        _ => {
+            let operator = match key {
+                "$eq" => Operator::Equal,
+                "$ne" => Operator::NotEqual,
+                "$gt" => Operator::GreaterThan,
+                "$gte" => Operator::GreaterThanOrEqual,
+                "$lt" => Operator::LessThan,
+                "$lte" => Operator::LessThanOrEqual,
+                "$like" => Operator::Like,
+                "$ilike" => Operator::ILike,
+                "$regex" => Operator::Regex,
+                "$contains" => Operator::Contains,
+                "$startsWith" => Operator::StartsWith,
+                "$endsWith" => Operator::EndsWith,
+                _ => return Err(QueryError::UnknownOperator(key.to_string())),
+            };
+            (operator, read_literal(operand))
+        }
+    };
+
+    Ok(Condition::new(operator, attribute_operand(field), right))
+}
+
+fn is_field(value: &Value) -> bool {
+    marked_operand(value, FIELD).is_some()
+}
+
+/// Whether `value` is a `$field` or `$expression` document, rather than operators.
+fn is_operand(value: &Value) -> bool {
+    is_field(value) || marked_operand(value, EXPRESSION).is_some()
+}
+
+/// The operand of a condition: strings are quoted, see `sql_string!`, `$field` documents are
+/// attribute names, and `$expression` documents are kept as they are.
+fn read_literal(value: &Value) -> Value {
+    if let Some(name) = marked_operand(value, FIELD) {
+        return attribute_operand(name);
+    }
+
+    match value.as_string_b() {
+        Some(string) => Value::from(crate::sql_string!(string.as_str())),
+        None => value.clone(),
+    }
+}
+
+/// Writes a clause as a query document.
+pub fn to_query(clause: &Clause) -> Result<Value, QueryError> {
+    let entries = match clause {
+        Clause::Condition(condition) => write_condition(condition)?,
+        Clause::ConditionGroup(condition_group) => write_or(&condition_group.conditions)?,
+    };
+
+    Ok(object(entries))
+}
+
+fn is_logical(token: &ConditionToken, operator: LogicalOperator) -> bool {
+    matches!(token, ConditionToken::LogicalOperator(found) if *found == operator)
+}
+
+fn write_or(tokens: &[ConditionToken]) -> Result<Entries, QueryError> {
+    let mut operands: Vec<Entries> = tokens
+        .split(|token| is_logical(token, LogicalOperator::Or))
+        .map(write_and)
+        .collect::<Result<_, _>>()?;
+
+    if operands.len() == 1 {
+        return Ok(operands.remove(0));
+    }
+
+    let documents: Vec<Value> = operands.into_iter().map(object).collect();
+    Ok(vec![("$or".to_string(), documents.to_value())])
+}
+
+/// Conditions are merged into one document when their fields are in key order, so reading
+/// the document keeps their order.
+fn write_and(tokens: &[ConditionToken]) -> Result<Entries, QueryError> {
+    let mut operands: Vec<Entries> = tokens
+        .split(|token| is_logical(token, LogicalOperator::And))
+        .map(write_not)
+        .collect::<Result<_, _>>()?;
+
+    if operands.len() == 1 {
+        return Ok(operands.remove(0));
+    }
+
+    let merged: Entries = operands.iter().flatten().cloned().collect();
+    if merged.windows(2).all(|pair| pair[0].0 < pair[1].0) {
+        return Ok(merged);
+    }
+
+    let documents: Vec<Value> = operands.into_iter().map(object).collect();
+    Ok(vec![("$and".to_string(), documents.to_value())])
+}
+
+fn write_not(tokens: &[ConditionToken]) -> Result<Entries, QueryError> {
+    match tokens {
+        [ConditionToken::LogicalOperator(LogicalOperator::Not), tokens @ ..] => {
+            let document = object(write_not(tokens)?);
+            Ok(vec![("$nor".to_string(), vec![document].to_value())])
+        }
+        [ConditionToken::Condition(condition)] => write_condition(condition),
+        [ConditionToken::ConditionGroup(condition_group)] => write_or(&condition_group.conditions),
+        _ => Err(QueryError::Unsupported(
+            "invalid condition group".to_string(),
+        )),
+    }
+}
+
+fn write_condition(condition: &Condition) -> Result<Entries, QueryError> {
+    let field = match attribute_name(&condition.left) {
+        Some(field) => field,
+        None => {
+            return Err(QueryError::Unsupported(format!(
+                "left operand {}",
+                condition.left.to_json(JsonMode::Inline)
+            )))
+        }
+    };
+
+    let right = write_literal(&condition.right);
+    let value = match &condition.operator {
+        Operator::Equal if !right.is_null() && (!right.is_object() || is_operand(&right)) => right,
+        operator => {
+            let (key, operand) = write_operator(operator, &condition.right);
+            object(vec![(key, operand)])
+        }
+    };
+
+    Ok(vec![(field, value)])
+}
+
+fn write_operator(operator: &Operator, right: &Value) -> (String, Value) {
+    let literal = || write_literal(right);
+
+    let (key, operand) = match operator {
+        Operator::Equal => ("$eq", literal()),
+        Operator::NotEqual => ("$ne", literal()),
+        Operator::IsNull => ("$eq", Value::Null),
+        Operator::IsNotNull => ("$ne", Value::Null),
+        Operator::GreaterThan => ("$gt", literal()),
+        Operator::GreaterThanOrEqual => ("$gte", literal()),
+        Operator::LessThan => ("$lt", literal()),
+        Operator::LessThanOrEqual => ("$lte", literal()),
+        Operator::In => ("$in", right.clone()),
+        Operator::NotIn => ("$nin", right.clone()),
+        Operator::Between => ("$between", right.clone()),
+        Operator::Like => ("$like", literal()),
+        Operator::ILike => ("$ilike", literal()),
+        Operator::Regex => ("$regex", literal()),
+        Operator::Contains => ("$contains", literal()),
+        Operator::StartsWith => ("$startsWith", literal()),
+        Operator::EndsWith => ("$endsWith", literal()),
+        Operator::Exists => ("$exists", Value::from(true)),
+        Operator::NotExists => ("$exists", Value::from(false)),
+        Operator::SizeEq => ("$size", right.clone()),
+        Operator::SizeGt => ("$sizeGt", right.clone()),
+        Operator::NotLike
+        | Operator::NotILike
+        | Operator::NotRegex
+        | Operator::NotBetween
+        | Operator::NotContains
+        | Operator::NotStartsWith
+        | Operator::NotEndsWith
+        | Operator::SizeNe
+        | Operator::SizeLe => {
+            let negated = write_operator(&operator.negate(), right);
+            ("$not", object(vec![negated]))
+        }
+    };
+
+    (key.to_string(), operand)
+}
+
+/// The value of an operand in a document, see `read_literal`.
+fn write_literal(value: &Value) -> Value {
+    if let Some(name) = attribute_name(value) {
+        return object(vec![(FIELD.to_string(), Value::from(name))]);
+    }
+
+    let string = value
+        .as_string_b()
+        .and_then(|string| Clause::extract_sql_string(&string.as_string()));
+    match string {
+        Some(string) => Value::from(string),
+        None => value.clone(),
+    }
+}
+
+/// The name of the attribute an operand names, see `attribute_operand`.
+fn attribute_name(operand: &Value) -> Option<String> {
+    if let Some(name) = marked_operand(operand, ATTRIBUTE) {
+        return Some(name.to_string());
+    }
+
+    match operand.as_string_b() {
+        Some(name) if Clause::extract_sql_string(&name.as_string()).is_none() => {
+            Some(name.as_string())
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql_string;
+
+    fn document(entries: Vec<(&str, Value)>) -> Value {
+        Value::from(entries)
+    }
+
+    #[test]
+    fn test_from_query() {
+        let query = document(vec![
+            ("age", document(vec![("$gt", Value::from(30_i64))])),
+            (
+                "$or",
+                vec![
+                    document(vec![("status", Value::from("a"))]),
+                    document(vec![("status", Value::from("b"))]),
+                ]
+                .to_value(),
+            ),
+        ]);
+
+        let clause = from_query(&query).unwrap();
+        assert_eq!(
+            clause,
+            Clause::parse("(status = 'a' OR status = 'b') AND age > 30").unwrap()
+        );
+
+        let value = Value::from(vec![("age", Value::from(31)), ("status", Value::from("b"))]);
+        assert!(clause.execute(&value).unwrap());
+        let value = Value::from(vec![("age", Value::from(31)), ("status", Value::from("c"))]);
+        assert!(!clause.execute(&value).unwrap());
+
+        let query = document(vec![
+            ("email", Value::Null),
+            ("name", document(vec![("$field", Value::from("nickname"))])),
+            (
+                "tags",
+                document(vec![
+                    ("$not", document(vec![("$size", Value::from(0_i64))])),
+                    ("$contains", Value::from("a")),
+                ]),
+            ),
+            (
+                "$nor",
+                vec![document(vec![(
+                    "score",
+                    document(vec![("$lt", Value::from(5_i64))]),
+                )])]
+                .to_value(),
+            ),
+        ]);
+        assert_eq!(
+            from_query(&query).unwrap(),
+            Clause::parse(
+                "NOT score < 5 AND email IS NULL AND name = nickname
+                   AND tags CONTAINS 'a' AND SIZE(tags) <> 0"
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_to_query() {
+        let query = to_query(&Clause::parse("age > 30 AND name = 'Bob'").unwrap()).unwrap();
+        assert_eq!(
+            query.get("age").and_then(|age| age.get("$gt")),
+            Some(&Value::from(30_i64))
+        );
+        assert_eq!(query.get("name"), Some(&Value::from("Bob")));
+
+        let query = to_query(&Clause::parse("name NOT LIKE 'J%' OR a = b").unwrap()).unwrap();
+        let or = query.get("$or").unwrap();
+        assert_eq!(
+            or.get(0)
+                .and_then(|name| name.get("name"))
+                .and_then(|name| name.get("$not"))
+                .and_then(|not| not.get("$like")),
+            Some(&Value::from("J%"))
+        );
+        assert_eq!(
+            or.get(1)
+                .and_then(|a| a.get("a"))
+                .and_then(|a| a.get("$field")),
+            Some(&Value::from("b"))
+        );
+    }
+
+    #[test]
+    fn test_query_round_trip() {
+        let clauses = [
+            "name = 'John'",
+            "age >= 18 AND age < 65",
+            "b = 1 AND a = 2",
+            "a = 1 OR b = 2 AND c <> 'x'",
+            "NOT (a > 1 OR b NOT LIKE 'J%') AND c IN (1, 'x', NULL)",
+            "age NOT BETWEEN 18 AND 65 OR name ILIKE 'j%' OR name NOT REGEX '^J'",
+            "email IS NULL AND phone IS NOT NULL AND tags EXISTS AND fax NOT EXISTS",
+            "SIZE(tags) > 2 AND SIZE(tags) <= 5 AND SIZE(name) = 4 AND SIZE(name) <> 3",
+            "name STARTS WITH 'J' AND name NOT ENDS WITH 'x' AND tags NOT CONTAINS 'y'",
+            "created > now() - interval '7 days' AND a = b AND c = length(tags) + 1",
+            "orders[*].total > 100 AND address.city NOT IN ('Lisbon')",
+        ];
+
+        for text in clauses {
+            let clause = Clause::parse(text).unwrap();
+            let query = to_query(&clause).unwrap();
+            assert_eq!(from_query(&query).unwrap(), clause, "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_query_fields_are_attributes() {
+        let query = document(vec![
+            ("user-id", Value::from(5)),
+            ("f(x)", document(vec![("$gt", Value::from(1))])),
+            (
+                "'quoted'",
+                document(vec![("$field", Value::from("created-at"))]),
+            ),
+        ]);
+        let clause = from_query(&query).unwrap();
+        assert_eq!(
+            clause,
+            Clause::group(vec![
+                ConditionToken::Condition(Condition::new(
+                    Operator::Equal,
+                    attribute_operand("'quoted'"),
+                    "created-at"
+                )),
+                ConditionToken::LogicalOperator(LogicalOperator::And),
+                ConditionToken::Condition(Condition::new(Operator::GreaterThan, "f(x)", 1)),
+                ConditionToken::LogicalOperator(LogicalOperator::And),
+                ConditionToken::Condition(Condition::new(Operator::Equal, "user-id", 5)),
+            ])
+        );
+
+        let value = Value::from(vec![
+            ("user-id", Value::from(5)),
+            ("user", Value::from(6)),
+            ("id", Value::from(1)),
+            ("f(x)", Value::from(2)),
+            ("'quoted'", Value::from(3)),
+            ("created-at", Value::from(3)),
+        ]);
+        assert!(clause.execute(&value).unwrap());
+        let value = Value::from(vec![("user", Value::from(6)), ("id", Value::from(1))]);
+        assert!(!clause.execute(&value).unwrap());
+
+        assert_eq!(from_query(&to_query(&clause).unwrap()).unwrap(), clause);
+    }
+
+    #[test]
+    fn test_query_errors() {
+        let error = |query: Value| from_query(&query).unwrap_err();
+
+        assert_eq!(
+            error(Value::from(1)),
+            QueryError::NotADocument("1".to_string())
+        );
+        assert_eq!(error(document(vec![])), QueryError::Empty);
+        assert_eq!(
+            error(document(vec![("$xor", Value::from(1))])),
+            QueryError::UnknownOperator("$xor".to_string())
+        );
+        assert_eq!(
+            error(document(vec![(
+                "a",
+                document(vec![("$gt", Value::from(1)), ("b", Value::from(2))])
+            )])),
+            QueryError::InvalidOperand("a".to_string())
+        );
+        assert_eq!(
+            error(document(vec![(
+                "a",
+                document(vec![("$in", Value::from(1))])
+            )])),
+            QueryError::InvalidOperand("$in".to_string())
+        );
+        assert_eq!(
+            error(document(vec![(
+                "a",
+                document(vec![("$exists", Value::from(1))])
+            )])),
+            QueryError::InvalidOperand("$exists".to_string())
+        );
+        assert_eq!(
+            error(document(vec![("$or", Value::from(Vec::<i32>::new()))])),
+            QueryError::InvalidOperand("$or".to_string())
+        );
+        assert_eq!(
+            error(document(vec![(
+                "a",
+                document(vec![("$near", Value::from(1))])
+            )])),
+            QueryError::UnknownOperator("$near".to_string())
+        );
+
+        let clause = Clause::condition(Operator::Equal, 1, sql_string!("a"));
+        assert!(matches!(to_query(&clause), Err(QueryError::Unsupported(_))));
+        let clause = Clause::parse("lower(name) = 'bob'").unwrap();
+        assert!(matches!(to_query(&clause), Err(QueryError::Unsupported(_))));
+    }
+}
@@ -0,0 +1,474 @@
+//! Eviction policies used by `Cache` to pick a victim once it reaches its capacity.
+//!
+//! A policy only keeps bookkeeping about keys, the values stay in the `Cache`. The cache
+//! notifies the policy on every insert, access and removal, and asks it for a victim with
+//! `evict` when a new key does not fit.
+//!
+//! Built-in policies:
+//!
+//! - `Lru`: evicts the least recently inserted or read key.
+//! - `Lfu`: evicts the least frequently read key, ties are broken by recency.
+//! - `Fifo`: evicts keys in insertion order, reads do not change anything.
+//! - `TtlFirst`: evicts the key closest to its expiration, then the least recently used of
+//!   the keys that never expire.
+//!
+//! Whatever the policy, a full `Cache` drops its expired entries first and only asks the
+//! policy for a victim when nothing has expired. A cache whose policy has no victim refuses
+//! new keys rather than going over its capacity.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Debug;
+use std::time::Instant;
+
+use crate::cache::CacheKey;
+
+pub trait EvictionPolicy<K>: Debug + Send
+where
+    K: CacheKey,
+{
+    /// Called after a new key is stored in the cache.
+    fn on_insert(&mut self, key: &K);
+    /// Called when a stored key is read or updated.
+    fn on_access(&mut self, key: &K);
+    /// Called after a key is removed from the cache for any reason other than `evict`.
+    fn on_remove(&mut self, key: &K);
+    /// Called when the expiration deadline of a key is set or cleared.
+    fn on_deadline(&mut self, _key: &K, _deadline: Option<Instant>) {}
+    /// Chooses the next key to evict and forgets it.
+    fn evict(&mut self) -> Option<K>;
+    /// Forgets every key.
+    fn clear(&mut self);
+    fn clone_box(&self) -> Box<dyn EvictionPolicy<K>>;
+}
+
+impl<K> Clone for Box<dyn EvictionPolicy<K>>
+where
+    K: CacheKey,
+{
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+/// Keys ordered by a tick, shared by `Lru` and `Fifo`.
+#[derive(Debug, Clone)]
+struct Ticks<K>
+where
+    K: CacheKey,
+{
+    tick: u64,
+    keys: HashMap<K, u64>,
+    order: BTreeMap<u64, K>,
+}
+
+impl<K> Ticks<K>
+where
+    K: CacheKey,
+{
+    fn new() -> Self {
+        Self {
+            tick: 0,
+            keys: HashMap::new(),
+            order: BTreeMap::new(),
+        }
+    }
+
+    fn touch(&mut self, key: &K) {
+        self.tick += 1;
+
+        if let Some(previous) = self.keys.insert(key.clone(), self.tick) {
+            self.order.remove(&previous);
+        }
+
+        self.order.insert(self.tick, key.clone());
+    }
+
+    fn remove(&mut self, key: &K) {
+        if let Some(tick) = self.keys.remove(key) {
+            self.order.remove(&tick);
+        }
+    }
+
+    fn pop_oldest(&mut self) -> Option<K> {
+        let (_, key) = self.order.pop_first()?;
+        self.keys.remove(&key);
+        Some(key)
+    }
+
+    fn clear(&mut self) {
+        self.keys.clear();
+        self.order.clear();
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Lru<K>
+where
+    K: CacheKey,
+{
+    ticks: Ticks<K>,
+}
+
+impl<K> Lru<K>
+where
+    K: CacheKey,
+{
+    pub fn new() -> Self {
+        Self {
+            ticks: Ticks::new(),
+        }
+    }
+}
+
+impl<K> Default for Lru<K>
+where
+    K: CacheKey,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K> EvictionPolicy<K> for Lru<K>
+where
+    K: CacheKey,
+{
+    fn on_insert(&mut self, key: &K) {
+        self.ticks.touch(key);
+    }
+
+    fn on_access(&mut self, key: &K) {
+        self.ticks.touch(key);
+    }
+
+    fn on_remove(&mut self, key: &K) {
+        self.ticks.remove(key);
+    }
+
+    fn evict(&mut self) -> Option<K> {
+        self.ticks.pop_oldest()
+    }
+
+    fn clear(&mut self) {
+        self.ticks.clear();
+    }
+
+    fn clone_box(&self) -> Box<dyn EvictionPolicy<K>> {
+        Box::new(self.clone())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Fifo<K>
+where
+    K: CacheKey,
+{
+    ticks: Ticks<K>,
+}
+
+impl<K> Fifo<K>
+where
+    K: CacheKey,
+{
+    pub fn new() -> Self {
+        Self {
+            ticks: Ticks::new(),
+        }
+    }
+}
+
+impl<K> Default for Fifo<K>
+where
+    K: CacheKey,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K> EvictionPolicy<K> for Fifo<K>
+where
+    K: CacheKey,
+{
+    fn on_insert(&mut self, key: &K) {
+        self.ticks.touch(key);
+    }
+
+    fn on_access(&mut self, _key: &K) {}
+
+    fn on_remove(&mut self, key: &K) {
+        self.ticks.remove(key);
+    }
+
+    fn evict(&mut self) -> Option<K> {
+        self.ticks.pop_oldest()
+    }
+
+    fn clear(&mut self) {
+        self.ticks.clear();
+    }
+
+    fn clone_box(&self) -> Box<dyn EvictionPolicy<K>> {
+        Box::new(self.clone())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Lfu<K>
+where
+    K: CacheKey,
+{
+    tick: u64,
+    keys: HashMap<K, (u64, u64)>,
+    order: BTreeSet<(u64, u64, K)>,
+}
+
+impl<K> Lfu<K>
+where
+    K: CacheKey,
+{
+    pub fn new() -> Self {
+        Self {
+            tick: 0,
+            keys: HashMap::new(),
+            order: BTreeSet::new(),
+        }
+    }
+
+    fn bump(&mut self, key: &K, reset: bool) {
+        self.tick += 1;
+
+        let frequency = match self.keys.get(key) {
+            Some(&(frequency, tick)) => {
+                self.order.remove(&(frequency, tick, key.clone()));
+                if reset {
+                    1
+                } else {
+                    frequency + 1
+                }
+            }
+            None => 1,
+        };
+
+        self.keys.insert(key.clone(), (frequency, self.tick));
+        self.order.insert((frequency, self.tick, key.clone()));
+    }
+}
+
+impl<K> Default for Lfu<K>
+where
+    K: CacheKey,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K> EvictionPolicy<K> for Lfu<K>
+where
+    K: CacheKey,
+{
+    fn on_insert(&mut self, key: &K) {
+        self.bump(key, true);
+    }
+
+    fn on_access(&mut self, key: &K) {
+        self.bump(key, false);
+    }
+
+    fn on_remove(&mut self, key: &K) {
+        if let Some((frequency, tick)) = self.keys.remove(key) {
+            self.order.remove(&(frequency, tick, key.clone()));
+        }
+    }
+
+    fn evict(&mut self) -> Option<K> {
+        let (_, _, key) = self.order.pop_first()?;
+        self.keys.remove(&key);
+        Some(key)
+    }
+
+    fn clear(&mut self) {
+        self.keys.clear();
+        self.order.clear();
+    }
+
+    fn clone_box(&self) -> Box<dyn EvictionPolicy<K>> {
+        Box::new(self.clone())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TtlFirst<K>
+where
+    K: CacheKey,
+{
+    deadlines: HashMap<K, Instant>,
+    order: BTreeSet<(Instant, K)>,
+    ticks: Ticks<K>,
+}
+
+impl<K> TtlFirst<K>
+where
+    K: CacheKey,
+{
+    pub fn new() -> Self {
+        Self {
+            deadlines: HashMap::new(),
+            order: BTreeSet::new(),
+            ticks: Ticks::new(),
+        }
+    }
+
+    fn forget_deadline(&mut self, key: &K) {
+        if let Some(deadline) = self.deadlines.remove(key) {
+            self.order.remove(&(deadline, key.clone()));
+        }
+    }
+}
+
+impl<K> Default for TtlFirst<K>
+where
+    K: CacheKey,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K> EvictionPolicy<K> for TtlFirst<K>
+where
+    K: CacheKey,
+{
+    fn on_insert(&mut self, key: &K) {
+        self.ticks.touch(key);
+    }
+
+    fn on_access(&mut self, key: &K) {
+        self.ticks.touch(key);
+    }
+
+    fn on_remove(&mut self, key: &K) {
+        self.ticks.remove(key);
+        self.forget_deadline(key);
+    }
+
+    fn on_deadline(&mut self, key: &K, deadline: Option<Instant>) {
+        self.forget_deadline(key);
+
+        if let Some(deadline) = deadline {
+            self.deadlines.insert(key.clone(), deadline);
+            self.order.insert((deadline, key.clone()));
+        }
+    }
+
+    fn evict(&mut self) -> Option<K> {
+        match self.order.pop_first() {
+            Some((_, key)) => {
+                self.deadlines.remove(&key);
+                self.ticks.remove(&key);
+                Some(key)
+            }
+            None => self.ticks.pop_oldest(),
+        }
+    }
+
+    fn clear(&mut self) {
+        self.deadlines.clear();
+        self.order.clear();
+        self.ticks.clear();
+    }
+
+    fn clone_box(&self) -> Box<dyn EvictionPolicy<K>> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn key(key: &str) -> String {
+        key.to_string()
+    }
+
+    #[test]
+    fn test_lru_evicts_least_recently_used() {
+        let mut policy = Lru::new();
+        policy.on_insert(&key("a"));
+        policy.on_insert(&key("b"));
+        policy.on_insert(&key("c"));
+        policy.on_access(&key("a"));
+
+        assert_eq!(policy.evict(), Some(key("b")));
+        assert_eq!(policy.evict(), Some(key("c")));
+        assert_eq!(policy.evict(), Some(key("a")));
+        assert_eq!(policy.evict(), None);
+    }
+
+    #[test]
+    fn test_fifo_ignores_access() {
+        let mut policy = Fifo::new();
+        policy.on_insert(&key("a"));
+        policy.on_insert(&key("b"));
+        policy.on_access(&key("a"));
+
+        assert_eq!(policy.evict(), Some(key("a")));
+        assert_eq!(policy.evict(), Some(key("b")));
+    }
+
+    #[test]
+    fn test_lfu_evicts_least_frequently_used() {
+        let mut policy = Lfu::new();
+        policy.on_insert(&key("a"));
+        policy.on_insert(&key("b"));
+        policy.on_insert(&key("c"));
+        policy.on_access(&key("a"));
+        policy.on_access(&key("a"));
+        policy.on_access(&key("c"));
+
+        assert_eq!(policy.evict(), Some(key("b")));
+        assert_eq!(policy.evict(), Some(key("c")));
+        assert_eq!(policy.evict(), Some(key("a")));
+    }
+
+    #[test]
+    fn test_ttl_first_evicts_nearest_deadline() {
+        let now = Instant::now();
+        let mut policy = TtlFirst::new();
+        policy.on_insert(&key("a"));
+        policy.on_insert(&key("b"));
+        policy.on_deadline(&key("b"), Some(now + Duration::from_secs(20)));
+        policy.on_insert(&key("c"));
+        policy.on_deadline(&key("c"), Some(now + Duration::from_secs(10)));
+        policy.on_insert(&key("d"));
+        policy.on_access(&key("a"));
+
+        assert_eq!(policy.evict(), Some(key("c")));
+        assert_eq!(policy.evict(), Some(key("b")));
+        assert_eq!(policy.evict(), Some(key("d")));
+        assert_eq!(policy.evict(), Some(key("a")));
+        assert_eq!(policy.evict(), None);
+
+        policy.on_insert(&key("e"));
+        policy.on_deadline(&key("e"), Some(now));
+        policy.on_deadline(&key("e"), None);
+        policy.on_insert(&key("f"));
+        policy.on_deadline(&key("f"), Some(now));
+        policy.on_remove(&key("f"));
+        assert_eq!(policy.evict(), Some(key("e")));
+        assert_eq!(policy.evict(), None);
+    }
+
+    #[test]
+    fn test_remove_forgets_key() {
+        let mut policy = Lru::new();
+        policy.on_insert(&key("a"));
+        policy.on_insert(&key("b"));
+        policy.on_remove(&key("a"));
+
+        assert_eq!(policy.evict(), Some(key("b")));
+        assert_eq!(policy.evict(), None);
+    }
+}
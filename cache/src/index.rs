@@ -0,0 +1,326 @@
+//! Secondary indexes over an attribute of the items of a `Table`.
+//!
+//! A `SecondaryIndex` maps the values an attribute takes in the items of every partition to
+//! the partition and sort keys of those items, so items can be looked up by attribute
+//! without scanning the table. Items without the attribute, or where it holds an array or an
+//! object, are left out.
+//!
+//! The index does not watch the table. Whoever changes the table calls `index_partition` or
+//! `index_item` with the new contents, as `CacheService` does for the indexes it declares.
+
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::{Bound, RangeBounds};
+
+use valu3::prelude::*;
+
+use crate::cache::is_empty_range;
+use crate::partition::Partition;
+use crate::table::Table;
+
+/// An indexed attribute value. Keys of different types sort as
+/// `Null < Boolean < Number < String`, numbers with `f64::total_cmp`.
+#[derive(Debug, Clone)]
+pub enum IndexKey {
+    Null,
+    Boolean(bool),
+    Number(f64),
+    String(String),
+}
+
+impl IndexKey {
+    /// `None` for arrays, objects and undefined values, which are not indexed.
+    pub fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Null => Some(IndexKey::Null),
+            Value::Boolean(_) => bool::from_value(value.clone()).map(IndexKey::Boolean),
+            Value::Number(_) => f64::from_value(value.clone()).map(IndexKey::Number),
+            Value::String(_) => Some(IndexKey::String(value.as_string())),
+            _ => None,
+        }
+    }
+
+    fn rank(&self) -> u8 {
+        match self {
+            IndexKey::Null => 0,
+            IndexKey::Boolean(_) => 1,
+            IndexKey::Number(_) => 2,
+            IndexKey::String(_) => 3,
+        }
+    }
+}
+
+impl PartialEq for IndexKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for IndexKey {}
+
+impl PartialOrd for IndexKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for IndexKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (IndexKey::Boolean(a), IndexKey::Boolean(b)) => a.cmp(b),
+            (IndexKey::Number(a), IndexKey::Number(b)) => a.total_cmp(b),
+            (IndexKey::String(a), IndexKey::String(b)) => a.cmp(b),
+            _ => self.rank().cmp(&other.rank()),
+        }
+    }
+}
+
+impl From<bool> for IndexKey {
+    fn from(value: bool) -> Self {
+        IndexKey::Boolean(value)
+    }
+}
+
+impl From<f64> for IndexKey {
+    fn from(value: f64) -> Self {
+        IndexKey::Number(value)
+    }
+}
+
+impl From<i64> for IndexKey {
+    fn from(value: i64) -> Self {
+        IndexKey::Number(value as f64)
+    }
+}
+
+impl From<&str> for IndexKey {
+    fn from(value: &str) -> Self {
+        IndexKey::String(value.to_string())
+    }
+}
+
+impl From<String> for IndexKey {
+    fn from(value: String) -> Self {
+        IndexKey::String(value)
+    }
+}
+
+/// Partition key and sort key of an indexed item.
+type ItemKey = (String, String);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SecondaryIndex {
+    attribute: String,
+    entries: BTreeMap<IndexKey, BTreeSet<ItemKey>>,
+    items: BTreeMap<ItemKey, IndexKey>,
+}
+
+impl SecondaryIndex {
+    pub fn new(attribute: &str) -> Self {
+        Self {
+            attribute: attribute.to_string(),
+            entries: BTreeMap::new(),
+            items: BTreeMap::new(),
+        }
+    }
+
+    /// An index of every live item of `table`.
+    pub fn build(attribute: &str, table: &Table) -> Self {
+        let mut index = Self::new(attribute);
+
+        for (partition_key, partition) in table.iter() {
+            index.index_partition(partition_key, Some(partition));
+        }
+
+        index
+    }
+
+    pub fn attribute(&self) -> &str {
+        &self.attribute
+    }
+
+    /// Number of indexed items.
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Indexes the item as `value`, or forgets it when `value` is `None`.
+    pub fn index_item(&mut self, partition_key: &str, sort_key: &str, value: Option<&Value>) {
+        let item = (partition_key.to_string(), sort_key.to_string());
+
+        if let Some(previous) = self.items.remove(&item) {
+            if let Some(items) = self.entries.get_mut(&previous) {
+                items.remove(&item);
+                if items.is_empty() {
+                    self.entries.remove(&previous);
+                }
+            }
+        }
+
+        let key = value
+            .and_then(|value| value.get(self.attribute.as_str()))
+            .and_then(IndexKey::from_value);
+
+        if let Some(key) = key {
+            self.entries
+                .entry(key.clone())
+                .or_default()
+                .insert(item.clone());
+            self.items.insert(item, key);
+        }
+    }
+
+    /// Replaces the items indexed for the partition with the live items of `partition`, or
+    /// forgets them when `partition` is `None`.
+    pub fn index_partition(&mut self, partition_key: &str, partition: Option<&Partition>) {
+        let sort_keys: Vec<String> = self
+            .items
+            .range((partition_key.to_string(), String::new())..)
+            .take_while(|((key, _), _)| key == partition_key)
+            .map(|((_, sort_key), _)| sort_key.clone())
+            .collect();
+
+        for sort_key in sort_keys {
+            self.index_item(partition_key, &sort_key, None);
+        }
+
+        for (sort_key, value) in partition.into_iter().flat_map(|partition| partition.iter()) {
+            self.index_item(partition_key, sort_key, Some(value));
+        }
+    }
+
+    /// Partition and sort keys of the items whose attribute is within `range`, ordered by
+    /// attribute, then partition key, then sort key.
+    pub fn range<R>(&self, range: R) -> impl Iterator<Item = (&IndexKey, &str, &str)>
+    where
+        R: RangeBounds<IndexKey>,
+    {
+        let from = range.start_bound().cloned();
+        let to = range.end_bound().cloned();
+        let entries = if is_empty_range(from.as_ref(), to.as_ref()) {
+            None
+        } else {
+            Some(self.entries.range((from, to)))
+        };
+
+        entries.into_iter().flatten().flat_map(|(key, items)| {
+            items.iter().map(move |(partition_key, sort_key)| {
+                (key, partition_key.as_str(), sort_key.as_str())
+            })
+        })
+    }
+
+    /// Keys of the items whose attribute equals `key`.
+    pub fn get(&self, key: &IndexKey) -> impl Iterator<Item = (&str, &str)> {
+        self.range((Bound::Included(key.clone()), Bound::Included(key.clone())))
+            .map(|(_, partition_key, sort_key)| (partition_key, sort_key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(email: &str, age: i64) -> Value {
+        Value::from(vec![
+            ("email", Value::from(email)),
+            ("age", Value::from(age)),
+        ])
+    }
+
+    fn table() -> Table {
+        let mut table = Table::new(10);
+
+        let mut partition = Partition::new(10);
+        partition
+            .insert("ana", user("ana@example.com", 25))
+            .unwrap();
+        partition
+            .insert("bob", user("bob@example.com", 31))
+            .unwrap();
+        table.insert("team1", partition).unwrap();
+
+        let mut partition = Partition::new(10);
+        partition
+            .insert("carl", user("carl@example.com", 31))
+            .unwrap();
+        partition
+            .insert("dan", Value::from(vec![("name", "Dan")]))
+            .unwrap();
+        table.insert("team2", partition).unwrap();
+
+        table
+    }
+
+    fn keys<'a>(items: impl Iterator<Item = (&'a IndexKey, &'a str, &'a str)>) -> Vec<&'a str> {
+        items.map(|(_, _, sort_key)| sort_key).collect()
+    }
+
+    #[test]
+    fn test_index_key_order() {
+        let mut keys = vec![
+            IndexKey::from("a"),
+            IndexKey::from(2.5),
+            IndexKey::Null,
+            IndexKey::from(true),
+            IndexKey::from(-1),
+        ];
+        keys.sort();
+
+        assert_eq!(
+            keys,
+            vec![
+                IndexKey::Null,
+                IndexKey::from(true),
+                IndexKey::from(-1),
+                IndexKey::from(2.5),
+                IndexKey::from("a"),
+            ]
+        );
+        assert_eq!(IndexKey::from_value(&Value::from(vec![1])), None);
+    }
+
+    #[test]
+    fn test_secondary_index_build_and_range() {
+        let index = SecondaryIndex::build("age", &table());
+
+        assert_eq!(index.len(), 3);
+        assert_eq!(keys(index.range(IndexKey::from(30)..)), vec!["bob", "carl"]);
+        assert_eq!(keys(index.range(..IndexKey::from(31))), vec!["ana"]);
+        assert_eq!(
+            index.get(&IndexKey::from(31)).collect::<Vec<_>>(),
+            vec![("team1", "bob"), ("team2", "carl")]
+        );
+        assert_eq!(
+            keys(index.range(IndexKey::from(40)..IndexKey::from(30))),
+            Vec::<&str>::new()
+        );
+    }
+
+    #[test]
+    fn test_secondary_index_updates() {
+        let mut table = table();
+        let mut index = SecondaryIndex::build("email", &table);
+
+        index.index_item("team1", "ana", Some(&user("ana@example.org", 25)));
+        assert_eq!(index.get(&IndexKey::from("ana@example.com")).count(), 0);
+        assert_eq!(
+            index
+                .get(&IndexKey::from("ana@example.org"))
+                .collect::<Vec<_>>(),
+            vec![("team1", "ana")]
+        );
+
+        let partition = table.get_mut("team2").unwrap();
+        partition.remove("carl").unwrap();
+        index.index_partition("team2", table.get("team2"));
+        assert_eq!(index.len(), 2);
+
+        index.index_partition("team1", None);
+        assert!(index.is_empty());
+    }
+}
@@ -1,4 +1,10 @@
+pub mod batch;
 pub mod cache;
 pub mod condition;
+pub mod eviction;
+pub mod index;
+pub mod listener;
 pub mod partition;
+pub mod stats;
 pub mod table;
+pub mod weigher;
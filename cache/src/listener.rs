@@ -0,0 +1,64 @@
+//! Listeners are told about every change made to the keys of a `Cache`.
+//!
+//! A listener is a callback receiving the kind of `Change` and the key it happened to. It
+//! runs inside the cache operation that caused the change, so it should be quick and must
+//! not call back into the same cache.
+//!
+//! Listeners belong to one cache: a clone of the cache starts without any.
+
+use std::fmt::{self, Debug, Formatter};
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Change {
+    /// A new key was stored.
+    Insert,
+    /// The value of a stored key was replaced.
+    Update,
+    /// A key was removed with `remove` or `clear`.
+    Remove,
+    /// A key was dropped by the eviction policy or to stay under `max_weight`.
+    Evict,
+    /// An expired key was dropped.
+    Expire,
+}
+
+pub type Listener<K> = Arc<dyn Fn(Change, &K) + Send + Sync>;
+
+pub(crate) struct Listeners<K> {
+    listeners: Vec<Listener<K>>,
+}
+
+impl<K> Listeners<K> {
+    pub(crate) fn new() -> Self {
+        Self {
+            listeners: Vec::new(),
+        }
+    }
+
+    pub(crate) fn push(&mut self, listener: Listener<K>) {
+        self.listeners.push(listener);
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.listeners.clear();
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.listeners.is_empty()
+    }
+
+    pub(crate) fn notify(&self, change: Change, key: &K) {
+        for listener in &self.listeners {
+            listener(change, key);
+        }
+    }
+}
+
+impl<K> Debug for Listeners<K> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("Listeners")
+            .field("len", &self.listeners.len())
+            .finish()
+    }
+}
@@ -2,11 +2,13 @@ use valu3::value::Value;
 
 use super::cache::Cache;
 
-pub type Partition = Cache<Value>;
+pub type Partition = Cache<String, Value>;
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cache::ListProps;
+    use crate::condition::{Clause, Operator};
 
     #[test]
     fn test_table_new() {
@@ -17,9 +19,9 @@ mod tests {
     #[test]
     fn test_table_insert_and_get() {
         let mut cache = Partition::new(10);
-        
-        cache.insert("key1", Value::from(1));
-        cache.insert("key2", Value::from(2));
+
+        cache.insert("key1", Value::from(1)).unwrap();
+        cache.insert("key2", Value::from(2)).unwrap();
         assert_eq!(cache.get("key1"), Some(&Value::from(1)));
         assert_eq!(cache.get("key2"), Some(&Value::from(2)));
     }
@@ -28,7 +30,7 @@ mod tests {
     fn test_table_remove() {
         let mut cache = Partition::new(10);
 
-        cache.insert("key2", Value::from(2));
+        cache.insert("key2", Value::from(2)).unwrap();
         cache.remove("key1");
         assert_eq!(cache.get("key1"), None);
         assert_eq!(cache.get("key2"), Some(&Value::from(2)));
@@ -38,10 +40,42 @@ mod tests {
     fn test_table_clear() {
         let mut cache = Partition::new(10);
 
-        cache.insert("key1", Value::from(1));
-        cache.insert("key2", Value::from(2));
+        cache.insert("key1", Value::from(1)).unwrap();
+        cache.insert("key2", Value::from(2)).unwrap();
         cache.clear();
         assert_eq!(cache.get("key1"), None);
         assert_eq!(cache.get("key2"), None);
     }
+
+    #[test]
+    fn test_table_list_clause() {
+        let mut cache = Partition::new(10);
+
+        for (key, age) in [
+            ("ana", 25),
+            ("bob", 31),
+            ("carl", 42),
+            ("dan", 19),
+            ("eve", 35),
+        ] {
+            cache.insert(key, Value::from(vec![("age", age)])).unwrap();
+        }
+
+        let props = || {
+            ListProps::default()
+                .clause(Clause::condition(Operator::GreaterThan, "age", 30))
+                .limit(2)
+        };
+
+        let page = cache.list_where(props()).unwrap();
+        let keys: Vec<&String> = page.items.iter().map(|(key, _)| *key).collect();
+        assert_eq!(keys, vec!["bob", "carl"]);
+
+        let page = cache
+            .list_where(props().cursor(page.cursor.unwrap()))
+            .unwrap();
+        let keys: Vec<&String> = page.items.iter().map(|(key, _)| *key).collect();
+        assert_eq!(keys, vec!["eve"]);
+        assert_eq!(page.cursor, None);
+    }
 }
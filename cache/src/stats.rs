@@ -0,0 +1,133 @@
+//! Counters kept by every `Cache` and read with `Cache::stats`.
+//!
+//! Reads only take `&self`, so the counters are atomics and a `Cache` behind a `RwLock`
+//! can count hits and misses under a read lock.
+
+use std::ops::{Add, AddAssign};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use valu3::prelude::*;
+
+use crate::listener::Change;
+
+/// A snapshot of the counters of a `Cache`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, ToValue, ToJson)]
+pub struct Stats {
+    /// Reads that found a live entry.
+    pub hits: u64,
+    /// Reads of a key that is not stored or has expired.
+    pub misses: u64,
+    pub inserts: u64,
+    pub updates: u64,
+    pub evictions: u64,
+    pub expirations: u64,
+}
+
+impl Stats {
+    /// Share of the reads that were hits, `0.0` when nothing was read.
+    pub fn hit_rate(&self) -> f64 {
+        let reads = self.hits + self.misses;
+
+        if reads == 0 {
+            0.0
+        } else {
+            self.hits as f64 / reads as f64
+        }
+    }
+}
+
+impl Add for Stats {
+    type Output = Self;
+
+    fn add(mut self, other: Self) -> Self {
+        self += other;
+        self
+    }
+}
+
+impl AddAssign for Stats {
+    fn add_assign(&mut self, other: Self) {
+        self.hits += other.hits;
+        self.misses += other.misses;
+        self.inserts += other.inserts;
+        self.updates += other.updates;
+        self.evictions += other.evictions;
+        self.expirations += other.expirations;
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    inserts: AtomicU64,
+    updates: AtomicU64,
+    evictions: AtomicU64,
+    expirations: AtomicU64,
+}
+
+impl Counters {
+    pub(crate) fn read(&self, hit: bool) {
+        let counter = if hit { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record(&self, change: Change) {
+        let counter = match change {
+            Change::Insert => &self.inserts,
+            Change::Update => &self.updates,
+            Change::Evict => &self.evictions,
+            Change::Expire => &self.expirations,
+            Change::Remove => return,
+        };
+
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> Stats {
+        Stats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            inserts: self.inserts.load(Ordering::Relaxed),
+            updates: self.updates.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            expirations: self.expirations.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl From<Stats> for Counters {
+    fn from(stats: Stats) -> Self {
+        Self {
+            hits: AtomicU64::new(stats.hits),
+            misses: AtomicU64::new(stats.misses),
+            inserts: AtomicU64::new(stats.inserts),
+            updates: AtomicU64::new(stats.updates),
+            evictions: AtomicU64::new(stats.evictions),
+            expirations: AtomicU64::new(stats.expirations),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats_add() {
+        let stats = Stats {
+            hits: 3,
+            misses: 1,
+            ..Stats::default()
+        } + Stats {
+            hits: 1,
+            inserts: 2,
+            ..Stats::default()
+        };
+
+        assert_eq!(stats.hits, 4);
+        assert_eq!(stats.inserts, 2);
+        assert_eq!(stats.hit_rate(), 0.8);
+        assert_eq!(Stats::default().hit_rate(), 0.0);
+    }
+}
@@ -1,14 +1,66 @@
 use super::{cache::Cache, partition::Partition};
 
-pub type Table = Cache<Partition>;
+pub type Table = Cache<String, Partition>;
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cache::ListProps;
+    use crate::condition::{Clause, Operator};
+    use crate::sql_string;
+    use valu3::value::Value;
 
     #[test]
     fn test_table_new() {
         let cache = Table::new(10);
         assert_eq!(cache.capacity(), 10);
     }
+
+    #[test]
+    fn test_table_list_clause() {
+        let mut table = Table::new(10);
+
+        for (key, status) in [("order1", "open"), ("order2", "closed"), ("order3", "open")] {
+            let mut partition = Partition::new(10);
+            partition.insert("status", Value::from(status)).unwrap();
+            table.insert(key, partition).unwrap();
+        }
+
+        let clause = Clause::condition(Operator::Equal, "status", sql_string!("open"));
+        let page = table
+            .list_where(ListProps::default().clause(clause))
+            .unwrap();
+        let keys: Vec<&String> = page.items.iter().map(|(key, _)| *key).collect();
+
+        assert_eq!(keys, vec!["order1", "order3"]);
+    }
+
+    #[test]
+    fn test_table_list_clause_reads_attributes() {
+        let mut table = Table::new(10);
+
+        for (key, city, total) in [("order1", "Lisbon", 120), ("order2", "Porto", 200)] {
+            let mut partition = Partition::new(10);
+            partition
+                .insert("address", Value::from(vec![("city", city)]))
+                .unwrap();
+            partition.insert("total", Value::from(total)).unwrap();
+            partition.insert("notes", Value::from("unread")).unwrap();
+            table.insert(key, partition).unwrap();
+        }
+
+        let clause = Clause::parse("lower(address.city) = 'lisbon' AND total - 20 >= 100").unwrap();
+        let plan = clause.compile().unwrap();
+        assert_eq!(
+            plan.attributes().into_iter().collect::<Vec<_>>(),
+            vec!["address", "total"]
+        );
+
+        let page = table
+            .list_where(ListProps::default().clause(clause))
+            .unwrap();
+        let keys: Vec<&String> = page.items.iter().map(|(key, _)| *key).collect();
+
+        assert_eq!(keys, vec!["order1"]);
+    }
 }
@@ -0,0 +1,88 @@
+//! Weighers measure how much of a `Cache`'s `max_weight` an entry takes.
+//!
+//! The weight is measured when an entry is inserted. Changes made through `Cache::get_mut`
+//! are only accounted for after `Cache::reweigh` or a new insert of the same key.
+
+use std::fmt::Debug;
+use std::mem::size_of;
+
+use valu3::prelude::*;
+
+use crate::{cache::CacheKey, partition::Partition};
+
+pub trait Weigher<K, V>: Debug + Send + Sync {
+    fn weigh(&self, key: &K, value: &V) -> usize;
+}
+
+/// Every entry weighs 1, so the weight of a cache is its number of entries.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UnitWeigher;
+
+impl<K, V> Weigher<K, V> for UnitWeigher {
+    fn weigh(&self, _key: &K, _value: &V) -> usize {
+        1
+    }
+}
+
+/// Estimates the size in bytes of a sort key and its `Value`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ValueWeigher;
+
+impl ValueWeigher {
+    pub fn estimate(value: &Value) -> usize {
+        match value {
+            Value::String(_) => value.as_str().len(),
+            Value::Number(_) => size_of::<f64>(),
+            Value::Boolean(_) => size_of::<bool>(),
+            Value::Null => 0,
+            Value::Array(array) => array.into_iter().map(Self::estimate).sum(),
+            _ => value.to_json(JsonMode::Inline).len(),
+        }
+    }
+}
+
+impl Weigher<String, Value> for ValueWeigher {
+    fn weigh(&self, key: &String, value: &Value) -> usize {
+        key.len() + Self::estimate(value)
+    }
+}
+
+/// Weighs a partition as its key plus the current weight of the partition itself.
+///
+/// Partitions should use `ValueWeigher` for the result to be in bytes.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PartitionWeigher;
+
+impl<K> Weigher<K, Partition> for PartitionWeigher
+where
+    K: CacheKey + AsRef<str>,
+{
+    fn weigh(&self, key: &K, partition: &Partition) -> usize {
+        key.as_ref().len() + partition.weight()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_value_weigher() {
+        assert_eq!(
+            ValueWeigher.weigh(&"key".to_string(), &Value::from("value")),
+            8
+        );
+        assert_eq!(ValueWeigher::estimate(&Value::from(1)), 8);
+        assert_eq!(ValueWeigher::estimate(&Value::from(vec!["ab", "cd"])), 4);
+    }
+
+    #[test]
+    fn test_partition_weigher() {
+        let mut partition = Partition::new(10);
+        partition.set_weigher(ValueWeigher);
+        partition.insert("key1", Value::from("value")).unwrap();
+
+        assert_eq!(partition.weight(), 9);
+        assert_eq!(PartitionWeigher.weigh(&"user".to_string(), &partition), 13);
+    }
+}
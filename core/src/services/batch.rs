@@ -0,0 +1,92 @@
+//! Writes to several partitions and items of one table, applied all-or-nothing by
+//! `CacheService::write_batch`.
+
+use cache::{batch::Op, partition::Partition};
+use valu3::value::Value;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WriteOp {
+    Partition(Box<Op<String, Partition>>),
+    /// A write to an item of the partition with this key.
+    Item(String, Op<String, Value>),
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WriteBatch {
+    ops: Vec<WriteOp>,
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn push(mut self, op: WriteOp) -> Self {
+        self.ops.push(op);
+        self
+    }
+
+    fn item(self, partition_key: &str, op: Op<String, Value>) -> Self {
+        self.push(WriteOp::Item(partition_key.to_string(), op))
+    }
+
+    pub fn put(self, partition_key: &str, sort_key: &str, value: Value) -> Self {
+        self.item(partition_key, Op::Insert(sort_key.to_string(), value))
+    }
+
+    /// Fails the batch with `Error::SortKeyExists` when the item exists.
+    pub fn put_if_not_exists(self, partition_key: &str, sort_key: &str, value: Value) -> Self {
+        self.item(
+            partition_key,
+            Op::InsertIfNotExists(sort_key.to_string(), value),
+        )
+    }
+
+    /// Fails the batch with `Error::KeyNotFound` when the item is missing.
+    pub fn delete(self, partition_key: &str, sort_key: &str) -> Self {
+        self.item(partition_key, Op::Remove(sort_key.to_string()))
+    }
+
+    pub fn put_partition(self, partition_key: &str, partition: Partition) -> Self {
+        self.push(WriteOp::Partition(Box::new(Op::Insert(
+            partition_key.to_string(),
+            partition,
+        ))))
+    }
+
+    /// Fails the batch with `Error::SortKeyExists` when the partition exists.
+    pub fn put_partition_if_not_exists(self, partition_key: &str, partition: Partition) -> Self {
+        self.push(WriteOp::Partition(Box::new(Op::InsertIfNotExists(
+            partition_key.to_string(),
+            partition,
+        ))))
+    }
+
+    /// Fails the batch with `Error::KeyNotFound` when the partition is missing.
+    pub fn delete_partition(self, partition_key: &str) -> Self {
+        self.push(WriteOp::Partition(Box::new(Op::Remove(
+            partition_key.to_string(),
+        ))))
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    pub fn ops(&self) -> &[WriteOp] {
+        &self.ops
+    }
+}
+
+impl IntoIterator for WriteBatch {
+    type Item = WriteOp;
+    type IntoIter = std::vec::IntoIter<WriteOp>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.ops.into_iter()
+    }
+}
@@ -1,104 +1,2087 @@
-use std::sync::{Arc, Mutex};
+use super::batch::{WriteBatch, WriteOp};
+use super::loader::{self, Loader, TableLoader, Write, WriteMode};
+use super::snapshot::{self, millis, PartitionSnapshot, ServiceSnapshot, TableSnapshot};
 use cache::{
-    cache::{Cache, Error, ListProps},
-    table::Table, partition::Partition,
+    batch::Op,
+    cache::{Cache, Error, ListProps, Page},
+    eviction::{EvictionPolicy, Lru},
+    index::{IndexKey, SecondaryIndex},
+    listener::Change,
+    partition::Partition,
+    stats::Stats,
+    table::Table,
+    weigher::{PartitionWeigher, ValueWeigher},
 };
 use events::Events;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io;
+use std::ops::RangeBounds;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use valu3::prelude::*;
 
+/// Name of the event emitted for every new partition or item.
+pub const EVENT_INSERT: &str = "cache:insert";
+/// Name of the event emitted for every replaced partition or item.
+pub const EVENT_UPDATE: &str = "cache:update";
+/// Name of the event emitted for every removed partition or item.
+pub const EVENT_REMOVE: &str = "cache:remove";
+/// Name of the event emitted for every partition or item dropped to make room.
+pub const EVENT_EVICT: &str = "cache:evict";
+/// Name of the event emitted for every expired partition or item.
+pub const EVENT_EXPIRE: &str = "cache:expire";
+
+fn event_name(change: Change) -> &'static str {
+    match change {
+        Change::Insert => EVENT_INSERT,
+        Change::Update => EVENT_UPDATE,
+        Change::Remove => EVENT_REMOVE,
+        Change::Evict => EVENT_EVICT,
+        Change::Expire => EVENT_EXPIRE,
+    }
+}
+
+/// Payload of the events emitted by `CacheService`.
+///
+/// `sort_key` is `None` when the event concerns a whole partition.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CacheEvent {
+    pub table: String,
+    pub partition_key: String,
+    pub sort_key: Option<String>,
+}
+
+/// Counters of one table, see `CacheService::stats`.
+#[derive(Debug, Clone, Default, PartialEq, ToValue, ToJson)]
+pub struct TableStats {
+    pub name: String,
+    /// Number of partitions stored.
+    pub len: u64,
+    pub weight: u64,
+    /// Reads and changes of whole partitions.
+    pub partitions: Stats,
+    /// Reads and changes of items, added up over the partitions currently stored.
+    pub items: Stats,
+}
+
+/// Snapshot of the counters of every table and their totals, serializable to JSON.
+#[derive(Debug, Clone, Default, PartialEq, ToValue, ToJson)]
+pub struct ServiceStats {
+    pub tables: Vec<TableStats>,
+    pub len: u64,
+    pub weight: u64,
+    pub partitions: Stats,
+    pub items: Stats,
+}
+
+/// Settings of a table created through `CacheService::create_table`.
+pub struct TableOptions {
+    capacity: usize,
+    policy: Box<dyn EvictionPolicy<String>>,
+    ttl: Option<Duration>,
+    max_weight: Option<usize>,
+    loader: Option<(Arc<dyn Loader>, WriteMode)>,
+}
+
+impl TableOptions {
+    /// A table holding up to `capacity` partitions, evicting the least recently used one.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            policy: Box::new(Lru::new()),
+            ttl: None,
+            max_weight: None,
+            loader: None,
+        }
+    }
+
+    pub fn policy<P>(mut self, policy: P) -> Self
+    where
+        P: EvictionPolicy<String> + 'static,
+    {
+        self.policy = Box::new(policy);
+        self
+    }
+
+    /// Partitions expire after `ttl` unless they are inserted with their own TTL.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Maximum estimated size in bytes of all the partitions of the table.
+    pub fn max_weight(mut self, max_weight: usize) -> Self {
+        self.max_weight = Some(max_weight);
+        self
+    }
+
+    /// Reads missing partitions through `loader` and writes changes to it according to `mode`,
+    /// see the `loader` module.
+    pub fn loader(mut self, loader: Arc<dyn Loader>, mode: WriteMode) -> Self {
+        self.loader = Some((loader, mode));
+        self
+    }
+
+    fn build(self) -> (Table, Option<TableLoader>) {
+        let mut table = Table::with_boxed_policy(self.capacity, self.policy);
+        table.set_ttl(self.ttl);
+        table.set_weigher(PartitionWeigher);
+        table.set_max_weight(self.max_weight);
+
+        let loader = self
+            .loader
+            .map(|(loader, mode)| TableLoader::new(loader, mode));
+        (table, loader)
+    }
+}
+
+impl From<usize> for TableOptions {
+    fn from(capacity: usize) -> Self {
+        Self::new(capacity)
+    }
+}
+
+/// Events raised by the listeners of a table, emitted once its lock is released.
+type Pending = Arc<Mutex<Vec<(&'static str, CacheEvent)>>>;
+
+/// A table behind its own lock, so that tables never wait on each other.
+///
+/// `weight` mirrors `Table::weight` and is refreshed after every write, which lets the
+/// service add up its weight without locking the tables.
+#[derive(Debug)]
+struct TableHandle {
+    name: String,
+    table: RwLock<Table>,
+    weight: AtomicUsize,
+    pending: Pending,
+    loader: Option<TableLoader>,
+    indexes: Mutex<BTreeMap<String, SecondaryIndex>>,
+}
+
+#[derive(Debug, Clone)]
+struct SharedTable(Arc<TableHandle>);
+
+impl PartialEq for SharedTable {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl SharedTable {
+    fn new(name: &str, mut table: Table, loader: Option<TableLoader>) -> Self {
+        let pending = Pending::default();
+        let table_name = name.to_string();
+        let queue = pending.clone();
+
+        table.add_listener(move |change, partition_key: &String| {
+            queue.lock().unwrap_or_else(PoisonError::into_inner).push((
+                event_name(change),
+                CacheEvent {
+                    table: table_name.clone(),
+                    partition_key: partition_key.clone(),
+                    sort_key: None,
+                },
+            ));
+        });
+
+        Self(Arc::new(TableHandle {
+            name: name.to_string(),
+            weight: AtomicUsize::new(table.weight()),
+            table: RwLock::new(table),
+            pending,
+            loader,
+            indexes: Mutex::new(BTreeMap::new()),
+        }))
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, Table> {
+        self.0.table.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Table> {
+        self.0.table.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn weight(&self) -> usize {
+        self.0.weight.load(Ordering::Relaxed)
+    }
+
+    fn writes_through(&self) -> bool {
+        matches!(&self.0.loader, Some(loader) if loader.mode == WriteMode::Through)
+    }
+
+    /// Replaces the listeners of the partitions inserted or updated since the last
+    /// `take_pending`, so that changes to their items are reported with their sort key.
+    fn watch_partitions(&self, table: &mut Table) {
+        let partition_keys: Vec<String> = self
+            .0
+            .pending
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .filter(|(name, event)| {
+                event.sort_key.is_none() && (*name == EVENT_INSERT || *name == EVENT_UPDATE)
+            })
+            .map(|(_, event)| event.partition_key.clone())
+            .collect();
+
+        for partition_key in partition_keys {
+            if let Some(partition) = table.peek_mut(&partition_key) {
+                self.watch_partition(partition_key, partition);
+            }
+        }
+    }
+
+    /// Gives a new version to the partitions whose items changed since the last
+    /// `take_pending`, so that the version of a partition follows its items.
+    fn bump_versions(&self, table: &mut Table) {
+        let partition_keys: BTreeSet<String> = self
+            .0
+            .pending
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .filter(|(_, event)| event.sort_key.is_some())
+            .map(|(_, event)| event.partition_key.clone())
+            .collect();
+
+        for partition_key in partition_keys {
+            table.bump_version(&partition_key);
+        }
+    }
+
+    fn watch_partition(&self, partition_key: String, partition: &mut Partition) {
+        let table_name = self.0.name.clone();
+        let queue = self.0.pending.clone();
+
+        partition.clear_listeners();
+        partition.add_listener(move |change, sort_key: &String| {
+            queue.lock().unwrap_or_else(PoisonError::into_inner).push((
+                event_name(change),
+                CacheEvent {
+                    table: table_name.clone(),
+                    partition_key: partition_key.clone(),
+                    sort_key: Some(sort_key.clone()),
+                },
+            ));
+        });
+    }
+
+    /// When the table writes behind, queues a write for every partition changed since the
+    /// last `take_pending`. Partitions are copied as they are in `table`.
+    fn queue_writes(&self, table: &Table) {
+        let loader = match &self.0.loader {
+            Some(loader) if loader.writes_behind() => loader,
+            _ => return,
+        };
+
+        let mut deleted = BTreeMap::new();
+        for (name, event) in self
+            .0
+            .pending
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+        {
+            match (*name, &event.sort_key) {
+                (EVENT_REMOVE, None) => deleted.insert(event.partition_key.clone(), true),
+                (EVENT_INSERT | EVENT_UPDATE, None) | (_, Some(_)) => {
+                    deleted.insert(event.partition_key.clone(), false)
+                }
+                // evicted and expired partitions stay in the storage
+                _ => None,
+            };
+        }
+
+        for (partition_key, deleted) in deleted {
+            if deleted {
+                loader.queue(partition_key, Write::Delete);
+            } else if let Some(partition) = table.peek(&partition_key) {
+                loader.queue(partition_key, Write::Store(Box::new(partition.clone())));
+            }
+        }
+    }
+
+    fn indexes(&self) -> MutexGuard<'_, BTreeMap<String, SecondaryIndex>> {
+        self.0
+            .indexes
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Brings the secondary indexes up to date with the partitions and items changed since
+    /// the last `take_pending`, as they are in `table`.
+    fn update_indexes(&self, table: &Table) {
+        let mut indexes = self.indexes();
+        if indexes.is_empty() {
+            return;
+        }
+
+        let changed: BTreeSet<(String, Option<String>)> = self
+            .0
+            .pending
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .map(|(_, event)| (event.partition_key.clone(), event.sort_key.clone()))
+            .collect();
+
+        for (partition_key, sort_key) in changed {
+            let partition = table.peek(&partition_key);
+
+            for index in indexes.values_mut() {
+                match &sort_key {
+                    Some(sort_key) => index.index_item(
+                        &partition_key,
+                        sort_key,
+                        partition.and_then(|partition| partition.peek(sort_key)),
+                    ),
+                    None => index.index_partition(&partition_key, partition),
+                }
+            }
+        }
+    }
+
+    fn take_pending(&self) -> Vec<(&'static str, CacheEvent)> {
+        std::mem::take(
+            &mut *self
+                .0
+                .pending
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner),
+        )
+    }
+}
+
+/// Thread-safe cache of tables.
+///
+/// Every table has its own `RwLock`: reads on a table share its lock, and writes only
+/// block readers and writers of that same table. The list of tables is behind a separate
+/// lock that is only held long enough to look a table up, create or remove one.
+///
+/// Every change to a partition, or to an item of a partition stored in the service, is
+/// emitted on `events` as one of the `EVENT_*` names once the table lock is released.
 pub struct CacheService {
-    pub tables: Cache<Table>,
-    pub events: Arc<Mutex<Events>>,
+    tables: RwLock<Cache<String, SharedTable>>,
+    pub events: Arc<Mutex<Events<CacheEvent>>>,
+    max_weight: RwLock<Option<usize>>,
 }
 
 impl CacheService {
-    pub fn build(capacity: usize, events: Arc<Mutex<Events>>) -> Arc<Mutex<Self>> {
-        Arc::new(Mutex::new(Self::new(capacity, events)))
+    pub fn build(capacity: usize, events: Arc<Mutex<Events<CacheEvent>>>) -> Arc<Self> {
+        Arc::new(Self::new(capacity, events))
     }
 
-    pub fn new(capacity: usize, events: Arc<Mutex<Events>>) -> Self {
+    pub fn new(capacity: usize, events: Arc<Mutex<Events<CacheEvent>>>) -> Self {
         Self {
-            tables: Cache::new(capacity),
+            tables: RwLock::new(Cache::new(capacity)),
             events,
+            max_weight: RwLock::new(None),
         }
     }
 
-    pub fn create_table(&mut self, table_name: &'static str, capacity: usize) {
-        self.tables.insert(table_name, Table::new(capacity));
+    /// Spawns a tokio task that removes expired partitions and items every `period`.
+    ///
+    /// Must be called from within a tokio runtime.
+    pub fn spawn_sweeper(service: Arc<Self>, period: Duration) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+
+            loop {
+                interval.tick().await;
+                service.remove_expired();
+            }
+        })
+    }
+
+    fn tables(&self) -> RwLockReadGuard<'_, Cache<String, SharedTable>> {
+        self.tables.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn tables_mut(&self) -> RwLockWriteGuard<'_, Cache<String, SharedTable>> {
+        self.tables.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn table(&self, table_name: &str) -> Result<SharedTable, Error> {
+        self.tables()
+            .get(table_name)
+            .cloned()
+            .ok_or(Error::TableNotFound)
+    }
+
+    pub fn create_table<O>(&self, table_name: &str, options: O) -> Result<(), Error>
+    where
+        O: Into<TableOptions>,
+    {
+        let (table, loader) = options.into().build();
+        let table = SharedTable::new(table_name, table, loader);
+        self.tables_mut().insert(table_name, table)
+    }
+
+    pub fn create_table_if_not_exists<O>(&self, table_name: &str, options: O)
+    where
+        O: Into<TableOptions>,
+    {
+        let (table, loader) = options.into().build();
+        let table = SharedTable::new(table_name, table, loader);
+        let _ = self.tables_mut().insert_if_not_exists(table_name, table);
+    }
+
+    pub fn remove_table(&self, table_name: &str) {
+        let _ = self.tables_mut().remove(table_name);
+    }
+
+    pub fn table_exists(&self, table_name: &str) -> bool {
+        self.tables().contains_key(table_name)
+    }
+
+    pub fn update_tables(&self, capacity: usize) {
+        self.tables_mut().set_capacity(capacity)
     }
 
-    pub fn create_table_if_not_exists(&mut self, table_name: &'static str, capacity: usize) {
-        self.tables
-            .insert_if_not_exists(table_name, Table::new(capacity));
+    pub fn clear_tables(&self) {
+        self.tables_mut().clear();
     }
 
-    pub fn remove_table(&mut self, table_name: &'static str) {
-        self.tables.remove(table_name);
+    /// Runs `f` with a shared lock on the table, without copying its partitions.
+    pub fn with_table<F, R>(&self, table_name: &str, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&Table) -> R,
+    {
+        let table = self.table(table_name)?;
+        let guard = table.read();
+        Ok(f(&guard))
     }
 
-    pub fn table_exists(&self, table_name: &'static str) -> bool {
-        self.tables.contains_key(table_name)
+    /// Runs `f` with an exclusive lock on the table. Only writers and readers of this table wait.
+    ///
+    /// Fails with `Error::TableWritesThrough` on a table writing through its loader, which
+    /// only `put_partition` and `delete_partition` write to. So do the other writes of the
+    /// service, which all go through here.
+    pub fn with_table_mut<F, R>(&self, table_name: &str, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&mut Table) -> R,
+    {
+        let table = self.table(table_name)?;
+        if table.writes_through() {
+            return Err(Error::TableWritesThrough);
+        }
+
+        Ok(self.update_table(&table, true, f))
     }
 
-    pub fn update_tables(&mut self, capacity: usize) {
-        self.tables.set_capacity(capacity)
+    /// Body of `with_table_mut`. Loaded partitions are inserted without `queue`, so that they
+    /// are not written back, and `delete_partition` queues its own write.
+    fn update_table<F, R>(&self, table: &SharedTable, queue: bool, f: F) -> R
+    where
+        F: FnOnce(&mut Table) -> R,
+    {
+        let result = {
+            let mut guard = table.write();
+            guard.purge_expired();
+            let result = f(&mut guard);
+            table.watch_partitions(&mut guard);
+            table.bump_versions(&mut guard);
+            table.update_indexes(&guard);
+            if queue {
+                table.queue_writes(&guard);
+            }
+            table.0.weight.store(guard.weight(), Ordering::Relaxed);
+            result
+        };
+
+        self.shrink_to_max_weight(table);
+        self.emit(table);
+        result
     }
 
-    pub fn clear_tables(&mut self) {
-        self.tables.clear();
+    /// Estimated size in bytes of every partition of every table.
+    pub fn weight(&self) -> usize {
+        self.tables().values().map(|table| table.weight()).sum()
+    }
+
+    pub fn table_weight(&self, table_name: &str) -> Option<usize> {
+        self.table(table_name).ok().map(|table| table.weight())
+    }
+
+    pub fn max_weight(&self) -> Option<usize> {
+        *self
+            .max_weight
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Bounds the estimated size of the whole service. When a write goes over it,
+    /// partitions are evicted from the table being written to.
+    pub fn set_max_weight(&self, max_weight: Option<usize>) {
+        *self
+            .max_weight
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = max_weight;
     }
 
     pub fn list_table(
         &self,
-        table_name: &'static str,
-        props: ListProps,
-    ) -> Result<Vec<(&str, &Partition)>, Error> {
-        let table: &Cache<Partition> = self.tables.get(table_name).unwrap();
-        table.list(props)
+        table_name: &str,
+        props: ListProps<String>,
+    ) -> Result<Page<(String, Partition), String>, Error> {
+        self.with_table(table_name, |table| {
+            table
+                .list_where(props)
+                .map(|page| page.map(|(key, partition)| (key.clone(), partition.clone())))
+        })?
     }
 
     pub fn create_partition(
-        &mut self,
+        &self,
         table_name: &str,
-        partition_key: &'static str,
+        partition_key: &str,
         value: Partition,
-    ) {
-        let table: &mut Cache<Partition> = self.tables.get_mut(table_name).unwrap();
-        table.insert(partition_key, value);
+    ) -> Result<(), Error> {
+        self.with_table_mut(table_name, |table| {
+            table.insert(partition_key, Self::weighted(value))
+        })?
     }
 
-    pub fn create_partition_if_not_exists(
-        &mut self,
+    pub fn create_partition_with_ttl(
+        &self,
         table_name: &str,
-        partition_key: &'static str,
+        partition_key: &str,
         value: Partition,
-    ) {
-        let table = self.tables.get_mut(table_name).unwrap();
-        table.insert_if_not_exists(partition_key, value);
+        ttl: Duration,
+    ) -> Result<(), Error> {
+        self.with_table_mut(table_name, |table| {
+            table.insert_with_ttl(partition_key, Self::weighted(value), ttl)
+        })?
     }
 
-    pub fn get_partition(&self, table_name: &str, partition_key: &'static str) -> Option<&Partition> {
-        let table = self.tables.get(table_name).unwrap();
-        table.get(partition_key)
+    pub fn create_partition_if_not_exists(
+        &self,
+        table_name: &str,
+        partition_key: &str,
+        value: Partition,
+    ) -> Result<(), Error> {
+        self.with_table_mut(table_name, |table| {
+            table.insert_if_not_exists(partition_key, Self::weighted(value))
+        })?
     }
 
     pub fn update_partition(
-        &mut self,
+        &self,
+        table_name: &str,
+        partition_key: &str,
+        value: Partition,
+    ) -> Result<(), Error> {
+        self.with_table_mut(table_name, |table| {
+            table.insert(partition_key, Self::weighted(value))
+        })?
+    }
+
+    /// Returns a copy of the partition along with its version, see `partition_version`.
+    pub fn get_partition_with_version(
+        &self,
+        table_name: &str,
+        partition_key: &str,
+    ) -> Option<(Partition, u64)> {
+        self.with_table(table_name, |table| {
+            let partition = table.get(partition_key)?.clone();
+            Some((partition, table.version_of(partition_key)?))
+        })
+        .ok()
+        .flatten()
+    }
+
+    /// Version of the partition, meant to be used as an ETag. It changes with every write to
+    /// the partition or to one of its items.
+    pub fn partition_version(&self, table_name: &str, partition_key: &str) -> Option<u64> {
+        self.with_table(table_name, |table| table.version_of(partition_key))
+            .ok()
+            .flatten()
+    }
+
+    pub fn item_version(
+        &self,
+        table_name: &str,
+        partition_key: &str,
+        sort_key: &str,
+    ) -> Option<u64> {
+        self.with_table(table_name, |table| {
+            table.peek(partition_key)?.version_of(sort_key)
+        })
+        .ok()
+        .flatten()
+    }
+
+    /// Replaces the partition only if its version is still `expected_version`, and returns
+    /// its new version. Fails with `Error::VersionMismatch` otherwise.
+    pub fn compare_and_swap_partition(
+        &self,
+        table_name: &str,
+        partition_key: &str,
+        expected_version: u64,
+        value: Partition,
+    ) -> Result<u64, Error> {
+        self.with_table_mut(table_name, |table| {
+            table.compare_and_swap(partition_key, expected_version, Self::weighted(value))
+        })?
+    }
+
+    /// Replaces the item only if its version is still `expected_version`, and returns its new
+    /// version. The partition gets a new version as well.
+    pub fn compare_and_swap_item(
+        &self,
+        table_name: &str,
+        partition_key: &str,
+        sort_key: &str,
+        expected_version: u64,
+        value: Value,
+    ) -> Result<u64, Error> {
+        self.with_table_mut(table_name, |table| {
+            let partition = table.peek_mut(partition_key).ok_or(Error::KeyNotFound)?;
+            let version = partition.compare_and_swap(sort_key, expected_version, value)?;
+            table.reweigh(partition_key);
+            Ok(version)
+        })?
+    }
+
+    pub fn remove_partition(&self, table_name: &str, partition_key: &str) -> Result<(), Error> {
+        self.with_table_mut(table_name, |table| table.remove(partition_key))?
+    }
+
+    pub fn partition_exists(&self, table_name: &str, partition_key: &str) -> bool {
+        self.with_table(table_name, |table| table.contains_key(partition_key))
+            .unwrap_or(false)
+    }
+
+    /// Applies every write of `batch` under one lock of the table, or none of them when one
+    /// fails its check, see `Cache::apply`. Writes to the items of a missing partition fail
+    /// with `Error::KeyNotFound`.
+    ///
+    /// The partitions and items the batch writes to are pinned while it is applied, so that
+    /// making room for one of its writes never evicts another, see `Cache::pin`.
+    pub fn write_batch(&self, table_name: &str, batch: WriteBatch) -> Result<(), Error> {
+        self.with_table_mut(table_name, |table| {
+            let (partition_keys, item_keys) = Self::batch_keys(&batch);
+            Self::check_batch(table, &batch, &partition_keys, &item_keys)?;
+
+            table.pin(partition_keys);
+            let mut changed = BTreeSet::new();
+            let mut applied = Ok(());
+
+            for (index, op) in batch.into_iter().enumerate() {
+                let result = match op {
+                    WriteOp::Partition(op) => table.apply_op(match *op {
+                        Op::Insert(key, partition) => Op::Insert(key, Self::weighted(partition)),
+                        Op::InsertIfNotExists(key, partition) => {
+                            Op::InsertIfNotExists(key, Self::weighted(partition))
+                        }
+                        Op::Remove(key) => Op::Remove(key),
+                    }),
+                    WriteOp::Item(partition_key, op) => match table.peek_mut(&partition_key) {
+                        Some(partition) => {
+                            partition.pin(item_keys[&partition_key].iter().cloned());
+                            let result = partition.apply_op(op);
+                            changed.insert(partition_key);
+                            result
+                        }
+                        None => Err(Error::KeyNotFound),
+                    },
+                };
+
+                if let Err(err) = result {
+                    applied = Err(Error::Batch(index, Box::new(err)));
+                    break;
+                }
+            }
+
+            table.unpin();
+            for partition_key in changed {
+                if let Some(partition) = table.peek_mut(&partition_key) {
+                    partition.unpin();
+                }
+                table.reweigh(&partition_key);
+            }
+
+            applied
+        })?
+    }
+
+    /// The keys of the partitions `batch` writes to, and of the items it writes to in each.
+    fn batch_keys(batch: &WriteBatch) -> (BTreeSet<String>, HashMap<String, BTreeSet<String>>) {
+        let mut partition_keys = BTreeSet::new();
+        let mut item_keys: HashMap<String, BTreeSet<String>> = HashMap::new();
+
+        for op in batch.ops() {
+            match op {
+                WriteOp::Partition(op) => {
+                    partition_keys.insert(op.key().clone());
+                }
+                WriteOp::Item(partition_key, op) => {
+                    partition_keys.insert(partition_key.clone());
+                    item_keys
+                        .entry(partition_key.clone())
+                        .or_default()
+                        .insert(op.key().clone());
+                }
+            }
+        }
+
+        (partition_keys, item_keys)
+    }
+
+    /// Checks every write of `batch`, then that the table and the partitions it writes to
+    /// have room for them with the keys of the batch pinned, see `Cache::check_room`.
+    fn check_batch(
+        table: &Table,
+        batch: &WriteBatch,
+        partition_keys: &BTreeSet<String>,
+        item_keys: &HashMap<String, BTreeSet<String>>,
+    ) -> Result<(), Error> {
+        let mut partitions = HashMap::new();
+        // the partition item writes apply to, as left by the writes before them, along with
+        // those writes
+        let mut items = HashMap::new();
+        // the item writes to partitions replaced by a later write of the batch
+        let mut replaced = Vec::new();
+
+        for (index, op) in batch.ops().iter().enumerate() {
+            let checked = match op {
+                WriteOp::Partition(op) => table.check(op, &mut partitions).map(|()| {
+                    let partition = match &**op {
+                        Op::Insert(_, partition) | Op::InsertIfNotExists(_, partition) => {
+                            Some(partition)
+                        }
+                        Op::Remove(_) => None,
+                    };
+                    let previous = items.insert(op.key(), (partition, HashMap::new(), Vec::new()));
+                    replaced.extend(previous.map(|previous| (op.key(), previous)));
+                }),
+                WriteOp::Item(partition_key, op) => {
+                    let (partition, staged, writes) = items
+                        .entry(partition_key)
+                        .or_insert_with(|| (table.peek(partition_key), HashMap::new(), Vec::new()));
+
+                    match partition {
+                        Some(partition) => partition
+                            .check(op, staged)
+                            .map(|()| writes.push((index, op))),
+                        None => Err(Error::KeyNotFound),
+                    }
+                }
+            };
+
+            checked.map_err(|err| Error::Batch(index, Box::new(err)))?;
+        }
+
+        table.check_room(
+            batch
+                .ops()
+                .iter()
+                .enumerate()
+                .filter_map(|(index, op)| match op {
+                    WriteOp::Partition(op) => Some((index, &**op)),
+                    WriteOp::Item(..) => None,
+                }),
+            partition_keys,
+        )?;
+
+        for (partition_key, (partition, _, writes)) in items.into_iter().chain(replaced) {
+            if writes.is_empty() {
+                continue;
+            }
+            if let Some(partition) = partition {
+                partition.check_room(writes, &item_keys[partition_key])?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns a copy of the partition, reading it through the loader of the table when the
+    /// table does not hold it. A loaded partition is stored in the table but not written back.
+    /// A partition with a write still queued is read from the queue, which is newer than the
+    /// loader. Use `with_table` to read a partition in place.
+    pub async fn get_partition(
+        &self,
+        table_name: &str,
+        partition_key: &str,
+    ) -> Result<Option<Partition>, loader::Error> {
+        let table = self.table(table_name)?;
+
+        let cached = table.read().get(partition_key).cloned();
+        if cached.is_some() {
+            return Ok(cached);
+        }
+
+        let loader = match &table.0.loader {
+            Some(loader) => loader,
+            None => return Ok(None),
+        };
+
+        let loaded = match loader.queued_write(partition_key) {
+            Some(Write::Store(partition)) => Some(*partition),
+            Some(Write::Delete) => None,
+            None => loader.loader.load(table_name, partition_key).await?,
+        };
+        let partition = match loaded {
+            Some(partition) => Self::weighted(partition),
+            None => return Ok(None),
+        };
+
+        // a write made while loading wins over the loaded partition
+        Ok(self.update_table(&table, false, |table| {
+            let _ = table.insert_if_not_exists(partition_key, partition);
+            table.peek(partition_key).cloned()
+        }))
+    }
+
+    /// Inserts or replaces the partition. Tables writing through write it to their loader
+    /// first, and are left untouched when that fails.
+    pub async fn put_partition(
+        &self,
         table_name: &str,
-        partition_key: &'static str,
+        partition_key: &str,
         value: Partition,
-    ) {
-        let table = self.tables.get_mut(table_name).unwrap();
-        table.insert(partition_key, value);
+    ) -> Result<(), loader::Error> {
+        let table = self.table(table_name)?;
+        let value = Self::weighted(value);
+
+        if let Some(loader) = &table.0.loader {
+            if table.writes_through() {
+                loader
+                    .loader
+                    .store(table_name, partition_key, &value)
+                    .await?;
+            }
+        }
+
+        self.update_table(&table, true, |table| table.insert(partition_key, value))?;
+        Ok(())
+    }
+
+    /// Removes the partition from the table and from the loader of the table, if any. The
+    /// partition only has to exist in one of them.
+    pub async fn delete_partition(
+        &self,
+        table_name: &str,
+        partition_key: &str,
+    ) -> Result<(), loader::Error> {
+        let table = self.table(table_name)?;
+
+        if let Some(loader) = &table.0.loader {
+            if table.writes_through() {
+                loader.loader.delete(table_name, partition_key).await?;
+            }
+        }
+
+        // the delete is queued below, whether or not the table held the partition
+        let removed = self.update_table(&table, false, |table| table.remove(partition_key));
+
+        match &table.0.loader {
+            Some(loader) => {
+                if loader.writes_behind() {
+                    loader.queue(partition_key.to_string(), Write::Delete);
+                }
+                Ok(())
+            }
+            None => Ok(removed?),
+        }
+    }
+
+    /// Sends the queued writes of every table writing behind, and returns the number of
+    /// partitions written. Tables are flushed one at a time.
+    pub async fn flush(&self) -> Result<usize, loader::Error> {
+        let tables: Vec<SharedTable> = self.tables().values().cloned().collect();
+        let mut count = 0;
+
+        for table in tables {
+            if let Some(loader) = &table.0.loader {
+                count += loader.flush(&table.0.name).await?;
+            }
+        }
+
+        Ok(count)
+    }
+
+    /// Spawns a tokio task that calls `flush` every `period`. Failed writes stay queued for
+    /// the next tick.
+    ///
+    /// Must be called from within a tokio runtime.
+    pub fn spawn_flusher(service: Arc<Self>, period: Duration) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+
+            loop {
+                interval.tick().await;
+                let _ = service.flush().await;
+            }
+        })
+    }
+
+    /// Indexes the items of every partition of the table by their `attribute`, see the
+    /// `index` module of the cache crate. The index is kept up to date by every later write.
+    pub fn create_index(
+        &self,
+        table_name: &str,
+        index_name: &str,
+        attribute: &str,
+    ) -> Result<(), Error> {
+        let table = self.table(table_name)?;
+        // the table lock keeps writers out until the index is in place
+        let guard = table.read();
+        let mut indexes = table.indexes();
+
+        if indexes.contains_key(index_name) {
+            return Err(Error::IndexAlreadyExists);
+        }
+
+        indexes.insert(
+            index_name.to_string(),
+            SecondaryIndex::build(attribute, &guard),
+        );
+        Ok(())
+    }
+
+    pub fn remove_index(&self, table_name: &str, index_name: &str) -> Result<(), Error> {
+        let table = self.table(table_name)?;
+        let removed = table.indexes().remove(index_name);
+        removed.map(|_| ()).ok_or(Error::IndexNotFound)
+    }
+
+    /// Returns a copy of the items whose indexed attribute is within `range`, as
+    /// `(partition_key, sort_key, value)`, ordered by attribute, then partition and sort key.
+    pub fn query_index<R>(
+        &self,
+        table_name: &str,
+        index_name: &str,
+        range: R,
+    ) -> Result<Vec<(String, String, Value)>, Error>
+    where
+        R: RangeBounds<IndexKey>,
+    {
+        let table = self.table(table_name)?;
+        let guard = table.read();
+        let indexes = table.indexes();
+        let index = indexes.get(index_name).ok_or(Error::IndexNotFound)?;
+
+        Ok(index
+            .range(range)
+            .filter_map(|(_, partition_key, sort_key)| {
+                let value = guard.peek(partition_key)?.peek(sort_key)?;
+                Some((
+                    partition_key.to_string(),
+                    sort_key.to_string(),
+                    value.clone(),
+                ))
+            })
+            .collect())
+    }
+
+    /// Rolls up the counters of every table. Tables are locked one at a time.
+    pub fn stats(&self) -> ServiceStats {
+        let tables: Vec<SharedTable> = self.tables().values().cloned().collect();
+        let mut stats = ServiceStats::default();
+
+        for table in tables {
+            let guard = table.read();
+            let table_stats = TableStats {
+                name: table.0.name.clone(),
+                len: guard.len() as u64,
+                weight: guard.weight() as u64,
+                partitions: guard.stats(),
+                items: guard.values().fold(Stats::default(), |items, partition| {
+                    items + partition.stats()
+                }),
+            };
+            drop(guard);
+
+            stats.len += table_stats.len;
+            stats.weight += table_stats.weight;
+            stats.partitions += table_stats.partitions;
+            stats.items += table_stats.items;
+            stats.tables.push(table_stats);
+        }
+
+        stats.tables.sort_by(|a, b| a.name.cmp(&b.name));
+        stats
+    }
+
+    /// Writes every table, partition and item to `path`, see the `snapshot` module for the format.
+    ///
+    /// Tables are locked one at a time, so each table is consistent on its own. The file is
+    /// only replaced once the snapshot is complete. Eviction policies are not saved.
+    pub fn snapshot<P>(&self, path: P) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let capacity = self.tables().capacity();
+        let tables: Vec<SharedTable> = self.tables().values().cloned().collect();
+
+        let tables: Vec<TableSnapshot> = tables
+            .iter()
+            .map(|table| {
+                let guard = table.read();
+                let partitions = guard
+                    .iter()
+                    .map(|(partition_key, partition)| {
+                        let ttl = guard.ttl_of(partition_key);
+                        PartitionSnapshot::new(partition_key, partition, ttl)
+                    })
+                    .collect();
+
+                TableSnapshot {
+                    name: table.0.name.clone(),
+                    capacity: guard.capacity() as u64,
+                    ttl_ms: guard.ttl().map(millis),
+                    max_weight: guard.max_weight().map(|max_weight| max_weight as u64),
+                    partitions,
+                }
+            })
+            .collect();
+
+        let service = ServiceSnapshot {
+            capacity: capacity as u64,
+            max_weight: self.max_weight().map(|max_weight| max_weight as u64),
+            tables: tables.len() as u64,
+        };
+
+        snapshot::write(path.as_ref(), &service, &tables)
+    }
+
+    /// Replaces every table with the ones saved by `snapshot`.
+    ///
+    /// The whole file is read and checked before anything is replaced, so a missing or
+    /// damaged snapshot leaves the service as it was. No event is emitted for the restored
+    /// partitions. A restored table keeps the eviction policy and the loader of the table it
+    /// replaces, without its queued writes, and nothing is written to it, while a table the
+    /// service did not have evicts with `Lru`. Its secondary indexes are rebuilt.
+    pub fn restore<P>(&self, path: P) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let (service, tables) = snapshot::read(path.as_ref())?;
+        let mut restored = Cache::new(service.capacity as usize);
+
+        for snapshot in tables {
+            let name = snapshot.name.clone();
+            let previous = self.table(&name).ok();
+            let loader = previous.as_ref().and_then(|table| {
+                table
+                    .0
+                    .loader
+                    .as_ref()
+                    .map(|loader| TableLoader::new(loader.loader.clone(), loader.mode))
+            });
+            let policy = previous.as_ref().map(|table| table.read().empty_policy());
+            let table = Self::restore_table(snapshot, policy, loader)?;
+
+            if let Some(previous) = previous {
+                let guard = table.read();
+                *table.indexes() = previous
+                    .indexes()
+                    .iter()
+                    .map(|(index_name, index)| {
+                        (
+                            index_name.clone(),
+                            SecondaryIndex::build(index.attribute(), &guard),
+                        )
+                    })
+                    .collect();
+            }
+
+            restored
+                .insert(name.clone(), table)
+                .map_err(|err| snapshot::invalid(&format!("table {}: {}", name, err)))?;
+        }
+
+        *self.tables_mut() = restored;
+        self.set_max_weight(service.max_weight.map(|max_weight| max_weight as usize));
+        Ok(())
     }
 
-    pub fn remove_partition(&mut self, table_name: &str, partition_key: &'static str) {
-        let table: &mut Cache<Partition> = self.tables.get_mut(table_name).unwrap();
-        table.remove(partition_key);
+    fn restore_table(
+        snapshot: TableSnapshot,
+        policy: Option<Box<dyn EvictionPolicy<String>>>,
+        loader: Option<TableLoader>,
+    ) -> io::Result<SharedTable> {
+        let invalid = |err: Error| snapshot::invalid(&format!("table {}: {}", snapshot.name, err));
+        let mut options = TableOptions::new(snapshot.capacity as usize);
+        if let Some(policy) = policy {
+            options.policy = policy;
+        }
+        if let Some(max_weight) = snapshot.max_weight {
+            options = options.max_weight(max_weight as usize);
+        }
+
+        // the default TTL is only set at the end, partitions keep the TTL they had left
+        let (mut table, _) = options.build();
+        let mut partition_keys = Vec::new();
+
+        for partition in snapshot.partitions {
+            let partition_key = partition.key.clone();
+            let (restored, ttl) = partition.into_partition().map_err(invalid)?;
+            let restored = Self::weighted(restored);
+            partition_keys.push(partition_key.clone());
+
+            match ttl {
+                Some(ttl) => table.insert_with_ttl(partition_key, restored, ttl),
+                None => table.insert(partition_key, restored),
+            }
+            .map_err(invalid)?;
+        }
+
+        table.set_ttl(snapshot.ttl_ms.map(Duration::from_millis));
+        table.reset_stats();
+
+        let table = SharedTable::new(&snapshot.name, table, loader);
+        {
+            let mut guard = table.write();
+            for partition_key in partition_keys {
+                if let Some(partition) = guard.peek_mut(&partition_key) {
+                    table.watch_partition(partition_key, partition);
+                }
+            }
+        }
+
+        Ok(table)
+    }
+
+    /// Removes every expired partition and item and emits an `EVENT_EXPIRE` event for each one.
+    ///
+    /// Returns the number of expired entries. Tables are locked one at a time.
+    pub fn remove_expired(&self) -> usize {
+        let tables: Vec<SharedTable> = self.tables().values().cloned().collect();
+        let mut count = 0;
+
+        for table in tables {
+            let mut guard = table.write();
+            count += guard.purge_expired().len();
+            let mut shrunk = Vec::new();
+
+            for (partition_key, partition) in guard.iter_mut() {
+                let expired = partition.purge_expired().len();
+                if expired > 0 {
+                    count += expired;
+                    shrunk.push(partition_key.clone());
+                }
+            }
+
+            for partition_key in shrunk {
+                guard.reweigh(&partition_key);
+            }
+
+            table.bump_versions(&mut guard);
+            table.update_indexes(&guard);
+            table.queue_writes(&guard);
+            table.0.weight.store(guard.weight(), Ordering::Relaxed);
+            drop(guard);
+
+            self.emit(&table);
+        }
+
+        count
     }
 
-    pub fn partition_exists(&self, table_name: &str, partition_key: &'static str) -> bool {
-        let table: &Cache<Partition> = self.tables.get(table_name).unwrap();
-        table.contains_key(partition_key)
+    fn weighted(mut partition: Partition) -> Partition {
+        partition.set_weigher(ValueWeigher);
+        partition
+    }
+
+    fn shrink_to_max_weight(&self, table: &SharedTable) {
+        let max_weight = match self.max_weight() {
+            Some(max_weight) => max_weight,
+            None => return,
+        };
+
+        while self.weight() > max_weight {
+            let mut guard = table.write();
+            let evicted = guard.evict();
+            table.update_indexes(&guard);
+            table.0.weight.store(guard.weight(), Ordering::Relaxed);
+
+            if evicted.is_none() {
+                return;
+            }
+        }
+    }
+
+    fn emit(&self, table: &SharedTable) {
+        let pending = table.take_pending();
+        if pending.is_empty() {
+            return;
+        }
+
+        let mut events = self.events.lock().unwrap_or_else(PoisonError::into_inner);
+        for (name, event) in pending {
+            events.emit(name.to_string(), None, Some(event));
+        }
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::loader::{tests::MemoryStorage, StorageLoader};
+    use cache::eviction::Fifo;
+    use std::sync::{mpsc, Barrier};
+    use std::thread;
+    use valu3::value::Value;
+
+    fn expired_events(service: &CacheService) -> Arc<Mutex<Vec<CacheEvent>>> {
+        let expired = Arc::new(Mutex::new(Vec::new()));
+        let expired_clone = expired.clone();
+
+        service.events.lock().unwrap().on(
+            EVENT_EXPIRE.to_string(),
+            Box::new(move |_, event| {
+                if let Some(event) = event {
+                    expired_clone.lock().unwrap().push(event);
+                }
+            }),
+        );
+
+        expired
+    }
+
+    /// A copy of the partition as the table holds it, without reading through a loader.
+    fn cached_partition(
+        service: &CacheService,
+        table_name: &str,
+        partition_key: &str,
+    ) -> Option<Partition> {
+        service
+            .with_table(table_name, |table| table.get(partition_key).cloned())
+            .ok()
+            .flatten()
+    }
+
+    #[test]
+    fn test_change_events() {
+        let service = CacheService::new(10, Events::build());
+        let events = Arc::new(Mutex::new(Vec::new()));
+
+        for name in [EVENT_INSERT, EVENT_UPDATE, EVENT_REMOVE, EVENT_EVICT] {
+            let events = events.clone();
+            service.events.lock().unwrap().on(
+                name.to_string(),
+                Box::new(move |_, event| {
+                    if let Some(event) = event {
+                        events.lock().unwrap().push((name, event));
+                    }
+                }),
+            );
+        }
+
+        service.create_table("users", 1).unwrap();
+        service
+            .create_partition("users", "user1", Partition::new(10))
+            .unwrap();
+        service
+            .with_table_mut("users", |table| {
+                let partition = table.get_mut("user1").unwrap();
+                partition.insert("name", Value::from("John")).unwrap();
+                partition.insert("name", Value::from("Jane")).unwrap();
+            })
+            .unwrap();
+        service
+            .update_partition("users", "user1", Partition::new(10))
+            .unwrap();
+        service
+            .create_partition("users", "user2", Partition::new(10))
+            .unwrap();
+        service.remove_partition("users", "user2").unwrap();
+
+        let event = |partition_key: &str, sort_key: Option<&str>| CacheEvent {
+            table: "users".to_string(),
+            partition_key: partition_key.to_string(),
+            sort_key: sort_key.map(|sort_key| sort_key.to_string()),
+        };
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                (EVENT_INSERT, event("user1", None)),
+                (EVENT_INSERT, event("user1", Some("name"))),
+                (EVENT_UPDATE, event("user1", Some("name"))),
+                (EVENT_UPDATE, event("user1", None)),
+                (EVENT_EVICT, event("user1", None)),
+                (EVENT_INSERT, event("user2", None)),
+                (EVENT_REMOVE, event("user2", None)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_stats() {
+        let service = CacheService::new(10, Events::build());
+        service.create_table("users", 1).unwrap();
+        service.create_table("orders", 10).unwrap();
+
+        let mut partition = Partition::new(10);
+        partition.insert("name", Value::from("John")).unwrap();
+        service
+            .create_partition("users", "user1", partition)
+            .unwrap();
+        service
+            .create_partition("users", "user2", Partition::new(10))
+            .unwrap();
+        service
+            .create_partition("orders", "order1", Partition::new(10))
+            .unwrap();
+
+        service
+            .with_table("users", |table| {
+                let partition = table.get("user2").unwrap();
+                partition.get("name");
+            })
+            .unwrap();
+        cached_partition(&service, "orders", "order2");
+
+        let stats = service.stats();
+        let users = &stats.tables[1];
+
+        assert_eq!(stats.tables.len(), 2);
+        assert_eq!(users.name, "users");
+        assert_eq!(users.len, 1);
+        assert_eq!(users.partitions.inserts, 2);
+        assert_eq!(users.partitions.evictions, 1);
+        assert_eq!(users.partitions.hits, 1);
+        assert_eq!(users.items.misses, 1);
+        assert_eq!(stats.partitions.inserts, 3);
+        assert_eq!(stats.partitions.misses, 1);
+        assert_eq!(stats.len, 2);
+
+        let json = stats.to_json();
+        assert!(json.contains("\"tables\""));
+        assert!(json.contains("\"evictions\""));
+    }
+
+    #[test]
+    fn test_snapshot_restore() {
+        let path = std::env::temp_dir().join(format!("cache-snapshot-{}", std::process::id()));
+        let service = CacheService::new(10, Events::build());
+        service
+            .create_table("users", TableOptions::new(5).ttl(Duration::from_secs(60)))
+            .unwrap();
+
+        let mut partition = Partition::new(3);
+        partition.insert("name", Value::from("John")).unwrap();
+        partition
+            .insert_with_ttl("session", Value::from("abc"), Duration::from_secs(60))
+            .unwrap();
+        partition
+            .insert_with_ttl("expired", Value::from(1), Duration::ZERO)
+            .unwrap();
+        service
+            .create_partition("users", "user1", partition)
+            .unwrap();
+        service
+            .create_partition_with_ttl("users", "user2", Partition::new(3), Duration::ZERO)
+            .unwrap();
+
+        service.snapshot(&path).unwrap();
+
+        let restored = CacheService::new(1, Events::build());
+        restored.create_table("stale", 1).unwrap();
+        restored.restore(&path).unwrap();
+
+        assert!(!restored.table_exists("stale"));
+        assert!(!restored.partition_exists("users", "user2"));
+        assert_eq!(
+            restored
+                .with_table("users", |table| table.capacity())
+                .unwrap(),
+            5
+        );
+        assert_eq!(
+            restored.with_table("users", |table| table.ttl()).unwrap(),
+            Some(Duration::from_secs(60))
+        );
+
+        let partition = cached_partition(&restored, "users", "user1").unwrap();
+        assert_eq!(partition.capacity(), 3);
+        assert_eq!(partition.get("name"), Some(&Value::from("John")));
+        assert!(partition.ttl_of("name").is_none());
+        assert!(partition.ttl_of("session").unwrap() > Duration::from_secs(50));
+        assert!(partition.get("expired").is_none());
+
+        // a damaged file leaves the service untouched
+        std::fs::write(&path, b"garbage").unwrap();
+        assert!(restored.restore(&path).is_err());
+        assert!(restored.partition_exists("users", "user1"));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_restore_keeps_eviction_policy() {
+        let path =
+            std::env::temp_dir().join(format!("cache-snapshot-policy-{}", std::process::id()));
+        let service = CacheService::new(10, Events::build());
+        service
+            .create_table("users", TableOptions::new(2).policy(Fifo::new()))
+            .unwrap();
+        for partition_key in ["user1", "user2"] {
+            service
+                .create_partition("users", partition_key, Partition::new(1))
+                .unwrap();
+        }
+
+        service.snapshot(&path).unwrap();
+        service.restore(&path).unwrap();
+
+        // the first partition in goes first, however recently it was read
+        assert!(cached_partition(&service, "users", "user1").is_some());
+        service
+            .create_partition("users", "user3", Partition::new(1))
+            .unwrap();
+        assert!(!service.partition_exists("users", "user1"));
+        assert!(service.partition_exists("users", "user2"));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_versions() {
+        let service = CacheService::new(10, Events::build());
+        service.create_table("users", 10).unwrap();
+
+        let mut partition = Partition::new(10);
+        partition.insert("name", Value::from("John")).unwrap();
+        service
+            .create_partition("users", "user1", partition)
+            .unwrap();
+
+        let (partition, version) = service
+            .get_partition_with_version("users", "user1")
+            .unwrap();
+        assert_eq!(service.partition_version("users", "user1"), Some(version));
+        assert_eq!(service.partition_version("users", "user2"), None);
+        assert_eq!(service.partition_version("orders", "order1"), None);
+
+        // changing an item gives the partition a new version
+        let item_version = service.item_version("users", "user1", "name").unwrap();
+        let swapped = service
+            .compare_and_swap_item("users", "user1", "name", item_version, Value::from("Jane"))
+            .unwrap();
+        assert_eq!(
+            service.item_version("users", "user1", "name"),
+            Some(swapped)
+        );
+        let jack = Value::from("Jack");
+        assert!(matches!(
+            service.compare_and_swap_item("users", "user1", "name", item_version, jack),
+            Err(Error::VersionMismatch)
+        ));
+
+        assert!(matches!(
+            service.compare_and_swap_partition("users", "user1", version, partition.clone()),
+            Err(Error::VersionMismatch)
+        ));
+        let version = service.partition_version("users", "user1").unwrap();
+        service
+            .with_table_mut("users", |table| {
+                table
+                    .get_mut("user1")
+                    .unwrap()
+                    .insert("age", Value::from(30))
+                    .unwrap();
+            })
+            .unwrap();
+        assert!(service.partition_version("users", "user1").unwrap() > version);
+
+        let version = service.partition_version("users", "user1").unwrap();
+        let swapped = service
+            .compare_and_swap_partition("users", "user1", version, partition)
+            .unwrap();
+        assert!(swapped > version);
+        assert_eq!(
+            cached_partition(&service, "users", "user1")
+                .unwrap()
+                .get("name"),
+            Some(&Value::from("John"))
+        );
+    }
+
+    #[test]
+    fn test_query_index() {
+        let service = CacheService::new(10, Events::build());
+        service
+            .create_table("users", TableOptions::new(10).max_weight(1_000))
+            .unwrap();
+
+        let user = |status: &str| Value::from(vec![("status", status)]);
+        let mut partition = Partition::new(10);
+        partition.insert("ana", user("open")).unwrap();
+        partition.insert("bob", user("closed")).unwrap();
+        service
+            .create_partition("users", "team1", partition)
+            .unwrap();
+
+        service
+            .create_index("users", "by_status", "status")
+            .unwrap();
+        assert!(matches!(
+            service.create_index("users", "by_status", "status"),
+            Err(Error::IndexAlreadyExists)
+        ));
+
+        let open = || IndexKey::from("open")..=IndexKey::from("open");
+        let sort_keys = |items: Vec<(String, String, Value)>| -> Vec<String> {
+            items.into_iter().map(|(_, sort_key, _)| sort_key).collect()
+        };
+
+        assert_eq!(
+            sort_keys(service.query_index("users", "by_status", open()).unwrap()),
+            ["ana"]
+        );
+
+        // inserts, in-place updates and removals are indexed
+        let mut partition = Partition::new(10);
+        partition.insert("carl", user("open")).unwrap();
+        service
+            .create_partition("users", "team2", partition)
+            .unwrap();
+        service
+            .with_table_mut("users", |table| {
+                let partition = table.get_mut("team1").unwrap();
+                partition.insert("bob", user("open")).unwrap();
+                partition.remove("ana").unwrap();
+            })
+            .unwrap();
+
+        let items = service.query_index("users", "by_status", open()).unwrap();
+        assert_eq!(
+            items,
+            vec![
+                ("team1".to_string(), "bob".to_string(), user("open")),
+                ("team2".to_string(), "carl".to_string(), user("open")),
+            ]
+        );
+        assert_eq!(
+            sort_keys(service.query_index("users", "by_status", ..).unwrap()).len(),
+            2
+        );
+
+        // evictions to fit the table under max_weight are indexed as well
+        service.set_max_weight(Some(service.weight()));
+        service
+            .create_partition("users", "team3", Partition::new(10))
+            .unwrap();
+        assert!(!service.partition_exists("users", "team2"));
+        assert_eq!(
+            sort_keys(service.query_index("users", "by_status", open()).unwrap()),
+            ["bob"]
+        );
+
+        service.remove_partition("users", "team1").unwrap();
+        assert!(service
+            .query_index("users", "by_status", open())
+            .unwrap()
+            .is_empty());
+
+        service.remove_index("users", "by_status").unwrap();
+        assert!(matches!(
+            service.query_index("users", "by_status", open()),
+            Err(Error::IndexNotFound)
+        ));
+    }
+
+    #[test]
+    fn test_write_batch() {
+        let service = CacheService::new(10, Events::build());
+        service.create_table("users", 10).unwrap();
+
+        let mut partition = Partition::new(10);
+        partition.insert("name", Value::from("John")).unwrap();
+        service
+            .create_partition("users", "user1", partition)
+            .unwrap();
+
+        let batch = WriteBatch::new()
+            .put("user1", "age", Value::from(30))
+            .put_partition("user2", Partition::new(10))
+            .put_if_not_exists("user2", "name", Value::from("Jane"))
+            .put_if_not_exists("user1", "name", Value::from("Jack"));
+
+        assert!(matches!(
+            service.write_batch("users", batch),
+            Err(Error::Batch(3, err)) if matches!(*err, Error::SortKeyExists)
+        ));
+        assert!(!service.partition_exists("users", "user2"));
+        assert_eq!(
+            cached_partition(&service, "users", "user1")
+                .unwrap()
+                .get("age"),
+            None
+        );
+
+        let batch =
+            WriteBatch::new()
+                .delete_partition("user1")
+                .put("user1", "age", Value::from(30));
+        assert!(matches!(
+            service.write_batch("users", batch),
+            Err(Error::Batch(1, err)) if matches!(*err, Error::KeyNotFound)
+        ));
+
+        let weight = service.weight();
+        let batch = WriteBatch::new()
+            .put("user1", "age", Value::from(30))
+            .delete("user1", "name")
+            .put_partition("user2", Partition::new(10))
+            .put_if_not_exists("user2", "name", Value::from("Jane"));
+        service.write_batch("users", batch).unwrap();
+
+        let user1 = cached_partition(&service, "users", "user1").unwrap();
+        assert_eq!(user1.get("age"), Some(&Value::from(30)));
+        assert_eq!(user1.get("name"), None);
+        let user2 = cached_partition(&service, "users", "user2").unwrap();
+        assert_eq!(user2.get("name"), Some(&Value::from("Jane")));
+        assert!(service.weight() > weight);
+    }
+
+    #[test]
+    fn test_write_batch_at_capacity() {
+        let service = CacheService::new(10, Events::build());
+        service.create_table("users", 2).unwrap();
+        service
+            .create_partition("users", "user1", Partition::new(10))
+            .unwrap();
+        service
+            .create_partition("users", "user2", Partition::new(10))
+            .unwrap();
+
+        // user1 is the least recently used, but the batch writes to it after inserting user3
+        let mut user3 = Partition::new(2);
+        user3.insert("a", Value::from(1)).unwrap();
+        user3.insert("b", Value::from(2)).unwrap();
+        let batch =
+            WriteBatch::new()
+                .put_partition("user3", user3)
+                .put("user1", "age", Value::from(31));
+        service.write_batch("users", batch).unwrap();
+
+        assert!(!service.partition_exists("users", "user2"));
+        assert_eq!(
+            cached_partition(&service, "users", "user1")
+                .unwrap()
+                .get("age"),
+            Some(&Value::from(31))
+        );
+
+        // the same goes for the items of a full partition
+        let batch = WriteBatch::new()
+            .put("user3", "c", Value::from(3))
+            .delete("user3", "a");
+        service.write_batch("users", batch).unwrap();
+        let user3 = cached_partition(&service, "users", "user3").unwrap();
+        assert_eq!(user3.len(), 1);
+        assert_eq!(user3.get("c"), Some(&Value::from(3)));
+
+        // every partition is pinned by the batch, nothing can make room for user4
+        let batch = WriteBatch::new()
+            .put_partition("user4", Partition::new(10))
+            .put("user1", "name", Value::from("John"))
+            .put("user3", "d", Value::from(4));
+        assert!(matches!(
+            service.write_batch("users", batch),
+            Err(Error::Batch(0, err)) if matches!(*err, Error::CapacityExceeded)
+        ));
+
+        let batch = WriteBatch::new()
+            .put("user3", "d", Value::from(4))
+            .put("user3", "e", Value::from(5))
+            .put("user3", "c", Value::from(30));
+        assert!(matches!(
+            service.write_batch("users", batch),
+            Err(Error::Batch(1, err)) if matches!(*err, Error::CapacityExceeded)
+        ));
+
+        assert!(!service.partition_exists("users", "user4"));
+        assert_eq!(
+            cached_partition(&service, "users", "user1")
+                .unwrap()
+                .get("name"),
+            None
+        );
+        let user3 = cached_partition(&service, "users", "user3").unwrap();
+        assert_eq!(user3.len(), 1);
+        assert_eq!(user3.get("c"), Some(&Value::from(3)));
+    }
+
+    fn storage_loader() -> (MemoryStorage, Arc<dyn Loader>) {
+        let storage = MemoryStorage::default();
+        (storage.clone(), Arc::new(StorageLoader::new(storage)))
+    }
+
+    fn stored(storage: &MemoryStorage, partition_key: &str) -> bool {
+        storage
+            .objects
+            .lock()
+            .unwrap()
+            .contains_key(&StorageLoader::<MemoryStorage>::key("users", partition_key))
+    }
+
+    #[tokio::test]
+    async fn test_read_through() {
+        let (storage, loader) = storage_loader();
+        let mut partition = Partition::new(10);
+        partition.insert("name", Value::from("John")).unwrap();
+        loader.store("users", "user1", &partition).await.unwrap();
+
+        let service = CacheService::new(10, Events::build());
+        service
+            .create_table(
+                "users",
+                TableOptions::new(10).loader(loader, WriteMode::Through),
+            )
+            .unwrap();
+
+        assert!(!service.partition_exists("users", "user1"));
+        let loaded = service
+            .get_partition("users", "user1")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(loaded.peek("name"), Some(&Value::from("John")));
+        assert!(service.partition_exists("users", "user1"));
+
+        // served from the cache once loaded
+        storage.objects.lock().unwrap().clear();
+        assert!(service
+            .get_partition("users", "user1")
+            .await
+            .unwrap()
+            .is_some());
+        assert!(service
+            .get_partition("users", "user2")
+            .await
+            .unwrap()
+            .is_none());
+        assert!(matches!(
+            service.get_partition("orders", "order1").await,
+            Err(loader::Error::Cache(Error::TableNotFound))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_write_through() {
+        let (storage, loader) = storage_loader();
+        let service = CacheService::new(10, Events::build());
+        service
+            .create_table(
+                "users",
+                TableOptions::new(10).loader(loader, WriteMode::Through),
+            )
+            .unwrap();
+
+        service
+            .put_partition("users", "user1", Partition::new(10))
+            .await
+            .unwrap();
+        assert!(stored(&storage, "user1"));
+        assert!(service.partition_exists("users", "user1"));
+
+        service.delete_partition("users", "user1").await.unwrap();
+        assert!(!stored(&storage, "user1"));
+        assert!(!service.partition_exists("users", "user1"));
+
+        // writes that cannot wait on the loader are refused
+        assert!(matches!(
+            service.create_partition("users", "user2", Partition::new(10)),
+            Err(Error::TableWritesThrough)
+        ));
+        assert!(matches!(
+            service.write_batch("users", WriteBatch::new().delete_partition("user2")),
+            Err(Error::TableWritesThrough)
+        ));
+        assert!(matches!(
+            service.with_table_mut("users", |table| table.clear()),
+            Err(Error::TableWritesThrough)
+        ));
+        assert!(!stored(&storage, "user2"));
+    }
+
+    #[tokio::test]
+    async fn test_write_behind() {
+        let (storage, loader) = storage_loader();
+        let service = CacheService::new(10, Events::build());
+        service
+            .create_table(
+                "users",
+                TableOptions::new(1).loader(loader, WriteMode::Behind { batch: 2 }),
+            )
+            .unwrap();
+
+        service
+            .put_partition("users", "user1", Partition::new(10))
+            .await
+            .unwrap();
+        service
+            .with_table_mut("users", |table| {
+                table
+                    .get_mut("user1")
+                    .unwrap()
+                    .insert("name", Value::from("John"))
+                    .unwrap();
+            })
+            .unwrap();
+        assert!(!stored(&storage, "user1"));
+
+        // evicting user1 keeps its queued write
+        service
+            .create_partition("users", "user2", Partition::new(10))
+            .unwrap();
+        assert!(!service.partition_exists("users", "user1"));
+
+        // until it is flushed, the queued write is newer than the storage
+        let loaded = service
+            .get_partition("users", "user1")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(loaded.peek("name"), Some(&Value::from("John")));
+        assert_eq!(service.flush().await.unwrap(), 2);
+        assert!(stored(&storage, "user1"));
+        assert!(stored(&storage, "user2"));
+
+        let loaded = service
+            .get_partition("users", "user1")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(loaded.peek("name"), Some(&Value::from("John")));
+        // loading is not written back
+        assert_eq!(service.flush().await.unwrap(), 0);
+
+        service.delete_partition("users", "user2").await.unwrap();
+        assert!(stored(&storage, "user2"));
+        assert_eq!(service.flush().await.unwrap(), 1);
+        assert!(!stored(&storage, "user2"));
+    }
+
+    #[test]
+    fn test_remove_expired() {
+        let service = CacheService::new(10, Events::build());
+        let expired = expired_events(&service);
+
+        service.create_table("sessions", 10).unwrap();
+
+        let mut partition = Partition::new(10);
+        partition.insert("token", Value::from("abc")).unwrap();
+        partition
+            .insert_with_ttl("window", Value::from(1), Duration::ZERO)
+            .unwrap();
+
+        service
+            .create_partition("sessions", "user1", partition)
+            .unwrap();
+        service
+            .create_partition_with_ttl("sessions", "user2", Partition::new(10), Duration::ZERO)
+            .unwrap();
+
+        assert!(cached_partition(&service, "sessions", "user2").is_none());
+        assert_eq!(service.remove_expired(), 2);
+        assert_eq!(service.remove_expired(), 0);
+
+        let partition = cached_partition(&service, "sessions", "user1").unwrap();
+        assert!(partition.get("token").is_some());
+        assert!(partition.get("window").is_none());
+
+        let mut expired = expired.lock().unwrap().clone();
+        expired.sort_by(|a, b| a.partition_key.cmp(&b.partition_key));
+
+        assert_eq!(
+            expired,
+            vec![
+                CacheEvent {
+                    table: "sessions".to_string(),
+                    partition_key: "user1".to_string(),
+                    sort_key: Some("window".to_string()),
+                },
+                CacheEvent {
+                    table: "sessions".to_string(),
+                    partition_key: "user2".to_string(),
+                    sort_key: None,
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sweeper() {
+        let service = CacheService::build(10, Events::build());
+        let expired = expired_events(&service);
+
+        service
+            .create_table("sessions", TableOptions::new(10).ttl(Duration::ZERO))
+            .unwrap();
+        service
+            .create_partition("sessions", "user1", Partition::new(10))
+            .unwrap();
+
+        let sweeper = CacheService::spawn_sweeper(service.clone(), Duration::from_millis(10));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        sweeper.abort();
+
+        assert_eq!(expired.lock().unwrap().len(), 1);
+        assert!(!service.partition_exists("sessions", "user1"));
+    }
+
+    #[test]
+    fn test_max_weight() {
+        let service = CacheService::new(10, Events::build());
+        service
+            .create_table("users", TableOptions::new(10).max_weight(40))
+            .unwrap();
+        service.create_table("orders", 10).unwrap();
+        service.set_max_weight(Some(60));
+
+        let mut partition = Partition::new(10);
+        partition.insert("name", Value::from("0123456789")).unwrap();
+
+        service
+            .create_partition("users", "user1", partition.clone())
+            .unwrap();
+        service
+            .create_partition("users", "user2", partition.clone())
+            .unwrap();
+        assert_eq!(service.table_weight("users"), Some(38));
+
+        service
+            .create_partition("users", "user3", partition.clone())
+            .unwrap();
+        assert_eq!(service.table_weight("users"), Some(38));
+        assert!(!service.partition_exists("users", "user1"));
+
+        service
+            .create_partition("orders", "order1", partition.clone())
+            .unwrap();
+        assert_eq!(service.weight(), 58);
+
+        service
+            .create_partition("orders", "order2", partition)
+            .unwrap();
+        assert!(service.weight() <= 60);
+        assert!(!service.partition_exists("orders", "order1"));
+        assert!(service.partition_exists("users", "user2"));
+    }
+
+    #[test]
+    fn test_missing_table() {
+        let service = CacheService::new(10, Events::build());
+
+        assert!(matches!(
+            service.create_partition("users", "user1", Partition::new(10)),
+            Err(Error::TableNotFound)
+        ));
+        assert!(cached_partition(&service, "users", "user1").is_none());
+        assert!(!service.partition_exists("users", "user1"));
+    }
+
+    #[test]
+    fn test_readers_do_not_wait_on_other_tables() {
+        let service = CacheService::build(10, Events::build());
+        service.create_table("busy", 10).unwrap();
+        service.create_table("idle", 10).unwrap();
+        service
+            .create_partition("idle", "user1", Partition::new(10))
+            .unwrap();
+
+        let locked = Arc::new(Barrier::new(2));
+        let (release, released) = mpsc::channel::<()>();
+
+        let writer = {
+            let service = service.clone();
+            let locked = locked.clone();
+
+            thread::spawn(move || {
+                service
+                    .with_table_mut("busy", |_| {
+                        locked.wait();
+                        released.recv().unwrap();
+                    })
+                    .unwrap();
+            })
+        };
+
+        locked.wait();
+        assert!(service.partition_exists("idle", "user1"));
+        service
+            .create_partition("idle", "user2", Partition::new(10))
+            .unwrap();
+
+        release.send(()).unwrap();
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn test_concurrent_stress() {
+        const THREADS: usize = 8;
+        const OPERATIONS: usize = 500;
+
+        let service = CacheService::build(THREADS + 1, Events::build());
+        service
+            .create_table("shared", THREADS * OPERATIONS)
+            .unwrap();
+
+        for thread in 0..THREADS {
+            service
+                .create_table(&format!("table{}", thread), OPERATIONS)
+                .unwrap();
+        }
+
+        let handles: Vec<_> = (0..THREADS)
+            .map(|thread| {
+                let service = service.clone();
+
+                thread::spawn(move || {
+                    let own_table = format!("table{}", thread);
+
+                    for operation in 0..OPERATIONS {
+                        let partition_key = format!("{}-{}", thread, operation);
+                        let mut partition = Partition::new(10);
+                        partition
+                            .insert("value", Value::from(operation as i32))
+                            .unwrap();
+
+                        service
+                            .create_partition(&own_table, &partition_key, partition.clone())
+                            .unwrap();
+                        service
+                            .create_partition("shared", &partition_key, partition)
+                            .unwrap();
+
+                        assert!(service.partition_exists(&own_table, &partition_key));
+                        assert!(cached_partition(&service, "shared", &partition_key).is_some());
+
+                        if operation % 2 == 0 {
+                            service.remove_partition("shared", &partition_key).unwrap();
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        for thread in 0..THREADS {
+            let own_table = format!("table{}", thread);
+            assert_eq!(
+                service.with_table(&own_table, |table| table.len()).unwrap(),
+                OPERATIONS
+            );
+        }
+
+        assert_eq!(
+            service.with_table("shared", |table| table.len()).unwrap(),
+            THREADS * OPERATIONS / 2
+        );
+    }
+}
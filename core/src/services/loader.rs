@@ -0,0 +1,543 @@
+//! Reading partitions from a storage backend and writing them back.
+//!
+//! A table created with `TableOptions::loader` reads the partitions it does not hold through
+//! its `Loader`, see `CacheService::get_partition`. Writes reach the loader according to the
+//! `WriteMode` of the table:
+//!
+//! - `WriteMode::Through`: `CacheService::put_partition` and `CacheService::delete_partition`
+//!   only return once the loader has written the change, and leave the cache untouched when
+//!   it fails. The other writes, such as `CacheService::with_table_mut`, cannot wait on the
+//!   loader and fail with `Error::TableWritesThrough` of the cache crate.
+//! - `WriteMode::Behind`: every change made to the table queues a write of the partition.
+//!   Writes are coalesced per partition and sent in batches by `CacheService::flush`, or
+//!   periodically by the task started with `CacheService::spawn_flusher`. A write stays queued,
+//!   and is read instead of the storage, until the loader has written it.
+//!
+//! Partitions evicted from or expired in the cache are never deleted from the storage.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Debug, Display};
+use std::future::Future;
+use std::ops::Bound;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use cache::{cache::Error as CacheError, partition::Partition};
+use futures::future::join_all;
+use sinfonia_sdk::{Storage, StorageListObjectsParams};
+
+use super::snapshot::{self, PartitionSnapshot};
+
+#[derive(Debug)]
+pub enum Error {
+    Cache(CacheError),
+    /// The storage backend failed, with its error formatted with `Debug`.
+    Storage(String),
+    /// The stored object under this key is not a partition.
+    InvalidPartition(String),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Cache(err) => write!(f, "Cache error: {}", err),
+            Error::Storage(err) => write!(f, "Storage error: {}", err),
+            Error::InvalidPartition(key) => write!(f, "Invalid partition at {}", key),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<CacheError> for Error {
+    fn from(err: CacheError) -> Self {
+        Error::Cache(err)
+    }
+}
+
+pub type LoaderFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, Error>> + Send + 'a>>;
+
+/// Where the partitions of a table are read from on a miss and written to.
+pub trait Loader: Send + Sync {
+    /// Reads a partition, `None` when the storage does not have it.
+    fn load<'a>(
+        &'a self,
+        table: &'a str,
+        partition_key: &'a str,
+    ) -> LoaderFuture<'a, Option<Partition>>;
+    /// Writes a partition, replacing the stored one.
+    fn store<'a>(
+        &'a self,
+        table: &'a str,
+        partition_key: &'a str,
+        partition: &'a Partition,
+    ) -> LoaderFuture<'a, ()>;
+    fn delete<'a>(&'a self, table: &'a str, partition_key: &'a str) -> LoaderFuture<'a, ()>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteMode {
+    /// Writes are sent to the loader before the cache is changed.
+    Through,
+    /// Writes are queued and flushed by up to `batch` concurrent writes at a time.
+    Behind { batch: usize },
+}
+
+/// A `Loader` storing every partition as one valu3 JSON object of a `Storage`.
+///
+/// Objects are named `table={}/partition_key={}.json`, with both names percent-encoded, see
+/// `StorageLoader::key`. Items keep the TTL they had left when the partition was written.
+pub struct StorageLoader<S> {
+    storage: S,
+}
+
+impl<S> StorageLoader<S> {
+    pub fn new(storage: S) -> Self {
+        Self { storage }
+    }
+
+    /// Every byte of the names other than an ASCII letter, digit, `-` or `_` is
+    /// percent-encoded, so that no name can reach the object of another partition through
+    /// `/`, `..` or `=`.
+    pub fn key(table: &str, partition_key: &str) -> String {
+        format!(
+            "table={}/partition_key={}.json",
+            escape(table),
+            escape(partition_key)
+        )
+    }
+}
+
+fn escape(name: &str) -> String {
+    let mut escaped = String::with_capacity(name.len());
+
+    for byte in name.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' => escaped.push(byte as char),
+            _ => escaped.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+
+    escaped
+}
+
+fn storage_error<E: Debug>(err: E) -> Error {
+    Error::Storage(format!("{:?}", err))
+}
+
+impl<S> Loader for StorageLoader<S>
+where
+    S: Storage + Send + Sync,
+    S::Error: Debug,
+{
+    fn load<'a>(
+        &'a self,
+        table: &'a str,
+        partition_key: &'a str,
+    ) -> LoaderFuture<'a, Option<Partition>> {
+        Box::pin(async move {
+            let key = Self::key(table, partition_key);
+            let keys = self
+                .storage
+                .list_objects(StorageListObjectsParams {
+                    max_keys: Some(1),
+                    prefix: Some(key.clone()),
+                    delimiter: None,
+                    start_after: None,
+                    order: None,
+                })
+                .await
+                .map_err(storage_error)?;
+
+            if !keys.contains(&key) {
+                return Ok(None);
+            }
+
+            let buffer = self.storage.get_object(&key).await.map_err(storage_error)?;
+            let partition = snapshot::decode::<PartitionSnapshot>(&buffer)
+                .ok()
+                .and_then(|snapshot| snapshot.into_partition().ok());
+
+            match partition {
+                Some((partition, _)) => Ok(Some(partition)),
+                None => Err(Error::InvalidPartition(key)),
+            }
+        })
+    }
+
+    fn store<'a>(
+        &'a self,
+        table: &'a str,
+        partition_key: &'a str,
+        partition: &'a Partition,
+    ) -> LoaderFuture<'a, ()> {
+        Box::pin(async move {
+            let key = Self::key(table, partition_key);
+            let json = snapshot::encode(&PartitionSnapshot::new(partition_key, partition, None));
+
+            self.storage
+                .put_object(json.into_bytes(), &key)
+                .await
+                .map_err(storage_error)
+        })
+    }
+
+    fn delete<'a>(&'a self, table: &'a str, partition_key: &'a str) -> LoaderFuture<'a, ()> {
+        Box::pin(async move {
+            let key = Self::key(table, partition_key);
+            self.storage
+                .delete_object(&key)
+                .await
+                .map_err(storage_error)
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum Write {
+    Store(Box<Partition>),
+    Delete,
+}
+
+/// A queued write, numbered so that a flush only drops the write it sent.
+struct Queued {
+    id: u64,
+    write: Arc<Write>,
+}
+
+/// The loader of a table and, when it writes behind, its queue of writes.
+pub(crate) struct TableLoader {
+    pub loader: Arc<dyn Loader>,
+    pub mode: WriteMode,
+    writes: Mutex<BTreeMap<String, Queued>>,
+    ids: AtomicU64,
+}
+
+impl Debug for TableLoader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TableLoader")
+            .field("mode", &self.mode)
+            .field("writes", &self.queued())
+            .finish()
+    }
+}
+
+impl TableLoader {
+    pub fn new(loader: Arc<dyn Loader>, mode: WriteMode) -> Self {
+        Self {
+            loader,
+            mode,
+            writes: Mutex::new(BTreeMap::new()),
+            ids: AtomicU64::new(0),
+        }
+    }
+
+    pub fn writes_behind(&self) -> bool {
+        matches!(self.mode, WriteMode::Behind { .. })
+    }
+
+    /// The write queued for the partition, which is newer than what the loader holds.
+    pub fn queued_write(&self, partition_key: &str) -> Option<Write> {
+        self.writes
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(partition_key)
+            .map(|queued| queued.write.as_ref().clone())
+    }
+
+    /// Number of partitions waiting to be written.
+    pub fn queued(&self) -> usize {
+        self.writes
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .len()
+    }
+
+    /// Queues a write, replacing the one already queued for the same partition.
+    pub fn queue(&self, partition_key: String, write: Write) {
+        let queued = Queued {
+            id: self.ids.fetch_add(1, Ordering::Relaxed),
+            write: Arc::new(write),
+        };
+
+        self.writes
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(partition_key, queued);
+    }
+
+    /// Up to `limit` queued writes, in key order from after the partition `after`. They stay
+    /// queued until `written` drops them.
+    fn pending(&self, after: Option<&str>, limit: usize) -> Vec<(String, u64, Arc<Write>)> {
+        let writes = self.writes.lock().unwrap_or_else(PoisonError::into_inner);
+        let start = match after {
+            Some(partition_key) => Bound::Excluded(partition_key),
+            None => Bound::Unbounded,
+        };
+
+        writes
+            .range::<str, _>((start, Bound::Unbounded))
+            .take(limit)
+            .map(|(partition_key, queued)| (partition_key.clone(), queued.id, queued.write.clone()))
+            .collect()
+    }
+
+    /// Drops a write the loader has written, unless a newer one was queued in the meantime.
+    fn written(&self, partition_key: &str, id: u64) {
+        let mut writes = self.writes.lock().unwrap_or_else(PoisonError::into_inner);
+        if writes
+            .get(partition_key)
+            .is_some_and(|queued| queued.id == id)
+        {
+            writes.remove(partition_key);
+        }
+    }
+
+    async fn write(&self, table: &str, partition_key: &str, write: &Write) -> Result<(), Error> {
+        match write {
+            Write::Store(partition) => self.loader.store(table, partition_key, partition).await,
+            Write::Delete => self.loader.delete(table, partition_key).await,
+        }
+    }
+
+    /// Sends the queued writes in batches and returns how many succeeded.
+    ///
+    /// Stops at the first batch with a failure, whose failed writes stay queued.
+    pub async fn flush(&self, table: &str) -> Result<usize, Error> {
+        let batch = match self.mode {
+            WriteMode::Behind { batch } => batch.max(1),
+            WriteMode::Through => return Ok(0),
+        };
+        let mut count = 0;
+        let mut after = None;
+
+        loop {
+            let writes = self.pending(after.as_deref(), batch);
+            after = match writes.last() {
+                Some((partition_key, _, _)) => Some(partition_key.clone()),
+                None => return Ok(count),
+            };
+
+            let results = join_all(
+                writes
+                    .iter()
+                    .map(|(partition_key, _, write)| self.write(table, partition_key, write)),
+            )
+            .await;
+
+            let mut failed = None;
+            for ((partition_key, id, _), result) in writes.iter().zip(results) {
+                match result {
+                    Ok(()) => {
+                        self.written(partition_key, *id);
+                        count += 1;
+                    }
+                    Err(err) => {
+                        failed.get_or_insert(err);
+                    }
+                }
+            }
+
+            if let Some(err) = failed {
+                return Err(err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use valu3::value::Value;
+
+    /// A `Storage` keeping its objects in memory, shared by its clones.
+    #[derive(Debug, Clone, Default)]
+    pub(crate) struct MemoryStorage {
+        pub objects: Arc<Mutex<BTreeMap<String, Vec<u8>>>>,
+    }
+
+    impl Storage for MemoryStorage {
+        type Error = String;
+
+        fn try_builder(
+            _param: &str,
+        ) -> Result<Pin<Box<dyn Future<Output = Result<Self, Self::Error>> + Send>>, Self::Error>
+        {
+            Ok(Box::pin(async { Ok(Self::default()) }))
+        }
+
+        fn list_objects(
+            &self,
+            params: StorageListObjectsParams,
+        ) -> Pin<Box<dyn Future<Output = Result<Vec<String>, Self::Error>> + Send + '_>> {
+            Box::pin(async move {
+                let prefix = params.prefix.unwrap_or_default();
+                Ok(self
+                    .objects
+                    .lock()
+                    .unwrap()
+                    .keys()
+                    .filter(|key| key.starts_with(&prefix))
+                    .cloned()
+                    .collect())
+            })
+        }
+
+        fn put_object<'a>(
+            &self,
+            buffer: Vec<u8>,
+            key: &'a str,
+        ) -> Pin<Box<dyn Future<Output = Result<(), Self::Error>> + Send + '_>> {
+            let key = key.to_string();
+            Box::pin(async move {
+                self.objects.lock().unwrap().insert(key, buffer);
+                Ok(())
+            })
+        }
+
+        fn get_object<'a>(
+            &self,
+            key: &'a str,
+        ) -> Pin<Box<dyn Future<Output = Result<Vec<u8>, Self::Error>> + Send + '_>> {
+            let key = key.to_string();
+            Box::pin(async move {
+                self.objects
+                    .lock()
+                    .unwrap()
+                    .get(&key)
+                    .cloned()
+                    .ok_or_else(|| format!("{} not found", key))
+            })
+        }
+
+        fn delete_object<'a>(
+            &self,
+            key: &'a str,
+        ) -> Pin<Box<dyn Future<Output = Result<(), Self::Error>> + Send + '_>> {
+            let key = key.to_string();
+            Box::pin(async move {
+                self.objects.lock().unwrap().remove(&key);
+                Ok(())
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_storage_loader() {
+        let storage = MemoryStorage::default();
+        let loader = StorageLoader::new(storage.clone());
+
+        let mut partition = Partition::new(10);
+        partition.insert("name", Value::from("John")).unwrap();
+
+        assert!(loader.load("users", "user1").await.unwrap().is_none());
+
+        loader.store("users", "user1", &partition).await.unwrap();
+        assert!(storage
+            .objects
+            .lock()
+            .unwrap()
+            .contains_key("table=users/partition_key=user1.json"));
+
+        let loaded = loader.load("users", "user1").await.unwrap().unwrap();
+        assert_eq!(loaded.capacity(), 10);
+        assert_eq!(loaded.peek("name"), Some(&Value::from("John")));
+
+        storage.objects.lock().unwrap().insert(
+            StorageLoader::<MemoryStorage>::key("users", "user2"),
+            b"not json".to_vec(),
+        );
+        assert!(matches!(
+            loader.load("users", "user2").await,
+            Err(Error::InvalidPartition(_))
+        ));
+
+        loader.delete("users", "user1").await.unwrap();
+        assert!(loader.load("users", "user1").await.unwrap().is_none());
+    }
+
+    /// A loader whose writes wait for a permit of `gate`.
+    struct GatedLoader {
+        loader: StorageLoader<MemoryStorage>,
+        gate: Arc<tokio::sync::Semaphore>,
+    }
+
+    impl Loader for GatedLoader {
+        fn load<'a>(
+            &'a self,
+            table: &'a str,
+            partition_key: &'a str,
+        ) -> LoaderFuture<'a, Option<Partition>> {
+            self.loader.load(table, partition_key)
+        }
+
+        fn store<'a>(
+            &'a self,
+            table: &'a str,
+            partition_key: &'a str,
+            partition: &'a Partition,
+        ) -> LoaderFuture<'a, ()> {
+            Box::pin(async move {
+                self.gate.acquire().await.unwrap().forget();
+                self.loader.store(table, partition_key, partition).await
+            })
+        }
+
+        fn delete<'a>(&'a self, table: &'a str, partition_key: &'a str) -> LoaderFuture<'a, ()> {
+            Box::pin(async move {
+                self.gate.acquire().await.unwrap().forget();
+                self.loader.delete(table, partition_key).await
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_flush_keeps_writes_queued_until_written() {
+        let gate = Arc::new(tokio::sync::Semaphore::new(0));
+        let loader = GatedLoader {
+            loader: StorageLoader::new(MemoryStorage::default()),
+            gate: gate.clone(),
+        };
+        let table = TableLoader::new(Arc::new(loader), WriteMode::Behind { batch: 2 });
+
+        let mut partition = Partition::new(10);
+        partition.insert("name", Value::from("John")).unwrap();
+        table.queue("user1".to_string(), Write::Store(Box::new(partition)));
+
+        let mut flush = Box::pin(table.flush("users"));
+        assert!(futures::poll!(flush.as_mut()).is_pending());
+        assert!(matches!(table.queued_write("user1"), Some(Write::Store(_))));
+
+        // a write queued while the older one is in flight is not dropped with it
+        table.queue("user1".to_string(), Write::Delete);
+        gate.add_permits(1);
+        assert_eq!(flush.await.unwrap(), 1);
+        assert!(matches!(table.queued_write("user1"), Some(Write::Delete)));
+        assert!(table.loader.load("users", "user1").await.unwrap().is_some());
+
+        gate.add_permits(1);
+        assert_eq!(table.flush("users").await.unwrap(), 1);
+        assert!(table.queued_write("user1").is_none());
+        assert!(table.loader.load("users", "user1").await.unwrap().is_none());
+    }
+
+    #[test]
+    fn test_storage_loader_key() {
+        type Loader = StorageLoader<MemoryStorage>;
+
+        assert_eq!(
+            Loader::key("users", "user-1_a"),
+            "table=users/partition_key=user-1_a.json"
+        );
+        assert_eq!(
+            Loader::key("users", "../orders/partition_key=1"),
+            "table=users/partition_key=%2E%2E%2Forders%2Fpartition_key%3D1.json"
+        );
+        assert_eq!(
+            Loader::key("a/b", "é"),
+            "table=a%2Fb/partition_key=%C3%A9.json"
+        );
+        assert_ne!(Loader::key("a", "b/c"), Loader::key("a/b", "c"));
+    }
+}
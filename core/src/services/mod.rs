@@ -1 +1,4 @@
-pub mod cache;
\ No newline at end of file
+pub mod batch;
+pub mod cache;
+pub mod loader;
+mod snapshot;
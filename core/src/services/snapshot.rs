@@ -0,0 +1,260 @@
+//! On-disk format of `CacheService::snapshot`.
+//!
+//! A snapshot file starts with `MAGIC` and the format `VERSION` as a little-endian `u32`,
+//! followed by frames made of a little-endian `u32` length and a valu3 JSON document. The
+//! first frame is the `ServiceSnapshot`, which counts the `TableSnapshot` frames after it.
+//!
+//! Files are written next to their destination and renamed over it once complete, so an
+//! interrupted snapshot leaves the previous file untouched.
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use cache::cache::Error;
+use cache::partition::Partition;
+use valu3::prelude::*;
+
+pub(crate) const MAGIC: &[u8; 8] = b"PDBSNAP\0";
+pub(crate) const VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, ToValue, FromValue)]
+pub(crate) struct ServiceSnapshot {
+    pub capacity: u64,
+    pub max_weight: Option<u64>,
+    pub tables: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, ToValue, FromValue)]
+pub(crate) struct TableSnapshot {
+    pub name: String,
+    pub capacity: u64,
+    pub ttl_ms: Option<u64>,
+    pub max_weight: Option<u64>,
+    pub partitions: Vec<PartitionSnapshot>,
+}
+
+/// `ttl_ms` is the time that was left before the partition expired.
+#[derive(Debug, Clone, PartialEq, ToValue, FromValue)]
+pub(crate) struct PartitionSnapshot {
+    pub key: String,
+    pub capacity: u64,
+    pub ttl_ms: Option<u64>,
+    pub items: Vec<ItemSnapshot>,
+}
+
+impl PartitionSnapshot {
+    /// Copies the items of `partition` along with the TTL they have left.
+    pub(crate) fn new(key: &str, partition: &Partition, ttl: Option<Duration>) -> Self {
+        Self {
+            key: key.to_string(),
+            capacity: partition.capacity() as u64,
+            ttl_ms: ttl.map(millis),
+            items: partition
+                .iter()
+                .map(|(sort_key, value)| ItemSnapshot {
+                    key: sort_key.clone(),
+                    value: value.clone(),
+                    ttl_ms: partition.ttl_of(sort_key).map(millis),
+                })
+                .collect(),
+        }
+    }
+
+    /// Rebuilds the partition with fresh counters, returning it with the TTL it had left.
+    ///
+    /// Fails with `Error::CapacityExceeded` when the snapshot has more items than its capacity.
+    pub(crate) fn into_partition(self) -> Result<(Partition, Option<Duration>), Error> {
+        let mut partition = Partition::new(self.capacity as usize);
+
+        for item in self.items {
+            match item.ttl_ms {
+                Some(ttl) => {
+                    partition.insert_with_ttl(item.key, item.value, Duration::from_millis(ttl))
+                }
+                None => partition.insert(item.key, item.value),
+            }?;
+        }
+
+        partition.reset_stats();
+        Ok((partition, self.ttl_ms.map(Duration::from_millis)))
+    }
+}
+
+/// `ttl_ms` is the time that was left before the item expired.
+#[derive(Debug, Clone, PartialEq, ToValue, FromValue)]
+pub(crate) struct ItemSnapshot {
+    pub key: String,
+    pub value: Value,
+    pub ttl_ms: Option<u64>,
+}
+
+pub(crate) fn millis(duration: Duration) -> u64 {
+    duration.as_millis() as u64
+}
+
+pub(crate) fn invalid(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+pub(crate) fn encode<T>(frame: &T) -> String
+where
+    T: ToValueBehavior,
+{
+    frame.to_value().to_json(JsonMode::Inline)
+}
+
+pub(crate) fn decode<T>(json: &[u8]) -> io::Result<T>
+where
+    T: FromValueBehavior<Item = T>,
+{
+    let json = std::str::from_utf8(json).map_err(|_| invalid("Snapshot frame is not UTF-8"))?;
+    let value = Value::json_to_value(json).map_err(|_| invalid("Snapshot frame is not JSON"))?;
+
+    T::from_value(value).ok_or_else(|| invalid("Unexpected snapshot frame"))
+}
+
+fn write_frame<W, T>(writer: &mut W, frame: &T) -> io::Result<()>
+where
+    W: Write,
+    T: ToValueBehavior,
+{
+    let json = encode(frame);
+    let len = u32::try_from(json.len()).map_err(|_| invalid("Snapshot frame too large"))?;
+
+    writer.write_all(&len.to_le_bytes())?;
+    writer.write_all(json.as_bytes())
+}
+
+fn read_frame<R, T>(reader: &mut R) -> io::Result<T>
+where
+    R: Read,
+    T: FromValueBehavior<Item = T>,
+{
+    let mut len = [0; 4];
+    reader.read_exact(&mut len)?;
+    let len = u32::from_le_bytes(len) as u64;
+
+    // The length is not trusted, the buffer only grows with the bytes actually read.
+    let mut json = Vec::new();
+    if reader.take(len).read_to_end(&mut json)? as u64 != len {
+        return Err(io::Error::new(
+            ErrorKind::UnexpectedEof,
+            "Snapshot frame is truncated",
+        ));
+    }
+
+    decode(&json)
+}
+
+pub(crate) fn write(
+    path: &Path,
+    service: &ServiceSnapshot,
+    tables: &[TableSnapshot],
+) -> io::Result<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+
+    let file = File::create(&temp)?;
+    let mut writer = BufWriter::new(file);
+
+    writer.write_all(MAGIC)?;
+    writer.write_all(&VERSION.to_le_bytes())?;
+    write_frame(&mut writer, service)?;
+
+    for table in tables {
+        write_frame(&mut writer, table)?;
+    }
+
+    let file = writer.into_inner().map_err(|err| err.into_error())?;
+    file.sync_all()?;
+    drop(file);
+
+    fs::rename(&temp, path)?;
+    sync_parent(path)
+}
+
+/// Makes the rename of a snapshot durable, which on Unix takes syncing its directory.
+#[cfg(unix)]
+fn sync_parent(path: &Path) -> io::Result<()> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    File::open(parent)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_parent(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+pub(crate) fn read(path: &Path) -> io::Result<(ServiceSnapshot, Vec<TableSnapshot>)> {
+    let mut reader = BufReader::new(File::open(path)?);
+
+    let mut magic = [0; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid("Not a snapshot file"));
+    }
+
+    let mut version = [0; 4];
+    reader.read_exact(&mut version)?;
+    if u32::from_le_bytes(version) != VERSION {
+        return Err(invalid("Unsupported snapshot version"));
+    }
+
+    let service: ServiceSnapshot = read_frame(&mut reader)?;
+    let tables = (0..service.tables)
+        .map(|_| read_frame(&mut reader))
+        .collect::<io::Result<Vec<TableSnapshot>>>()?;
+
+    if reader.read(&mut [0])? != 0 {
+        return Err(invalid("Unexpected data after the last snapshot frame"));
+    }
+
+    Ok((service, tables))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_read_truncated() {
+        let path = env::temp_dir().join(format!("snapshot-truncated-{}", std::process::id()));
+        let service = ServiceSnapshot {
+            capacity: 10,
+            max_weight: None,
+            tables: 1,
+        };
+        let table = TableSnapshot {
+            name: "users".to_string(),
+            capacity: 10,
+            ttl_ms: None,
+            max_weight: None,
+            partitions: Vec::new(),
+        };
+
+        write(&path, &service, std::slice::from_ref(&table)).unwrap();
+        assert_eq!(read(&path).unwrap(), (service, vec![table]));
+
+        let bytes = fs::read(&path).unwrap();
+        fs::write(&path, &bytes[..bytes.len() - 3]).unwrap();
+        assert!(read(&path).is_err());
+
+        let mut huge = b"PDBSNAP\0\x01\0\0\0".to_vec();
+        huge.extend_from_slice(&u32::MAX.to_le_bytes());
+        huge.extend_from_slice(b"{}");
+        fs::write(&path, &huge).unwrap();
+        assert_eq!(read(&path).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+
+        fs::write(&path, b"PDBSNAP\0\x02\0\0\0").unwrap();
+        assert_eq!(read(&path).unwrap_err().kind(), ErrorKind::InvalidData);
+
+        fs::remove_file(&path).unwrap();
+    }
+}